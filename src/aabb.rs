@@ -1,7 +1,9 @@
 use crate::ray::Ray;
 use crate::vector::Vec3;
 
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AxisAlignedBoundingBox {
     pub minimum: Vec3,
     pub maximum: Vec3,
@@ -35,6 +37,21 @@ impl AxisAlignedBoundingBox {
         o
     }
 
+    pub fn union(&self, other: &AxisAlignedBoundingBox) -> AxisAlignedBoundingBox {
+        AxisAlignedBoundingBox::new(
+            Vec3::new(
+                self.minimum.x.min(other.minimum.x),
+                self.minimum.y.min(other.minimum.y),
+                self.minimum.z.min(other.minimum.z),
+            ),
+            Vec3::new(
+                self.maximum.x.max(other.maximum.x),
+                self.maximum.y.max(other.maximum.y),
+                self.maximum.z.max(other.maximum.z),
+            ),
+        )
+    }
+
     pub fn surface_area(&self) -> f64 {
         let d = self.maximum - self.minimum;
         2.0 * (d.x * d.y + d.x * d.z + d.y * d.z)
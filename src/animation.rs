@@ -0,0 +1,129 @@
+use serde_json::Value;
+
+use crate::json::{AnimationJSON, InterpolationJSON, KeyframeJSON, SceneJSON, TrackJSON};
+
+// Keyframed changes to a scene file. Every track targets one value in the scene json using
+// a JSON pointer, ie "/camera/look_from" or "/models/2/Model/material/Light/intensity", and
+// the scene for a frame is the scene file with each track's value at that frame written in.
+pub struct Animation {
+    scene: Value,
+    tracks: Vec<TrackJSON>,
+}
+
+impl Animation {
+    pub fn new(scene: Value) -> Animation {
+        let mut tracks = match scene.get("animation") {
+            Some(animation) => match serde_json::from_value::<AnimationJSON>(animation.clone()) {
+                Err(why) => panic!("Error parsing animation: {}", why),
+                Ok(animation) => animation.tracks,
+            },
+            None => vec![],
+        };
+
+        for track in &mut tracks {
+            if track.keyframes.is_empty() {
+                panic!("Animation track {} has no keyframes", track.target);
+            }
+
+            let dimensions = track.keyframes[0].value.len();
+            for keyframe in &track.keyframes {
+                let mismatched = |values: &Vec<f64>| values.len() != dimensions;
+                if mismatched(&keyframe.value)
+                    || keyframe
+                        .handle_in
+                        .iter()
+                        .chain(&keyframe.handle_out)
+                        .any(mismatched)
+                {
+                    panic!(
+                        "Keyframe values for track {} must all have {} components",
+                        track.target, dimensions
+                    );
+                }
+            }
+
+            track
+                .keyframes
+                .sort_by(|k0, k1| k0.frame.total_cmp(&k1.frame));
+        }
+
+        Animation { scene, tracks }
+    }
+
+    pub fn scene_at(&self, frame: f64) -> SceneJSON {
+        let mut scene = self.scene.clone();
+
+        for track in &self.tracks {
+            let value = evaluate(&track.keyframes, frame);
+            let value = if value.len() == 1 {
+                Value::from(value[0])
+            } else {
+                Value::from(value)
+            };
+            set_target(&mut scene, &track.target, value);
+        }
+
+        match serde_json::from_value(scene) {
+            Err(why) => panic!("Error parsing json for frame {}: {}", frame, why),
+            Ok(json) => json,
+        }
+    }
+}
+
+fn evaluate(keyframes: &[KeyframeJSON], frame: f64) -> Vec<f64> {
+    let first = &keyframes[0];
+    let last = &keyframes[keyframes.len() - 1];
+    if frame <= first.frame {
+        return first.value.clone();
+    }
+    if frame >= last.frame {
+        return last.value.clone();
+    }
+
+    let next = keyframes.iter().position(|k| k.frame > frame).unwrap();
+    let k0 = &keyframes[next - 1];
+    let k1 = &keyframes[next];
+    let s = (frame - k0.frame) / (k1.frame - k0.frame);
+
+    match k0.interpolation.unwrap_or(InterpolationJSON::Linear) {
+        InterpolationJSON::Linear => k0
+            .value
+            .iter()
+            .zip(&k1.value)
+            .map(|(v0, v1)| (1.0 - s) * v0 + s * v1)
+            .collect(),
+        InterpolationJSON::Bezier => {
+            // without handles the curve eases out of and into the keyframe values
+            let p1 = k0.handle_out.as_ref().unwrap_or(&k0.value);
+            let p2 = k1.handle_in.as_ref().unwrap_or(&k1.value);
+
+            (0..k0.value.len())
+                .map(|i| {
+                    let r = 1.0 - s;
+                    r * r * r * k0.value[i]
+                        + 3.0 * r * r * s * p1[i]
+                        + 3.0 * r * s * s * p2[i]
+                        + s * s * s * k1.value[i]
+                })
+                .collect()
+        }
+    }
+}
+
+// Replaces the value at the pointer, adding it if only the last key is missing so that
+// optional fields can be animated
+fn set_target(scene: &mut Value, target: &str, value: Value) {
+    if let Some(existing) = scene.pointer_mut(target) {
+        *existing = value;
+        return;
+    }
+
+    if let Some((parent, key)) = target.rsplit_once('/') {
+        if let Some(Value::Object(parent)) = scene.pointer_mut(parent) {
+            parent.insert(key.replace("~1", "/").replace("~0", "~"), value);
+            return;
+        }
+    }
+
+    panic!("Animation target {} does not exist in the scene", target);
+}
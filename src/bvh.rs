@@ -0,0 +1,673 @@
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::hittable::{HitRecord, Hittable, Pixel};
+use crate::kdtree::KDTreeHitRecord;
+use crate::ray::{Ray, RayPacket};
+use crate::simd::{Mask4, LANES};
+use crate::triangle::{triangle_intersection, triangle_occludes, TriangleMesh};
+use crate::vector::Vec3;
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Objects are expensive to intersect compared to a box test, so leaves are kept small
+const MAX_OBJECTS_IN_LEAF: usize = 2;
+
+#[derive(Debug)]
+enum ObjectBVHNode {
+    Interior {
+        bounding_box: AxisAlignedBoundingBox,
+        axis: usize,
+        left: usize,
+        right: usize,
+    },
+    Leaf {
+        bounding_box: AxisAlignedBoundingBox,
+        first: usize,
+        count: usize,
+    },
+}
+
+impl ObjectBVHNode {
+    fn bounding_box(&self) -> &AxisAlignedBoundingBox {
+        match self {
+            ObjectBVHNode::Interior { bounding_box, .. } => bounding_box,
+            ObjectBVHNode::Leaf { bounding_box, .. } => bounding_box,
+        }
+    }
+}
+
+// Top level acceleration structure over the objects of a scene. Each object is found
+// through a BVH over the objects' bounding boxes and then intersected with its own
+// acceleration structure. Objects without bounds, such as volumes and planes, are kept
+// in a list that every ray is tested against.
+#[derive(Debug)]
+pub struct ObjectBVH {
+    nodes: Vec<ObjectBVHNode>,
+    objects: Vec<Box<dyn Hittable>>,
+    unbounded: Vec<Box<dyn Hittable>>,
+}
+
+impl ObjectBVH {
+    pub fn new(objects: Vec<Box<dyn Hittable>>) -> ObjectBVH {
+        let mut bounded = vec![];
+        let mut unbounded = vec![];
+        for object in objects {
+            match object.bounding_box() {
+                Some(bounding_box) => bounded.push((object, bounding_box)),
+                None => unbounded.push(object),
+            }
+        }
+
+        let mut nodes = vec![];
+        if !bounded.is_empty() {
+            build(&mut bounded[..], 0, &mut nodes);
+        }
+
+        ObjectBVH {
+            nodes,
+            objects: bounded.into_iter().map(|(object, _)| object).collect(),
+            unbounded,
+        }
+    }
+}
+
+// Builds the subtree for `objects`, which start at index `first` of the final object list,
+// and returns the index of its root node. Splits are chosen with the surface area
+// heuristic along the axis where the object centroids are most spread out.
+fn build(
+    objects: &mut [(Box<dyn Hittable>, AxisAlignedBoundingBox)],
+    first: usize,
+    nodes: &mut Vec<ObjectBVHNode>,
+) -> usize {
+    let bounding_box = objects[1..]
+        .iter()
+        .fold(objects[0].1.clone(), |bounds, (_, object_box)| {
+            bounds.union(object_box)
+        });
+
+    let index = nodes.len();
+    if objects.len() <= MAX_OBJECTS_IN_LEAF {
+        nodes.push(ObjectBVHNode::Leaf {
+            bounding_box,
+            first,
+            count: objects.len(),
+        });
+        return index;
+    }
+
+    let centroid_box = objects[1..].iter().fold(
+        AxisAlignedBoundingBox::new(objects[0].1.centroid, objects[0].1.centroid),
+        |bounds, (_, object_box)| {
+            bounds.union(&AxisAlignedBoundingBox::new(
+                object_box.centroid,
+                object_box.centroid,
+            ))
+        },
+    );
+    let axis = centroid_box.maximum_extent() as usize;
+    objects.sort_by(|(_, a), (_, b)| a.centroid.get(axis).total_cmp(&b.centroid.get(axis)));
+
+    // sweep from the right to find the area of every suffix, then from the left to find
+    // the cheapest split
+    let mut right_areas = vec![0.0; objects.len()];
+    let mut right_box = objects[objects.len() - 1].1.clone();
+    for i in (1..objects.len()).rev() {
+        right_box = right_box.union(&objects[i].1);
+        right_areas[i] = right_box.surface_area();
+    }
+
+    let mut best_split = objects.len() / 2;
+    let mut best_cost = f64::INFINITY;
+    let mut left_box = objects[0].1.clone();
+    for split in 1..objects.len() {
+        left_box = left_box.union(&objects[split - 1].1);
+        let cost = left_box.surface_area() * split as f64
+            + right_areas[split] * (objects.len() - split) as f64;
+        if cost < best_cost {
+            best_cost = cost;
+            best_split = split;
+        }
+    }
+
+    // reserve this node's slot before its children are pushed
+    nodes.push(ObjectBVHNode::Leaf {
+        bounding_box: bounding_box.clone(),
+        first,
+        count: 0,
+    });
+
+    let (left_objects, right_objects) = objects.split_at_mut(best_split);
+    let left = build(left_objects, first, nodes);
+    let right = build(right_objects, first + best_split, nodes);
+
+    nodes[index] = ObjectBVHNode::Interior {
+        bounding_box,
+        axis,
+        left,
+        right,
+    };
+
+    index
+}
+
+// The box test does not clip to the ray's interval itself. A NaN from an axis parallel ray
+// fails both comparisons, so the node is visited rather than wrongly skipped.
+fn box_in_range(bounding_box: &AxisAlignedBoundingBox, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+    match bounding_box.hit(ray, t_min, t_max) {
+        (false, _, _) => false,
+        (true, box_t_min, box_t_max) => !(box_t_max < t_min || box_t_min > t_max),
+    }
+}
+
+impl Hittable for ObjectBVH {
+    fn hit<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        pixel: Option<Pixel>,
+        first_ray: bool,
+    ) -> Option<HitRecord<'a>> {
+        let mut hit_anything: Option<HitRecord> = None;
+        let mut closest_so_far = t_max;
+
+        let hit_object = |object: &'a dyn Hittable, closest_so_far: &mut f64| {
+            if first_ray && !object.should_render() {
+                return None;
+            }
+
+            let hit_record = object.hit(ray, t_min, *closest_so_far, pixel, first_ray)?;
+            *closest_so_far = hit_record.t;
+            Some(hit_record)
+        };
+
+        for object in &self.unbounded {
+            if let Some(hit_record) = hit_object(object.as_ref(), &mut closest_so_far) {
+                hit_anything = Some(hit_record);
+            }
+        }
+
+        if self.nodes.is_empty() {
+            return hit_anything;
+        }
+
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !box_in_range(node.bounding_box(), ray, t_min, closest_so_far) {
+                continue;
+            }
+
+            match node {
+                ObjectBVHNode::Interior {
+                    axis, left, right, ..
+                } => {
+                    // visit the child facing the ray first so that the far child can
+                    // often be skipped once something closer has been found
+                    if ray.direction.get(*axis) < 0.0 {
+                        stack.push(*left);
+                        stack.push(*right);
+                    } else {
+                        stack.push(*right);
+                        stack.push(*left);
+                    }
+                }
+                ObjectBVHNode::Leaf { first, count, .. } => {
+                    for object in &self.objects[*first..*first + *count] {
+                        if let Some(hit_record) = hit_object(object.as_ref(), &mut closest_so_far) {
+                            hit_anything = Some(hit_record);
+                        }
+                    }
+                }
+            }
+        }
+
+        hit_anything
+    }
+
+    // The packet visits a node if any of its rays reach the node's box, and only the
+    // rays that do are passed on to the objects in a leaf
+    fn hit_packet<'a>(
+        &'a self,
+        packet: &RayPacket,
+        t_min: f64,
+        t_max: [f64; LANES],
+        pixels: [Option<Pixel>; LANES],
+        first_ray: bool,
+    ) -> [Option<HitRecord<'a>>; LANES] {
+        let mut hits: [Option<HitRecord>; LANES] = Default::default();
+        let mut closest_so_far = t_max;
+
+        let hit_object = |object: &'a dyn Hittable,
+                          active: Mask4,
+                          closest_so_far: &mut [f64; LANES],
+                          hits: &mut [Option<HitRecord<'a>>; LANES]| {
+            if first_ray && !object.should_render() {
+                return;
+            }
+
+            // rays that don't need this object search an empty interval
+            let object_t_max = std::array::from_fn(|lane| match active.lane(lane) {
+                true => closest_so_far[lane],
+                false => t_min,
+            });
+            let object_hits = object.hit_packet(packet, t_min, object_t_max, pixels, first_ray);
+
+            for (lane, hit_record) in object_hits.into_iter().enumerate() {
+                if let Some(hit_record) = hit_record {
+                    closest_so_far[lane] = hit_record.t;
+                    hits[lane] = Some(hit_record);
+                }
+            }
+        };
+
+        for object in &self.unbounded {
+            hit_object(
+                object.as_ref(),
+                packet.active(),
+                &mut closest_so_far,
+                &mut hits,
+            );
+        }
+
+        if self.nodes.is_empty() {
+            return hits;
+        }
+
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let active = Mask4::from_fn(|lane| {
+                lane < packet.len
+                    && box_in_range(
+                        node.bounding_box(),
+                        &packet.rays[lane],
+                        t_min,
+                        closest_so_far[lane],
+                    )
+            });
+            if !active.any() {
+                continue;
+            }
+
+            match node {
+                ObjectBVHNode::Interior {
+                    axis, left, right, ..
+                } => {
+                    if packet.rays[0].direction.get(*axis) < 0.0 {
+                        stack.push(*left);
+                        stack.push(*right);
+                    } else {
+                        stack.push(*right);
+                        stack.push(*left);
+                    }
+                }
+                ObjectBVHNode::Leaf { first, count, .. } => {
+                    for object in &self.objects[*first..*first + *count] {
+                        hit_object(object.as_ref(), active, &mut closest_so_far, &mut hits);
+                    }
+                }
+            }
+        }
+
+        hits
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        if self
+            .unbounded
+            .iter()
+            .any(|object| object.occluded(ray, t_max))
+        {
+            return true;
+        }
+
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !box_in_range(node.bounding_box(), ray, 0.0, t_max) {
+                continue;
+            }
+
+            match node {
+                ObjectBVHNode::Interior { left, right, .. } => {
+                    stack.push(*right);
+                    stack.push(*left);
+                }
+                ObjectBVHNode::Leaf { first, count, .. } => {
+                    if self.objects[*first..*first + *count]
+                        .iter()
+                        .any(|object| object.occluded(ray, t_max))
+                    {
+                        return true;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+
+        self.nodes.first().map(|root| root.bounding_box().clone())
+    }
+}
+
+// Number of buckets the centroids are binned into when looking for a split
+const SAH_BINS: usize = 12;
+const MAX_TRIANGLES_IN_LEAF: usize = 4;
+// Relative costs of stepping through a node and intersecting a triangle
+const TRAVERSAL_COST: f64 = 1.0;
+const INTERSECTION_COST: f64 = 2.0;
+
+// Nodes are stored depth first, so the first child of an interior node is the node after
+// it and only the second child's index needs storing. Each node is 64 bytes, one cache line.
+#[derive(Debug, Serialize, Deserialize)]
+struct MeshBVHNode {
+    // smallest and largest corner
+    bounds: [Vec3; 2],
+    // index of the second child for interior nodes, or of the first triangle for leaves
+    offset: u32,
+    triangle_count: u32,
+    axis: u32,
+}
+
+impl MeshBVHNode {
+    // Slab test against the ray's interval. A NaN from an axis parallel ray is ignored by
+    // max and min, so the node is visited rather than wrongly skipped.
+    fn in_range(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        let mut t_near = t_min;
+        let mut t_far = t_max;
+        for axis in 0..3 {
+            let origin = ray.origin.get(axis);
+            let inverse_direction = ray.inverse_direction.get(axis);
+            let near = (self.bounds[ray.sign[axis]].get(axis) - origin) * inverse_direction;
+            let far = (self.bounds[1 - ray.sign[axis]].get(axis) - origin) * inverse_direction;
+            t_near = t_near.max(near);
+            t_far = t_far.min(far);
+        }
+
+        t_near <= t_far
+    }
+}
+
+// Bounding volume hierarchy over the triangles of a mesh, built with a binned surface area
+// heuristic. Unlike the KD-tree every triangle is referenced by exactly one leaf. Other
+// primitives, such as the segments of curves, can be put in one too by their bounds.
+#[derive(Serialize, Deserialize)]
+pub struct MeshBVH {
+    nodes: Vec<MeshBVHNode>,
+    triangle_indices: Vec<u32>,
+}
+
+impl fmt::Debug for MeshBVH {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MeshBVH")
+    }
+}
+
+impl MeshBVH {
+    pub fn build(triangles: &TriangleMesh) -> MeshBVH {
+        let triangle_bounds: Vec<AxisAlignedBoundingBox> = (0..triangles.len())
+            .map(|triangle| {
+                let [minimum, maximum] = triangles.triangle_bounds(triangle);
+                AxisAlignedBoundingBox::new(minimum, maximum)
+            })
+            .collect();
+
+        MeshBVH::build_from_bounds(&triangle_bounds)
+    }
+
+    pub fn build_from_bounds(bounds: &[AxisAlignedBoundingBox]) -> MeshBVH {
+        let mut triangle_indices: Vec<u32> = (0..bounds.len() as u32).collect();
+
+        let mut nodes = vec![];
+        if !triangle_indices.is_empty() {
+            build_mesh_node(bounds, &mut triangle_indices[..], 0, &mut nodes);
+        }
+
+        MeshBVH {
+            nodes,
+            triangle_indices,
+        }
+    }
+
+    pub fn traverse(
+        &self,
+        triangles: &TriangleMesh,
+        ray: &Ray,
+        t_start: f64,
+        t_end: f64,
+    ) -> Option<KDTreeHitRecord> {
+        let mut potential_hit: Option<KDTreeHitRecord> = None;
+        self.closest_hit(ray, t_start, t_end, |triangle, closest_t_so_far| {
+            let hit = triangle_intersection(t_start, closest_t_so_far, ray, triangles, triangle)?;
+            let t = hit.t;
+            potential_hit = Some(hit);
+            Some(t)
+        });
+
+        potential_hit
+    }
+
+    // Visits the primitives whose leaves the ray passes through, nearest first. Each one is
+    // given to `intersect` along with the closest hit so far, and it returns the t of any
+    // closer hit it finds.
+    pub fn closest_hit(
+        &self,
+        ray: &Ray,
+        t_start: f64,
+        t_end: f64,
+        mut intersect: impl FnMut(usize, f64) -> Option<f64>,
+    ) {
+        let mut closest_t_so_far = t_end;
+
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.in_range(ray, t_start, closest_t_so_far) {
+                continue;
+            }
+
+            if node.triangle_count > 0 {
+                let first = node.offset as usize;
+                let last = first + node.triangle_count as usize;
+                for &triangle in &self.triangle_indices[first..last] {
+                    if let Some(t) = intersect(triangle as usize, closest_t_so_far) {
+                        closest_t_so_far = t;
+                    }
+                }
+            } else if ray.direction.get(node.axis as usize) < 0.0 {
+                stack.push(index + 1);
+                stack.push(node.offset as usize);
+            } else {
+                stack.push(node.offset as usize);
+                stack.push(index + 1);
+            }
+        }
+    }
+
+    pub fn occluded(&self, triangles: &TriangleMesh, ray: &Ray, t_start: f64, t_end: f64) -> bool {
+        self.any_hit(ray, t_start, t_end, |triangle| {
+            triangle_occludes(t_start, t_end, ray, triangles, triangle)
+        })
+    }
+
+    // Any hit in the interval will do, so children are visited in whatever order
+    pub fn any_hit(
+        &self,
+        ray: &Ray,
+        t_start: f64,
+        t_end: f64,
+        mut occludes: impl FnMut(usize) -> bool,
+    ) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.in_range(ray, t_start, t_end) {
+                continue;
+            }
+
+            if node.triangle_count > 0 {
+                let first = node.offset as usize;
+                let last = first + node.triangle_count as usize;
+                for &triangle in &self.triangle_indices[first..last] {
+                    if occludes(triangle as usize) {
+                        return true;
+                    }
+                }
+            } else {
+                stack.push(node.offset as usize);
+                stack.push(index + 1);
+            }
+        }
+
+        false
+    }
+}
+
+fn build_mesh_node(
+    triangle_bounds: &[AxisAlignedBoundingBox],
+    triangles: &mut [u32],
+    first: usize,
+    nodes: &mut Vec<MeshBVHNode>,
+) {
+    let bounds_of = |triangle: &u32| &triangle_bounds[*triangle as usize];
+    let bounds = triangles[1..]
+        .iter()
+        .fold(bounds_of(&triangles[0]).clone(), |bounds, triangle| {
+            bounds.union(bounds_of(triangle))
+        });
+    let first_centroid = bounds_of(&triangles[0]).centroid;
+    let centroid_bounds = triangles[1..].iter().fold(
+        AxisAlignedBoundingBox::new(first_centroid, first_centroid),
+        |bounds, triangle| {
+            let centroid = bounds_of(triangle).centroid;
+            bounds.union(&AxisAlignedBoundingBox::new(centroid, centroid))
+        },
+    );
+
+    let index = nodes.len();
+    let axis = centroid_bounds.maximum_extent() as usize;
+    let axis_min = centroid_bounds.minimum.get(axis);
+    let axis_extent = centroid_bounds.maximum.get(axis) - axis_min;
+
+    let node = |bounds: &AxisAlignedBoundingBox, offset: usize, triangle_count: usize| {
+        if offset > u32::MAX as usize {
+            panic!("Too many BVH nodes: {}", offset);
+        }
+
+        MeshBVHNode {
+            bounds: [bounds.minimum, bounds.maximum],
+            offset: offset as u32,
+            triangle_count: triangle_count as u32,
+            axis: axis as u32,
+        }
+    };
+
+    // all the centroids sit on top of each other so there is nothing to split
+    if triangles.len() == 1 || axis_extent <= 0.0 {
+        nodes.push(node(&bounds, first, triangles.len()));
+        return;
+    }
+
+    let bin_of = |triangle: &u32| {
+        let offset = (bounds_of(triangle).centroid.get(axis) - axis_min) / axis_extent;
+        ((offset * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+    };
+
+    let mut bin_counts = [0; SAH_BINS];
+    let mut bin_bounds: [Option<AxisAlignedBoundingBox>; SAH_BINS] = Default::default();
+    for triangle in triangles.iter() {
+        let bin = bin_of(triangle);
+        bin_counts[bin] += 1;
+        bin_bounds[bin] = Some(match &bin_bounds[bin] {
+            Some(bounds) => bounds.union(bounds_of(triangle)),
+            None => bounds_of(triangle).clone(),
+        });
+    }
+
+    // sweep from the right to find the area and count above every split, then from the
+    // left to find the cheapest one
+    let mut above = [(0.0, 0); SAH_BINS];
+    let mut above_bounds: Option<AxisAlignedBoundingBox> = None;
+    let mut above_count = 0;
+    for split in (1..SAH_BINS).rev() {
+        above_bounds = union_bins(above_bounds, &bin_bounds[split]);
+        above_count += bin_counts[split];
+        above[split] = (
+            above_bounds.as_ref().map_or(0.0, |b| b.surface_area()),
+            above_count,
+        );
+    }
+
+    let mut best_split = 0;
+    let mut best_cost = f64::INFINITY;
+    let mut below_bounds: Option<AxisAlignedBoundingBox> = None;
+    let mut below_count = 0;
+    for split in 1..SAH_BINS {
+        below_bounds = union_bins(below_bounds, &bin_bounds[split - 1]);
+        below_count += bin_counts[split - 1];
+        let below_area = below_bounds.as_ref().map_or(0.0, |b| b.surface_area());
+        let (above_area, above_count) = above[split];
+
+        let cost = TRAVERSAL_COST
+            + INTERSECTION_COST
+                * (below_area * below_count as f64 + above_area * above_count as f64)
+                / bounds.surface_area();
+        if cost < best_cost {
+            best_cost = cost;
+            best_split = split;
+        }
+    }
+
+    let leaf_cost = INTERSECTION_COST * triangles.len() as f64;
+    if triangles.len() <= MAX_TRIANGLES_IN_LEAF && leaf_cost <= best_cost {
+        nodes.push(node(&bounds, first, triangles.len()));
+        return;
+    }
+
+    // move the triangles below the split to the front
+    let mut middle = 0;
+    for i in 0..triangles.len() {
+        if bin_of(&triangles[i]) < best_split {
+            triangles.swap(i, middle);
+            middle += 1;
+        }
+    }
+
+    // reserve this node's slot before its children are pushed
+    nodes.push(node(&bounds, 0, 0));
+
+    let (below, above) = triangles.split_at_mut(middle);
+    build_mesh_node(triangle_bounds, below, first, nodes);
+    nodes[index] = node(&bounds, nodes.len(), 0);
+    build_mesh_node(triangle_bounds, above, first + middle, nodes);
+}
+
+fn union_bins(
+    bounds: Option<AxisAlignedBoundingBox>,
+    bin_bounds: &Option<AxisAlignedBoundingBox>,
+) -> Option<AxisAlignedBoundingBox> {
+    match (bounds, bin_bounds) {
+        (Some(bounds), Some(bin_bounds)) => Some(bounds.union(bin_bounds)),
+        (Some(bounds), None) => Some(bounds),
+        (None, bin_bounds) => bin_bounds.clone(),
+    }
+}
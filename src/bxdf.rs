@@ -8,6 +8,8 @@ use crate::vector::Vec3;
 pub trait BxDF: std::fmt::Debug + Send + Sync {
     fn f(&self, wo: &Vec3, wi: &Vec3, n: &Vec3, colour: &Colour) -> Colour;
     fn sample_wh(&self, wo: &Vec3) -> Vec3;
+    // The same BxDF with its metallic and roughness multiplied by a texture's at a hit
+    fn scaled(&self, metallic: f64, roughness: f64) -> Box<dyn BxDF + Send + Sync>;
 }
 
 #[derive(Debug)]
@@ -62,6 +64,14 @@ impl BxDF for MicrofacetReflection {
         }
     }
 
+    fn scaled(&self, metallic: f64, roughness: f64) -> Box<dyn BxDF + Send + Sync> {
+        Box::new(MicrofacetReflection {
+            metallic: self.metallic * metallic,
+            roughness: self.roughness * roughness,
+            ..*self
+        })
+    }
+
     fn sample_wh(&self, wo: &Vec3) -> Vec3 {
         let u: (f64, f64) = (rand::thread_rng().gen(), rand::thread_rng().gen());
         let mut log_sample = f64::ln(1.0 - u.0);
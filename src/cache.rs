@@ -0,0 +1,205 @@
+use crate::displacement::Displacement;
+use crate::object::{AcceleratorType, Mesh};
+use crate::triangle::VertexPrecision;
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+// Built meshes are written to `<dir>/<key>.mesh`, where the key hashes the OBJ's contents
+// together with everything the build depends on. Bump the version whenever the layout of
+// a mesh or the way it is built changes, so old cache files are rebuilt rather than read.
+const CACHE_MAGIC: &[u8; 8] = b"TRCMESH\0";
+const CACHE_VERSION: u32 = 5;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// 64 bit FNV-1a, continuing from `hash`
+pub fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+pub fn cache_key(
+    obj: &[u8],
+    shade_smooth: bool,
+    accelerator: AcceleratorType,
+    precision: VertexPrecision,
+    subdivision: u32,
+    displacement: Option<(&Displacement, &[u8])>,
+) -> u64 {
+    let hash = fnv1a(FNV_OFFSET_BASIS, &CACHE_VERSION.to_le_bytes());
+    let hash = fnv1a(
+        hash,
+        &[shade_smooth as u8, accelerator as u8, precision as u8],
+    );
+    let mut hash = fnv1a(hash, &subdivision.to_le_bytes());
+    // the height texture's contents count, not just its path
+    if let Some((displacement, height_image)) = displacement {
+        for value in [
+            displacement.scale,
+            displacement.midlevel,
+            displacement.edge_length,
+        ] {
+            hash = fnv1a(hash, &value.to_le_bytes());
+        }
+        hash = fnv1a(hash, height_image);
+    }
+    fnv1a(hash, obj)
+}
+
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    pub fn new(dir: &str) -> DiskCache {
+        if let Err(why) = fs::create_dir_all(dir) {
+            panic!("Could not create cache directory {}: {}", dir, why);
+        }
+
+        DiskCache {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.mesh", key))
+    }
+
+    // Returns None when there is no cache file for the key, or when the file was written by
+    // another version or is damaged, in which case the mesh is rebuilt and overwrites it
+    pub fn load(&self, key: u64) -> Option<Mesh> {
+        let path = self.path(key);
+        let mut file = BufReader::new(File::open(&path).ok()?);
+
+        let mut header = [0; 20];
+        if file.read_exact(&mut header).is_err()
+            || &header[0..8] != CACHE_MAGIC
+            || header[8..12] != CACHE_VERSION.to_le_bytes()
+            || header[12..20] != key.to_le_bytes()
+        {
+            eprintln!("Ignoring stale cache file {}", path.display());
+            return None;
+        }
+
+        match bincode::deserialize_from(file) {
+            Err(why) => {
+                eprintln!("Ignoring damaged cache file {}: {}", path.display(), why);
+                None
+            }
+            Ok(mesh) => Some(mesh),
+        }
+    }
+
+    // Failing to write the cache only costs a rebuild next time, so it isn't fatal
+    pub fn store(&self, key: u64, mesh: &Mesh) {
+        let path = self.path(key);
+        // written next to the real file and moved into place, so that a render which is
+        // killed part way through never leaves a truncated cache file behind
+        let partial_path = path.with_extension("partial");
+
+        let result = File::create(&partial_path)
+            .map_err(|why| why.to_string())
+            .and_then(|file| {
+                let mut file = BufWriter::new(file);
+                file.write_all(CACHE_MAGIC)
+                    .and_then(|_| file.write_all(&CACHE_VERSION.to_le_bytes()))
+                    .and_then(|_| file.write_all(&key.to_le_bytes()))
+                    .map_err(|why| why.to_string())?;
+                bincode::serialize_into(&mut file, mesh).map_err(|why| why.to_string())?;
+                file.flush().map_err(|why| why.to_string())
+            })
+            .and_then(|_| fs::rename(&partial_path, &path).map_err(|why| why.to_string()));
+
+        if let Err(why) = result {
+            eprintln!("Could not write cache file {}: {}", path.display(), why);
+            let _ = fs::remove_file(&partial_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::MeshMaterials;
+    use crate::triangulate::triangulate;
+    use obj::raw::parse_obj;
+
+    const QUAD: &[u8] = b"v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n";
+
+    fn quad_mesh() -> Mesh {
+        let (object, _) = triangulate(parse_obj(QUAD).unwrap()).unwrap();
+        let materials = MeshMaterials {
+            names: vec![],
+            libraries: vec![],
+            triangles: vec![],
+        };
+        Mesh::new(
+            object,
+            materials,
+            false,
+            AcceleratorType::KDTree,
+            VertexPrecision::Double,
+        )
+    }
+
+    fn key(shade_smooth: bool, subdivision: u32) -> u64 {
+        cache_key(
+            QUAD,
+            shade_smooth,
+            AcceleratorType::KDTree,
+            VertexPrecision::Double,
+            subdivision,
+            None,
+        )
+    }
+
+    #[test]
+    fn keys_depend_on_every_build_setting() {
+        assert_eq!(key(false, 0), key(false, 0));
+        assert_ne!(key(false, 0), key(true, 0));
+        assert_ne!(key(false, 0), key(false, 1));
+
+        let displacement = Displacement {
+            height_path: String::from("height.png"),
+            scale: 0.1,
+            midlevel: 0.5,
+            edge_length: 0.01,
+        };
+        let displaced = |height_image: &[u8]| {
+            cache_key(
+                QUAD,
+                false,
+                AcceleratorType::KDTree,
+                VertexPrecision::Double,
+                0,
+                Some((&displacement, height_image)),
+            )
+        };
+        assert_ne!(displaced(b"one"), key(false, 0));
+        assert_ne!(displaced(b"one"), displaced(b"two"));
+    }
+
+    #[test]
+    fn meshes_load_back_under_their_own_key_only() {
+        let dir = std::env::temp_dir().join(format!("tracer-cache-test-{}", std::process::id()));
+        let disk_cache = DiskCache::new(dir.to_str().unwrap());
+        let mesh = quad_mesh();
+        let (key, other_key) = (key(false, 0), key(true, 0));
+
+        assert!(disk_cache.load(key).is_none());
+        disk_cache.store(key, &mesh);
+        let loaded = disk_cache.load(key).unwrap();
+        assert_eq!(loaded.triangle_count, mesh.triangle_count);
+        assert_eq!(loaded.bounding_box.maximum.x, mesh.bounding_box.maximum.x);
+
+        // a file that ends up under another key is rebuilt rather than trusted
+        fs::copy(disk_cache.path(key), disk_cache.path(other_key)).unwrap();
+        assert!(disk_cache.load(other_key).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,32 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
 use crate::ray::Ray;
 use crate::utils::random_in_unit_disk;
 use crate::vector::Vec3;
 
+#[derive(Debug, Copy, Clone)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum StereoLayout {
+    SideBySide,
+    OverUnder,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Projection {
+    Perspective,
+    // Omni-directional stereo: an equirectangular panorama where every ray starts on a
+    // circle of radius `eye_offset` around the camera origin, tangent to the view direction
+    OmniDirectional { eye_offset: f64, convergence: f64 },
+}
+
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub origin: Vec3,
     pub lower_left_corner: Vec3,
@@ -10,6 +35,11 @@ pub struct Camera {
     pub lens_radius: f64,
     u: Vec3,
     v: Vec3,
+    w: Vec3,
+    focus_dist: f64,
+    projection: Projection,
+    time0: f64,
+    time1: f64,
 }
 
 impl Camera {
@@ -46,10 +76,59 @@ impl Camera {
             lens_radius,
             u,
             v,
+            w,
+            focus_dist,
+            projection: Projection::Perspective,
+            time0: 0.0,
+            time1: 0.0,
         }
     }
 
+    // Camera rays are spread randomly over the time the shutter is open
+    pub fn with_shutter(mut self, open: f64, close: f64) -> Camera {
+        self.time0 = open;
+        self.time1 = close;
+        self
+    }
+
+    // Off-axis stereo: the eye is shifted sideways along the camera's u axis and its image
+    // plane is sheared so that both eyes share the same screen at the convergence distance.
+    // This avoids the vertical parallax a toed-in rig would introduce.
+    pub fn eye(&self, eye: Eye, interocular_distance: f64, convergence: f64) -> Camera {
+        let shift = eye_shift(eye, interocular_distance);
+
+        let mut camera = self.clone();
+        camera.origin = self.origin + shift * &self.u;
+        camera.lower_left_corner =
+            self.lower_left_corner + shift * (1.0 - self.focus_dist / convergence) * &self.u;
+
+        camera
+    }
+
+    pub fn omni_directional_eye(
+        &self,
+        eye: Eye,
+        interocular_distance: f64,
+        convergence: f64,
+    ) -> Camera {
+        let mut camera = self.clone();
+        camera.projection = Projection::OmniDirectional {
+            eye_offset: eye_shift(eye, interocular_distance),
+            convergence,
+        };
+
+        camera
+    }
+
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
+        if let Projection::OmniDirectional {
+            eye_offset,
+            convergence,
+        } = self.projection
+        {
+            return self.get_omni_directional_ray(s, t, eye_offset, convergence);
+        }
+
         let rd = self.lens_radius * &random_in_unit_disk();
         let offset = rd.x * &self.u + rd.y * &self.v;
 
@@ -57,6 +136,96 @@ impl Camera {
         let direction = self.lower_left_corner + s * &self.horizontal + t * &self.vertical
             - self.origin
             - offset;
-        Ray::new(origin_c + offset, direction)
+        Ray::new(origin_c + offset, direction, self.random_time())
+    }
+
+    fn get_omni_directional_ray(&self, s: f64, t: f64, eye_offset: f64, convergence: f64) -> Ray {
+        // s sweeps the full circle of longitude, t goes from the bottom pole to the top pole
+        let theta = (s - 0.5) * 2.0 * PI;
+        let phi = (t - 0.5) * PI;
+
+        let view =
+            phi.cos() * &(theta.sin() * &self.u - theta.cos() * &self.w) + phi.sin() * &self.v;
+        let offset = eye_offset * &(theta.cos() * &self.u + theta.sin() * &self.w);
+
+        let origin = self.origin + offset;
+        let target = self.origin + convergence * &view;
+        Ray::new(origin, target - origin, self.random_time())
+    }
+
+    fn random_time(&self) -> f64 {
+        if self.time1 <= self.time0 {
+            return self.time0;
+        }
+
+        rand::thread_rng().gen_range(self.time0..self.time1)
+    }
+}
+
+fn eye_shift(eye: Eye, interocular_distance: f64) -> f64 {
+    match eye {
+        Eye::Left => -interocular_distance / 2.0,
+        Eye::Right => interocular_distance / 2.0,
+    }
+}
+
+#[derive(Debug)]
+pub struct StereoCamera {
+    pub left: Camera,
+    pub right: Camera,
+    pub layout: StereoLayout,
+}
+
+impl StereoCamera {
+    pub fn new(
+        camera: &Camera,
+        interocular_distance: f64,
+        convergence: f64,
+        layout: StereoLayout,
+        omni_directional: bool,
+    ) -> StereoCamera {
+        let (left, right) = if omni_directional {
+            (
+                camera.omni_directional_eye(Eye::Left, interocular_distance, convergence),
+                camera.omni_directional_eye(Eye::Right, interocular_distance, convergence),
+            )
+        } else {
+            (
+                camera.eye(Eye::Left, interocular_distance, convergence),
+                camera.eye(Eye::Right, interocular_distance, convergence),
+            )
+        };
+
+        StereoCamera {
+            left,
+            right,
+            layout,
+        }
+    }
+
+    // Size of the packed output image given the size of a single eye
+    pub fn image_dimensions(&self, eye_width: u32, eye_height: u32) -> (u32, u32) {
+        match self.layout {
+            StereoLayout::SideBySide => (2 * eye_width, eye_height),
+            StereoLayout::OverUnder => (eye_width, 2 * eye_height),
+        }
+    }
+
+    // Maps a pixel of the packed output image to the camera of the eye it belongs to and
+    // the pixel coordinates within that eye. The left eye is on the left or on top.
+    // Note that j counts up from the bottom of the image.
+    pub fn eye_pixel(
+        &self,
+        i: u32,
+        j: u32,
+        eye_width: u32,
+        eye_height: u32,
+    ) -> (&Camera, u32, u32) {
+        match self.layout {
+            StereoLayout::SideBySide if i < eye_width => (&self.left, i, j),
+            StereoLayout::SideBySide => (&self.right, i - eye_width, j),
+            StereoLayout::OverUnder if j >= eye_height => (&self.left, i, j - eye_height),
+            StereoLayout::OverUnder => (&self.right, i, j),
+        }
     }
 }
@@ -0,0 +1,161 @@
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::hittable::{depth_test, HitRecord, Hittable, Pixel};
+use crate::ray::Ray;
+use crate::sphere::{bounding_sphere, Sphere};
+use crate::vector::Vec3;
+
+#[derive(Debug, Copy, Clone)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl CsgOperation {
+    // Whether a point inside or outside each of the two objects is inside the result
+    fn inside(&self, inside_left: bool, inside_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => inside_left || inside_right,
+            CsgOperation::Intersection => inside_left && inside_right,
+            CsgOperation::Difference => inside_left && !inside_right,
+        }
+    }
+}
+
+// Combines two closed objects into one. The ray is followed through the surfaces of both,
+// keeping track of which of them it is inside, and the first surface where that changes
+// whether it is inside the result is the hit.
+#[derive(Debug)]
+pub struct Csg {
+    operation: CsgOperation,
+    left: Box<dyn Hittable>,
+    right: Box<dyn Hittable>,
+    bounding_box: Option<AxisAlignedBoundingBox>,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Csg {
+        let bounding_box = match operation {
+            CsgOperation::Union => match (left.bounding_box(), right.bounding_box()) {
+                (Some(a), Some(b)) => Some(a.union(&b)),
+                _ => None,
+            },
+            CsgOperation::Intersection => match (left.bounding_box(), right.bounding_box()) {
+                (Some(a), Some(b)) => Some(AxisAlignedBoundingBox::new(
+                    Vec3::new(
+                        a.minimum.x.max(b.minimum.x),
+                        a.minimum.y.max(b.minimum.y),
+                        a.minimum.z.max(b.minimum.z),
+                    ),
+                    Vec3::new(
+                        a.maximum.x.min(b.maximum.x),
+                        a.maximum.y.min(b.maximum.y),
+                        a.maximum.z.min(b.maximum.z),
+                    ),
+                )),
+                (a, b) => a.or(b),
+            },
+            CsgOperation::Difference => left.bounding_box(),
+        };
+
+        Csg {
+            operation,
+            left,
+            right,
+            bounding_box,
+        }
+    }
+
+    fn boundary(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut left_hit = next_hit(self.left.as_ref(), ray, t_min);
+        let mut right_hit = next_hit(self.right.as_ref(), ray, t_min);
+
+        // leaving through the first surface means the ray started inside
+        let mut inside_left = left_hit.as_ref().is_some_and(|hit| !hit.front_face);
+        let mut inside_right = right_hit.as_ref().is_some_and(|hit| !hit.front_face);
+
+        loop {
+            let from_left = match (&left_hit, &right_hit) {
+                (None, None) => return None,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(left), Some(right)) => left.t <= right.t,
+            };
+
+            let was_inside = self.operation.inside(inside_left, inside_right);
+            let mut hit = if from_left {
+                inside_left = !inside_left;
+                left_hit.take()?
+            } else {
+                inside_right = !inside_right;
+                right_hit.take()?
+            };
+
+            if hit.t > t_max {
+                return None;
+            }
+
+            if self.operation.inside(inside_left, inside_right) != was_inside {
+                // what is cut away by the right object leaves its inside showing, so that
+                // surface faces the other way
+                if !from_left && matches!(self.operation, CsgOperation::Difference) {
+                    hit.front_face = !hit.front_face;
+                    hit.geometric_normal = -hit.geometric_normal;
+                }
+
+                return Some(hit);
+            }
+
+            if from_left {
+                left_hit = next_hit(self.left.as_ref(), ray, hit.t);
+            } else {
+                right_hit = next_hit(self.right.as_ref(), ray, hit.t);
+            }
+        }
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        pixel: Option<Pixel>,
+        _first_ray: bool,
+    ) -> Option<HitRecord<'_>> {
+        let hit = self.boundary(ray, t_min, t_max)?;
+
+        if !depth_test(&hit.p, &ray.origin, pixel) {
+            return None;
+        }
+
+        Some(hit)
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.boundary(ray, 0.0, t_max).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        self.bounding_box.clone()
+    }
+
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        match &self.bounding_box {
+            Some(bounding_box) => bounding_sphere(bounding_box),
+            None => panic!("An unbounded CSG object can't be a light"),
+        }
+    }
+
+    // camera rays see the combination wherever either object would be seen
+    fn should_render(&self) -> bool {
+        self.left.should_render() || self.right.should_render()
+    }
+}
+
+// The next surface of one of the objects after t, looking past t_max too since the
+// surface the ray leaves an object through says it was inside it
+fn next_hit<'a>(object: &'a dyn Hittable, ray: &Ray, t: f64) -> Option<HitRecord<'a>> {
+    object.hit(ray, t, f64::INFINITY, None, false)
+}
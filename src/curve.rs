@@ -0,0 +1,407 @@
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::bvh::MeshBVH;
+use crate::hittable::{HitRecord, Hittable, Pixel};
+use crate::material::Material;
+use crate::onb::OrthonormalBasis;
+use crate::ray::Ray;
+use crate::sphere::{bounding_sphere, Sphere};
+use crate::vector::Vec3;
+
+// Segments are never halved more often than this looking for a hit
+const MAX_SPLITS: u32 = 10;
+
+#[derive(Debug, Copy, Clone)]
+pub enum CurveType {
+    // a strip turned to face the ray, shaded as if it were round like a hair or cable
+    Cylinder,
+    // a strip turned to face the ray and shaded flat
+    Ribbon,
+}
+
+// One cubic Bézier piece of a curve, with a width at each control point
+#[derive(Debug)]
+struct Segment {
+    points: [Vec3; 4],
+    widths: [f64; 4],
+    // where the segment starts and ends along its whole curve
+    u: (f64, f64),
+    // how many times it is halved before the pieces are straight enough to be lines
+    splits: u32,
+}
+
+impl Segment {
+    fn max_width(&self) -> f64 {
+        self.widths.iter().fold(0.0, |max, &width| width.max(max))
+    }
+
+    fn width(&self, u: f64) -> f64 {
+        let [a, b, c, d] = self.widths;
+        let s = 1.0 - u;
+        s * s * s * a + 3.0 * s * s * u * b + 3.0 * s * u * u * c + u * u * u * d
+    }
+
+    fn bounding_box(&self) -> AxisAlignedBoundingBox {
+        let half_width = 0.5 * self.max_width();
+        let grow = Vec3::new(half_width, half_width, half_width);
+        self.points[1..].iter().fold(
+            AxisAlignedBoundingBox::new(self.points[0] - grow, self.points[0] + grow),
+            |bounds, point| bounds.union(&AxisAlignedBoundingBox::new(point - grow, point + grow)),
+        )
+    }
+}
+
+// A ray hit on a segment, with u along the segment and v across it
+struct CurveHit {
+    t: f64,
+    u: f64,
+    v: f64,
+}
+
+// Coordinates with the ray starting at the origin and running down the z axis, where a curve
+// is hit when it passes within half its width of the origin in x and y
+struct RayFrame {
+    origin: Vec3,
+    basis: OrthonormalBasis,
+    length: f64,
+}
+
+impl RayFrame {
+    fn new(ray: &Ray) -> RayFrame {
+        RayFrame {
+            origin: ray.origin,
+            basis: OrthonormalBasis::build_from_w(&ray.direction),
+            length: ray.direction.length(),
+        }
+    }
+
+    // v, u and w rather than u, v and w, as that makes a right handed frame, so which side
+    // of the curve the ray passes is the same here as outside
+    fn local(&self, p: &Vec3) -> Vec3 {
+        let p = p - self.origin;
+        Vec3::new(
+            p.dot(&self.basis.v()),
+            p.dot(&self.basis.u()),
+            p.dot(&self.basis.w()),
+        )
+    }
+}
+
+// Strands such as hair, fur, grass and cables, each a chain of cubic Bézier segments. The
+// segments are intersected as strips that always face the ray, by halving them until the
+// pieces are close enough to straight lines, as in pbrt.
+#[derive(Debug)]
+pub struct Curves {
+    segments: Vec<Segment>,
+    bvh: MeshBVH,
+    curve_type: CurveType,
+    bounding_box: Option<AxisAlignedBoundingBox>,
+    material: Box<dyn Material>,
+}
+
+impl Curves {
+    // Each curve is its control points with their widths, 3n + 1 of them for n segments
+    pub fn new(
+        curves: Vec<Vec<(Vec3, f64)>>,
+        curve_type: CurveType,
+        material: Box<dyn Material>,
+    ) -> Curves {
+        let mut segments = vec![];
+        for curve in curves {
+            let segment_count = (curve.len() - 1) / 3;
+            for (i, points) in curve.windows(4).step_by(3).enumerate() {
+                let mut segment = Segment {
+                    points: [points[0].0, points[1].0, points[2].0, points[3].0],
+                    widths: [points[0].1, points[1].1, points[2].1, points[3].1],
+                    u: (
+                        i as f64 / segment_count as f64,
+                        (i + 1) as f64 / segment_count as f64,
+                    ),
+                    splits: 0,
+                };
+                segment.splits = splits(&segment);
+                segments.push(segment);
+            }
+        }
+
+        let bounds: Vec<AxisAlignedBoundingBox> =
+            segments.iter().map(Segment::bounding_box).collect();
+        let bounding_box = bounds
+            .iter()
+            .cloned()
+            .reduce(|bounds, segment_bounds| bounds.union(&segment_bounds));
+
+        Curves {
+            bvh: MeshBVH::build_from_bounds(&bounds),
+            segments,
+            curve_type,
+            bounding_box,
+            material,
+        }
+    }
+}
+
+impl Hittable for Curves {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        _pixel: Option<Pixel>,
+        _first_ray: bool,
+    ) -> Option<HitRecord<'_>> {
+        let frame = RayFrame::new(ray);
+        let mut closest = None;
+        self.bvh.closest_hit(ray, t_min, t_max, |segment, t_max| {
+            let hit = intersect(&self.segments[segment], &frame, t_min, t_max)?;
+            let t = hit.t;
+            closest = Some((segment, hit));
+            Some(t)
+        });
+        let (segment, hit) = closest?;
+        let segment = &self.segments[segment];
+
+        // The strip's frame has x along the curve and y across it, so that z = x × y points
+        // down the ray. The hair material works in the same frame.
+        let tangent = bezier(&segment.points, hit.u).1.unit();
+        let across = ray.direction.cross(&tangent).unit();
+        let facing = -tangent.cross(&across);
+
+        let normal = match self.curve_type {
+            CurveType::Ribbon => facing,
+            CurveType::Cylinder => {
+                let h = (2.0 * hit.v - 1.0).clamp(-1.0, 1.0);
+                h * &across + (1.0 - h * h).sqrt() * &facing
+            }
+        };
+
+        let width = segment.width(hit.u);
+        Some(HitRecord {
+            p: ray.at(hit.t),
+            t: hit.t,
+            normal,
+            tangent: Some(tangent),
+            bitangent: Some(across),
+            material: &self.material,
+            front_face: true,
+            u: segment.u.0 + hit.u * (segment.u.1 - segment.u.0),
+            v: hit.v,
+            geometric_normal: facing,
+            p_error: Vec3::new(2.0 * width, 2.0 * width, 2.0 * width),
+            colour: None,
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        let frame = RayFrame::new(ray);
+        self.bvh.any_hit(ray, 0.0, t_max, |segment| {
+            intersect(&self.segments[segment], &frame, 0.0, t_max).is_some()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        self.bounding_box.clone()
+    }
+
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        match &self.bounding_box {
+            Some(bounding_box) => bounding_sphere(bounding_box),
+            None => panic!("Curves without any segments can't be a light"),
+        }
+    }
+}
+
+// How often a segment has to be halved so each piece strays from a straight line by no
+// more than a twentieth of its width
+fn splits(segment: &Segment) -> u32 {
+    let p = &segment.points;
+    let mut l0: f64 = 0.0;
+    for i in 0..2 {
+        let bend = (p[i] - 2.0 * &p[i + 1] + p[i + 2]).abs();
+        l0 = l0.max(bend.x).max(bend.y).max(bend.z);
+    }
+
+    let epsilon = 0.05 * segment.max_width();
+    let splits = (std::f64::consts::SQRT_2 * 6.0 * l0 / (8.0 * epsilon)).log2() / 2.0;
+    if splits.is_nan() {
+        return 0;
+    }
+
+    splits.clamp(0.0, MAX_SPLITS as f64) as u32
+}
+
+fn intersect(segment: &Segment, frame: &RayFrame, t_min: f64, t_max: f64) -> Option<CurveHit> {
+    let points = segment.points.map(|point| frame.local(&point));
+    let half_width = 0.5 * segment.max_width();
+    let hit = recursive_intersect(
+        segment,
+        &points,
+        (0.0, 1.0),
+        segment.splits,
+        half_width,
+        (t_min * frame.length, t_max * frame.length),
+    )?;
+
+    Some(CurveHit {
+        t: hit.t / frame.length,
+        ..hit
+    })
+}
+
+// Works in the ray's frame, where t is the distance along the ray and u0 to u1 is the part
+// of the segment these control points cover
+fn recursive_intersect(
+    segment: &Segment,
+    points: &[Vec3; 4],
+    (u0, u1): (f64, f64),
+    splits: u32,
+    half_width: f64,
+    (z_min, z_max): (f64, f64),
+) -> Option<CurveHit> {
+    // the control points bound the piece, so miss it if they can't reach the ray
+    let (mut lo, mut hi) = (points[0], points[0]);
+    for point in &points[1..] {
+        for axis in 0..3 {
+            lo.set(axis, lo.get(axis).min(point.get(axis)));
+            hi.set(axis, hi.get(axis).max(point.get(axis)));
+        }
+    }
+    if hi.x + half_width < 0.0
+        || lo.x - half_width > 0.0
+        || hi.y + half_width < 0.0
+        || lo.y - half_width > 0.0
+        || hi.z + half_width < z_min
+        || lo.z - half_width > z_max
+    {
+        return None;
+    }
+
+    if splits > 0 {
+        let (first, second) = split(points);
+        let middle = 0.5 * (u0 + u1);
+        let range = (z_min, z_max);
+        let first_hit =
+            recursive_intersect(segment, &first, (u0, middle), splits - 1, half_width, range);
+        let z_max = first_hit.as_ref().map_or(z_max, |hit| hit.t);
+        let second_hit = recursive_intersect(
+            segment,
+            &second,
+            (middle, u1),
+            splits - 1,
+            half_width,
+            (z_min, z_max),
+        );
+
+        return second_hit.or(first_hit);
+    }
+
+    // the ray has to pass between the lines square to the piece at its two ends
+    let edge =
+        (points[1].y - points[0].y) * -points[0].y + points[0].x * (points[0].x - points[1].x);
+    if edge < 0.0 {
+        return None;
+    }
+    let edge =
+        (points[2].y - points[3].y) * -points[3].y + points[3].x * (points[3].x - points[2].x);
+    if edge < 0.0 {
+        return None;
+    }
+
+    // the point on the line from the first to the last control point closest to the ray
+    let (dx, dy) = (points[3].x - points[0].x, points[3].y - points[0].y);
+    let length_squared = dx * dx + dy * dy;
+    if length_squared == 0.0 {
+        return None;
+    }
+    let w = (-points[0].x * dx - points[0].y * dy) / length_squared;
+    let u = (u0 + w * (u1 - u0)).clamp(u0, u1);
+    let width = segment.width(u);
+
+    // The point found is only roughly the closest one on the piece, so the distance is
+    // measured square to the curve there, which is exact wherever the curve runs straight.
+    // It is positive on the side that dpdw turns towards.
+    let (p, dpdw) = bezier(points, w.clamp(0.0, 1.0));
+    let tangent_length = (dpdw.x * dpdw.x + dpdw.y * dpdw.y).sqrt();
+    let distance = if tangent_length > 0.0 {
+        (dpdw.x * -p.y + p.x * dpdw.y) / tangent_length
+    } else {
+        (p.x * p.x + p.y * p.y).sqrt()
+    };
+    if distance.abs() > 0.5 * width || p.z <= z_min || p.z > z_max {
+        return None;
+    }
+
+    // v runs across the strip from 0 to 1
+    let v = 0.5 + distance / width;
+
+    Some(CurveHit { t: p.z, u, v })
+}
+
+// The point at u along a cubic Bézier, and the derivative there
+fn bezier(points: &[Vec3; 4], u: f64) -> (Vec3, Vec3) {
+    let lerp = |a: &Vec3, b: &Vec3| (1.0 - u) * a + u * b;
+    let first = [
+        lerp(&points[0], &points[1]),
+        lerp(&points[1], &points[2]),
+        lerp(&points[2], &points[3]),
+    ];
+    let second = [lerp(&first[0], &first[1]), lerp(&first[1], &first[2])];
+
+    let derivative = if (second[1] - second[0]).length_squared() > 0.0 {
+        3.0 * &(second[1] - second[0])
+    } else {
+        // the middle control points sit on the ends
+        points[3] - points[0]
+    };
+
+    (lerp(&second[0], &second[1]), derivative)
+}
+
+// Halves a cubic Bézier into two that trace the same curve
+fn split(points: &[Vec3; 4]) -> ([Vec3; 4], [Vec3; 4]) {
+    let middle = |a: &Vec3, b: &Vec3| 0.5 * &(a + b);
+    let ab = middle(&points[0], &points[1]);
+    let bc = middle(&points[1], &points[2]);
+    let cd = middle(&points[2], &points[3]);
+    let abc = middle(&ab, &bc);
+    let bcd = middle(&bc, &cd);
+    let abcd = middle(&abc, &bcd);
+
+    ([points[0], ab, abc, abcd], [abcd, bcd, cd, points[3]])
+}
+
+// Reads curves written as text, one curve to a line. Every control point is four numbers,
+// its position and then its width, and a curve of n segments has 3n + 1 of them since each
+// segment starts where the one before ends. Blank lines and lines starting with # are
+// skipped.
+pub fn parse_curves(text: &str) -> Result<Vec<Vec<(Vec3, f64)>>, String> {
+    let mut curves = vec![];
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let numbers = line
+            .split_whitespace()
+            .map(|number| number.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|why| format!("line {}: {}", line_number + 1, why))?;
+
+        if numbers.len() % 4 != 0 || numbers.len() < 16 || (numbers.len() / 4) % 3 != 1 {
+            return Err(format!(
+                "line {}: a curve needs 3n + 1 control points of x y z width, not {} numbers",
+                line_number + 1,
+                numbers.len()
+            ));
+        }
+
+        curves.push(
+            numbers
+                .chunks_exact(4)
+                .map(|point| (Vec3::new(point[0], point[1], point[2]), point[3]))
+                .collect(),
+        );
+    }
+
+    Ok(curves)
+}
@@ -0,0 +1,108 @@
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::hittable::{HitRecord, Hittable, Pixel};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sphere::{bounding_sphere, Sphere};
+use crate::utils::gamma;
+use crate::vector::Vec3;
+
+use std::f64::consts::PI;
+
+// A disk in the y = 0 plane, centred on the origin and facing up the y axis. A non zero
+// inner radius cuts a hole out of the middle, making it a ring.
+#[derive(Debug)]
+pub struct Disk {
+    pub radius: f64,
+    pub inner_radius: f64,
+    pub material: Box<dyn Material>,
+}
+
+impl Disk {
+    pub fn new(radius: f64, inner_radius: f64, material: Box<dyn Material>) -> Disk {
+        Disk {
+            radius,
+            inner_radius,
+            material,
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let t = -ray.origin.y / ray.direction.y;
+        if t <= t_min || t > t_max || t.is_nan() {
+            return None;
+        }
+
+        let x = ray.origin.x + t * ray.direction.x;
+        let z = ray.origin.z + t * ray.direction.z;
+        let distance_squared = x * x + z * z;
+        if distance_squared > self.radius * self.radius
+            || distance_squared < self.inner_radius * self.inner_radius
+        {
+            return None;
+        }
+
+        Some(t)
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        _pixel: Option<Pixel>,
+        _first_ray: bool,
+    ) -> Option<HitRecord<'_>> {
+        let t = self.intersect(ray, t_min, t_max)?;
+
+        let mut p = ray.at(t);
+        let mut p_error = gamma(3) * &(ray.origin.abs() + (t * &ray.direction).abs());
+        p.y = 0.0;
+        p_error.y = 0.0;
+
+        // u goes around the disk and v runs from the outer edge in to the inner one
+        let phi = p.z.atan2(p.x).rem_euclid(2.0 * PI);
+        let r = (p.x * p.x + p.z * p.z).sqrt();
+        let u = phi / (2.0 * PI);
+        let v = (self.radius - r) / (self.radius - self.inner_radius);
+
+        let geometric_normal = Vec3::new(0.0, 1.0, 0.0);
+        let front_face = ray.direction.y < 0.0;
+        let normal = if front_face {
+            geometric_normal
+        } else {
+            -geometric_normal
+        };
+
+        Some(HitRecord {
+            p,
+            t,
+            normal,
+            tangent: None,
+            bitangent: None,
+            material: &self.material,
+            front_face,
+            u,
+            v,
+            geometric_normal,
+            p_error,
+            colour: None,
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.intersect(ray, 0.0, t_max).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        Some(AxisAlignedBoundingBox::new(
+            Vec3::new(-self.radius, 0.0, -self.radius),
+            Vec3::new(self.radius, 0.0, self.radius),
+        ))
+    }
+
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        bounding_sphere(&self.bounding_box().unwrap())
+    }
+}
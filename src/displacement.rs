@@ -0,0 +1,309 @@
+use crate::vector::Vec3;
+
+use image::DynamicImage;
+use obj::{Obj, TexturedVertex};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+// Displacement mapping for meshes. Every triangle is split until none of its edges are
+// longer than the micropolygon size, then each vertex is pushed along its normal by the
+// height texture. Whether an edge is split depends only on its own length, so the
+// triangles either side of it always agree and the tessellation has no cracks.
+#[derive(Debug, Clone)]
+pub struct Displacement {
+    pub height_path: String,
+    // how far a white texel moves the surface, in the mesh's own units
+    pub scale: f64,
+    // the height that leaves the surface where it is, mid grey unless the scene says otherwise
+    pub midlevel: f64,
+    // the longest edge left after tessellating
+    pub edge_length: f64,
+}
+
+// Displacements are compared bit for bit, so they can be part of the mesh cache's key
+impl PartialEq for Displacement {
+    fn eq(&self, other: &Self) -> bool {
+        self.height_path == other.height_path
+            && self.scale.to_bits() == other.scale.to_bits()
+            && self.midlevel.to_bits() == other.midlevel.to_bits()
+            && self.edge_length.to_bits() == other.edge_length.to_bits()
+    }
+}
+
+impl Eq for Displacement {}
+
+impl Hash for Displacement {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.height_path.hash(state);
+        self.scale.to_bits().hash(state);
+        self.midlevel.to_bits().hash(state);
+        self.edge_length.to_bits().hash(state);
+    }
+}
+
+// An edge_length far too small for the mesh would otherwise quietly split it until the
+// machine runs out of memory
+const MAX_TRIANGLES: usize = 16_000_000;
+
+// The height texture as greyscale values from 0 to 1, sampled with wrapping UVs the same
+// way the image textures are
+struct HeightMap {
+    heights: Vec<f64>,
+    width: usize,
+    height: usize,
+}
+
+impl HeightMap {
+    fn new(image: &DynamicImage) -> HeightMap {
+        let image = image.to_luma16();
+        let (width, height) = image.dimensions();
+        HeightMap {
+            heights: image.pixels().map(|p| p[0] as f64 / 65535.0).collect(),
+            width: width as usize,
+            height: height as usize,
+        }
+    }
+
+    fn texel(&self, i: i64, j: i64) -> f64 {
+        let i = i.rem_euclid(self.width as i64) as usize;
+        let j = j.rem_euclid(self.height as i64) as usize;
+        self.heights[j * self.width + i]
+    }
+
+    // Bilinear, so the surface doesn't step between texels
+    fn sample(&self, u: f64, v: f64) -> f64 {
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (i, j) = (x.floor(), y.floor());
+        let (s, t) = (x - i, y - j);
+        let (i, j) = (i as i64, j as i64);
+
+        let top = self.texel(i, j) * (1.0 - s) + self.texel(i + 1, j) * s;
+        let bottom = self.texel(i, j + 1) * (1.0 - s) + self.texel(i + 1, j + 1) * s;
+        top * (1.0 - t) + bottom * t
+    }
+}
+
+// Every triangle is tagged with the face it came from, and the triangles it is split into
+// carry the same tag
+pub fn displace(
+    object: Obj<TexturedVertex, u32>,
+    faces: &[u32],
+    displacement: &Displacement,
+    height_image: &DynamicImage,
+) -> (Obj<TexturedVertex, u32>, Vec<u32>) {
+    let heights = HeightMap::new(height_image);
+    let Obj {
+        name,
+        mut vertices,
+        indices,
+    } = object;
+
+    let tessellation = tessellate(&mut vertices, &indices, faces, displacement.edge_length);
+    let (triangles, faces) = match tessellation {
+        Some(tessellation) => tessellation,
+        None => panic!(
+            "Displacing by {} needs more than {} triangles, try an edge_length above {}",
+            displacement.height_path, MAX_TRIANGLES, displacement.edge_length
+        ),
+    };
+
+    // Vertices split for UV seams or hard edges all move the same way, along the average of
+    // their normals, so the surface doesn't tear open along them
+    let mut directions: HashMap<[u32; 3], Vec3> = HashMap::new();
+    for vertex in &vertices {
+        let direction = directions
+            .entry(position_key(vertex))
+            .or_insert(Vec3::new(0.0, 0.0, 0.0));
+        *direction += to_vec3(vertex.normal);
+    }
+    // meshes without normals fall back to the faces around each position
+    let face_normals: Vec<Vec3> = triangles
+        .iter()
+        .map(|triangle| face_normal(&vertices, triangle))
+        .collect();
+    for (triangle, normal) in triangles.iter().zip(&face_normals) {
+        for &index in triangle {
+            let direction = directions
+                .get_mut(&position_key(&vertices[index as usize]))
+                .unwrap();
+            if to_vec3(vertices[index as usize].normal).near_zero() {
+                *direction += *normal;
+            }
+        }
+    }
+
+    // Shading normals are rebuilt from the displaced faces. Vertices that only differ by
+    // their UVs are smoothed together, those with different normals stay apart.
+    let smoothing_keys: Vec<([u32; 3], [u32; 3])> = vertices
+        .iter()
+        .map(|vertex| (position_key(vertex), vertex.normal.map(f32::to_bits)))
+        .collect();
+
+    for vertex in vertices.iter_mut() {
+        let direction = &directions[&position_key(vertex)];
+        if direction.near_zero() {
+            continue;
+        }
+
+        let h = heights.sample(vertex.texture[0] as f64, vertex.texture[1] as f64);
+        let offset = (h - displacement.midlevel) * displacement.scale;
+        let p = to_vec3(vertex.position) + offset * &direction.unit();
+        vertex.position = [p.x as f32, p.y as f32, p.z as f32];
+    }
+
+    let mut normals: HashMap<([u32; 3], [u32; 3]), Vec3> = HashMap::new();
+    for triangle in &triangles {
+        let normal = face_normal(&vertices, triangle);
+        for &index in triangle {
+            *normals
+                .entry(smoothing_keys[index as usize])
+                .or_insert(Vec3::new(0.0, 0.0, 0.0)) += normal;
+        }
+    }
+    for (vertex, key) in vertices.iter_mut().zip(&smoothing_keys) {
+        let normal = &normals[key];
+        if !normal.near_zero() {
+            let normal = normal.unit();
+            vertex.normal = [normal.x as f32, normal.y as f32, normal.z as f32];
+        }
+    }
+
+    let object = Obj {
+        name,
+        vertices,
+        indices: triangles.into_iter().flatten().collect(),
+    };
+    (object, faces)
+}
+
+// Splits the triangles until every edge is at most `edge_length` long. Midpoints are
+// shared through the edge they split, and a triangle with some of its edges split is cut
+// into the 2, 3 or 4 triangles that use exactly those midpoints. Gives up with None once
+// there would be more than MAX_TRIANGLES.
+fn tessellate(
+    vertices: &mut Vec<TexturedVertex>,
+    indices: &[u32],
+    faces: &[u32],
+    edge_length: f64,
+) -> Option<(Vec<[u32; 3]>, Vec<u32>)> {
+    let max_length_squared = edge_length * edge_length;
+    let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut pending: Vec<([u32; 3], u32)> = indices
+        .chunks_exact(3)
+        .zip(faces)
+        .map(|(corners, &face)| ([corners[0], corners[1], corners[2]], face))
+        .collect();
+    let mut triangles = vec![];
+    let mut triangle_faces = vec![];
+
+    while let Some((mut triangle, face)) = pending.pop() {
+        // splitting never removes a triangle, so this only grows
+        if triangles.len() + pending.len() >= MAX_TRIANGLES {
+            return None;
+        }
+
+        let mut long = [0, 1, 2].map(|i| {
+            length_squared(vertices, triangle[i], triangle[(i + 1) % 3]) > max_length_squared
+        });
+
+        match long.iter().filter(|&&long| long).count() {
+            0 => {
+                triangles.push(triangle);
+                triangle_faces.push(face);
+            }
+            3 => {
+                let [a, b, c] = triangle;
+                let ab = midpoint(vertices, &mut midpoints, a, b);
+                let bc = midpoint(vertices, &mut midpoints, b, c);
+                let ca = midpoint(vertices, &mut midpoints, c, a);
+                pending.extend(
+                    [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]].map(|t| (t, face)),
+                );
+            }
+            1 => {
+                // turn the triangle so the long edge runs from a to b
+                while !long[0] {
+                    triangle.rotate_left(1);
+                    long.rotate_left(1);
+                }
+                let [a, b, c] = triangle;
+                let ab = midpoint(vertices, &mut midpoints, a, b);
+                pending.extend([[a, ab, c], [ab, b, c]].map(|t| (t, face)));
+            }
+            _ => {
+                // turn the triangle so the short edge runs from c to a
+                while long[2] {
+                    triangle.rotate_left(1);
+                    long.rotate_left(1);
+                }
+                let [a, b, c] = triangle;
+                let ab = midpoint(vertices, &mut midpoints, a, b);
+                let bc = midpoint(vertices, &mut midpoints, b, c);
+                pending.push(([ab, b, bc], face));
+
+                // the quad left over is cut along its shorter diagonal
+                if length_squared(vertices, a, bc) <= length_squared(vertices, ab, c) {
+                    pending.extend([[a, ab, bc], [a, bc, c]].map(|t| (t, face)));
+                } else {
+                    pending.extend([[a, ab, c], [ab, bc, c]].map(|t| (t, face)));
+                }
+            }
+        }
+    }
+
+    Some((triangles, triangle_faces))
+}
+
+fn midpoint(
+    vertices: &mut Vec<TexturedVertex>,
+    midpoints: &mut HashMap<(u32, u32), u32>,
+    a: u32,
+    b: u32,
+) -> u32 {
+    let key = (a.min(b), a.max(b));
+    if let Some(&index) = midpoints.get(&key) {
+        return index;
+    }
+
+    let (first, second) = (&vertices[key.0 as usize], &vertices[key.1 as usize]);
+    let average = |x: [f32; 3], y: [f32; 3]| [0, 1, 2].map(|i| (x[i] + y[i]) * 0.5);
+    let mut normal = average(first.normal, second.normal);
+    let length = normal.iter().map(|n| n * n).sum::<f32>().sqrt();
+    if length > 0.0 {
+        normal = normal.map(|n| n / length);
+    }
+    let vertex = TexturedVertex {
+        position: average(first.position, second.position),
+        normal,
+        texture: average(first.texture, second.texture),
+    };
+
+    let index = vertices.len() as u32;
+    vertices.push(vertex);
+    midpoints.insert(key, index);
+
+    index
+}
+
+// Worked out the same way whichever end the edge is seen from, so both triangles sharing it
+// make the same choice
+fn length_squared(vertices: &[TexturedVertex], a: u32, b: u32) -> f64 {
+    let (a, b) = (a.min(b), a.max(b));
+    let edge = to_vec3(vertices[b as usize].position) - to_vec3(vertices[a as usize].position);
+    edge.dot(&edge)
+}
+
+// Not normalised, so bigger faces count for more when averaged
+fn face_normal(vertices: &[TexturedVertex], triangle: &[u32; 3]) -> Vec3 {
+    let [a, b, c] = triangle.map(|index| to_vec3(vertices[index as usize].position));
+    (b - a).cross(&(c - a))
+}
+
+fn position_key(vertex: &TexturedVertex) -> [u32; 3] {
+    vertex.position.map(f32::to_bits)
+}
+
+fn to_vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0] as f64, v[1] as f64, v[2] as f64)
+}
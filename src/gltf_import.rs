@@ -0,0 +1,720 @@
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::bxdf::MicrofacetReflection;
+use crate::colour::Colour;
+use crate::disk::Disk;
+use crate::hittable::Hittable;
+use crate::instance::Transform;
+use crate::json::CameraJSON;
+use crate::material::{Cutout, Dielectric, Emissive, Light, Material, MicrofacetReflectance};
+use crate::object::{AcceleratorType, Mesh, MeshMaterials};
+use crate::onb::OrthonormalBasis;
+use crate::sphere::Sphere;
+use crate::texture::{ImageTexture, SharedTexture, SolidColour, Texture};
+use crate::transform::Matrix4;
+use crate::triangle::{TriangleMesh, VertexPrecision};
+use crate::vector::Vec3;
+
+use gltf::camera::Projection;
+use gltf::khr_lights_punctual::Kind;
+use gltf::material::AlphaMode;
+use gltf::mesh::Mode;
+use gltf::{buffer, Document, Gltf, Node};
+use image::DynamicImage;
+use std::cell::OnceCell;
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+// Angle from the centre of the sun's disk to its edge, seen from the ground
+const SUN_ANGULAR_RADIUS: f64 = 0.0047;
+
+// A glTF or GLB file with its buffers read in and its images decoded. Each material's
+// textures are made the first time it is used, and shared by every node using it.
+pub struct GltfFile {
+    pub document: Document,
+    buffers: Vec<Vec<u8>>,
+    images: Vec<DynamicImage>,
+    textures: Vec<OnceCell<MaterialTextures>>,
+}
+
+// The textures made for a material, with the intensity of its emission
+#[derive(Clone)]
+struct MaterialTextures {
+    albedo: SharedTexture,
+    metallic_roughness: Option<SharedTexture>,
+    emission: Option<(SharedTexture, f64)>,
+}
+
+impl GltfFile {
+    // Buffers and images come from a GLB's binary chunk, from data URIs or from files next
+    // to the glTF
+    pub fn open(path: &str) -> Result<GltfFile, String> {
+        let bytes = fs::read(path).map_err(|why| why.to_string())?;
+        let Gltf { document, mut blob } =
+            Gltf::from_slice(&bytes).map_err(|why| why.to_string())?;
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+
+        let mut buffers = vec![];
+        for buffer in document.buffers() {
+            let mut data = match buffer.source() {
+                buffer::Source::Bin => match blob.take() {
+                    Some(blob) => blob,
+                    None => return Err("buffer 0 is missing its binary chunk".to_string()),
+                },
+                buffer::Source::Uri(uri) => read_uri(uri, directory)?,
+            };
+            if data.len() < buffer.length() {
+                return Err(format!(
+                    "buffer {} has {} bytes but should have {}",
+                    buffer.index(),
+                    data.len(),
+                    buffer.length()
+                ));
+            }
+            // a GLB's binary chunk can be padded
+            data.truncate(buffer.length());
+            buffers.push(data);
+        }
+
+        let mut images = vec![];
+        for image in document.images() {
+            let data = match image.source() {
+                gltf::image::Source::View { view, .. } => buffers[view.buffer().index()]
+                    .get(view.offset()..view.offset() + view.length())
+                    .ok_or_else(|| format!("image {} is outside its buffer", image.index()))?
+                    .to_vec(),
+                gltf::image::Source::Uri { uri, .. } => read_uri(uri, directory)?,
+            };
+            match image::load_from_memory(&data) {
+                Err(why) => return Err(format!("image {}: {}", image.index(), why)),
+                Ok(decoded) => images.push(decoded),
+            }
+        }
+
+        let textures = document.materials().map(|_| OnceCell::new()).collect();
+
+        Ok(GltfFile {
+            document,
+            buffers,
+            images,
+            textures,
+        })
+    }
+
+    // Every node of the file's default scene, or else its first, with the transform from the
+    // node to the scene. Parents come before their children. Nodes scaled to nothing, as
+    // hidden objects are often exported, are left out along with their children.
+    pub fn nodes(&self) -> Result<Vec<(Node<'_>, Matrix4)>, String> {
+        let scene = match self.document.default_scene() {
+            Some(scene) => scene,
+            None => match self.document.scenes().next() {
+                Some(scene) => scene,
+                None => return Err("it has no scenes".to_string()),
+            },
+        };
+
+        let mut nodes = vec![];
+        let mut pending: Vec<(Node, Matrix4)> = scene
+            .nodes()
+            .map(|node| (node, Matrix4::identity()))
+            .collect();
+        while let Some((node, parent)) = pending.pop() {
+            let world = parent.multiply(&node_matrix(&node));
+            if world.try_inverse().is_none() {
+                continue;
+            }
+            pending.extend(node.children().map(|child| (child, world)));
+            nodes.push((node, world));
+        }
+
+        Ok(nodes)
+    }
+
+    // The materials of a mesh's primitives in the order they first appear, which are the
+    // mesh's material slots
+    pub fn mesh_materials<'a>(&self, mesh: &gltf::Mesh<'a>) -> Vec<gltf::Material<'a>> {
+        let mut materials: Vec<gltf::Material> = vec![];
+        for primitive in mesh.primitives() {
+            let material = primitive.material();
+            if materials
+                .iter()
+                .all(|seen| seen.index() != material.index())
+            {
+                materials.push(material);
+            }
+        }
+
+        materials
+    }
+
+    // All of a mesh's triangle primitives as one mesh, each triangle in the slot of its
+    // primitive's material. Point and line primitives are left out, so meshes without any
+    // triangles should be passed over with `has_triangles`. Primitives without normals are
+    // shaded flat, and the mesh's tangents are only kept if every primitive has them. The
+    // mesh has one set of UVs, which each primitive takes from the set its material's
+    // textures use.
+    pub fn mesh(
+        &self,
+        mesh: &gltf::Mesh,
+        accelerator: AcceleratorType,
+        precision: VertexPrecision,
+    ) -> Result<Mesh, String> {
+        let slots = self.mesh_materials(mesh);
+        let mut positions: Vec<Vec3> = vec![];
+        let mut normals = vec![];
+        let mut text_coords = vec![];
+        let mut tangents = vec![];
+        let mut has_tangents = true;
+        let mut indices = vec![];
+        let mut triangle_slots = vec![];
+
+        for primitive in mesh.primitives() {
+            let describe = || format!("mesh {} primitive {}", mesh.index(), primitive.index());
+            let reader =
+                primitive.reader(|buffer| self.buffers.get(buffer.index()).map(|data| &data[..]));
+
+            let primitive_positions: Vec<Vec3> = match reader.read_positions() {
+                Some(read) => read
+                    .map(|[x, y, z]| Vec3::new(x.into(), y.into(), z.into()))
+                    .collect(),
+                None => return Err(format!("{} has no positions", describe())),
+            };
+            let count = primitive_positions.len();
+            let corners: Vec<u32> = match reader.read_indices() {
+                Some(read) => read.into_u32().collect(),
+                None => (0..count as u32).collect(),
+            };
+            if let Some(index) = corners.iter().find(|&&index| index as usize >= count) {
+                return Err(format!(
+                    "{} has index {} for {} vertices",
+                    describe(),
+                    index,
+                    count
+                ));
+            }
+
+            let triangles: Vec<[u32; 3]> = match primitive.mode() {
+                Mode::Triangles => corners
+                    .chunks_exact(3)
+                    .map(|corners| [corners[0], corners[1], corners[2]])
+                    .collect(),
+                // every other triangle of a strip is turned round to face the same way
+                Mode::TriangleStrip => (2..corners.len())
+                    .map(|i| match i % 2 {
+                        0 => [corners[i - 2], corners[i - 1], corners[i]],
+                        _ => [corners[i - 1], corners[i - 2], corners[i]],
+                    })
+                    .collect(),
+                Mode::TriangleFan => (2..corners.len())
+                    .map(|i| [corners[0], corners[i - 1], corners[i]])
+                    .collect(),
+                _ => continue,
+            };
+
+            // glTF puts the origin of its UVs at the top of the image
+            let material = primitive.material();
+            let pbr = material.pbr_metallic_roughness();
+            let base_colour_set = pbr.base_color_texture().map(|info| info.tex_coord());
+            let other_sets = [
+                (
+                    "normal map",
+                    material.normal_texture().map(|info| info.tex_coord()),
+                ),
+                (
+                    "metallic-roughness texture",
+                    pbr.metallic_roughness_texture()
+                        .map(|info| info.tex_coord()),
+                ),
+                (
+                    "emissive texture",
+                    material.emissive_texture().map(|info| info.tex_coord()),
+                ),
+            ];
+            let set = base_colour_set
+                .or(other_sets.iter().find_map(|(_, set)| *set))
+                .unwrap_or(0);
+            for (texture, other_set) in other_sets {
+                if let Some(other_set) = other_set.filter(|&other_set| other_set != set) {
+                    println!(
+                        "The {} of {} uses TEXCOORD_{} but will be read with TEXCOORD_{}",
+                        texture,
+                        describe(),
+                        other_set,
+                        set
+                    );
+                }
+            }
+            let primitive_text_coords: Vec<[f32; 2]> = match reader.read_tex_coords(set) {
+                Some(read) => read.into_f32().map(|[u, v]| [u, 1.0 - v]).collect(),
+                None => vec![[0.0, 0.0]; count],
+            };
+            let primitive_normals: Option<Vec<[f32; 3]>> =
+                reader.read_normals().map(|read| read.collect());
+            let primitive_tangents: Option<Vec<[f32; 4]>> =
+                reader.read_tangents().map(|read| read.collect());
+            if primitive_text_coords.len() != count
+                || primitive_normals.as_ref().is_some_and(|n| n.len() != count)
+                || primitive_tangents
+                    .as_ref()
+                    .is_some_and(|t| t.len() != count)
+            {
+                return Err(format!(
+                    "{} has attributes of different lengths",
+                    describe()
+                ));
+            }
+            has_tangents &= primitive_tangents.is_some();
+
+            let slot = slots
+                .iter()
+                .position(|material| material.index() == primitive.material().index())
+                .unwrap_or(0) as u32;
+            triangle_slots.extend(std::iter::repeat_n(slot, triangles.len()));
+
+            match primitive_normals {
+                Some(primitive_normals) => {
+                    let first = positions.len() as u32;
+                    positions.extend(primitive_positions);
+                    normals.extend(primitive_normals);
+                    text_coords.extend(primitive_text_coords);
+                    tangents.extend(primitive_tangents.into_iter().flatten());
+                    for triangle in triangles {
+                        indices.extend(triangle.map(|corner| first + corner));
+                    }
+                }
+                // each flat triangle has corners of its own with its normal
+                None => {
+                    for triangle in triangles {
+                        let [p0, p1, p2] =
+                            triangle.map(|corner| primitive_positions[corner as usize]);
+                        // a triangle with no area has no normal, but it can't be hit either
+                        let normal = (p1 - p0).cross(&(p2 - p0)).unit();
+                        let normal = match normal.x.is_finite() {
+                            true => [normal.x as f32, normal.y as f32, normal.z as f32],
+                            false => [0.0, 1.0, 0.0],
+                        };
+                        for corner in triangle.map(|corner| corner as usize) {
+                            indices.push(positions.len() as u32);
+                            positions.push(primitive_positions[corner]);
+                            normals.push(normal);
+                            text_coords.push(primitive_text_coords[corner]);
+                            if let Some(primitive_tangents) = &primitive_tangents {
+                                tangents.push(primitive_tangents[corner]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        if indices.is_empty() {
+            return Err(format!("mesh {} has no triangles", mesh.index()));
+        }
+
+        let mut triangles =
+            TriangleMesh::new(positions, normals, text_coords, indices, true, precision);
+        if has_tangents {
+            triangles = triangles.with_tangents(tangents);
+        }
+
+        // a mesh with one material needs no slot for each triangle
+        let materials = MeshMaterials {
+            names: slots.iter().map(material_name).collect(),
+            libraries: vec![],
+            triangles: if slots.len() > 1 {
+                triangle_slots
+            } else {
+                vec![]
+            },
+        };
+
+        Ok(Mesh::from_triangles(triangles, materials, accelerator))
+    }
+
+    // Stands a metallic-roughness material in for the nearest material the tracer has, and
+    // says whether it is a light. Transmissive materials become glass and the rest a
+    // MicrofacetReflectance, glowing where they are emissive. Materials that are masked or
+    // blended have holes cut where their base colour is transparent. Occlusion textures are
+    // left out, as the tracer finds the occlusion itself.
+    pub fn material(&self, material: &gltf::Material) -> (Box<dyn Material>, bool) {
+        let MaterialTextures {
+            albedo,
+            metallic_roughness,
+            emission,
+        } = match material.index() {
+            Some(index) => self.textures[index]
+                .get_or_init(|| self.textures(material))
+                .clone(),
+            None => self.textures(material),
+        };
+
+        let index_of_refraction = material.ior().unwrap_or(1.5) as f64;
+        let transmission = material
+            .transmission()
+            .map_or(0.0, |transmission| transmission.transmission_factor());
+        let surface: Box<dyn Material> = if transmission > 0.0 {
+            Box::new(Dielectric {
+                albedo: Some(Box::new(albedo.clone())),
+                index_of_refraction,
+            })
+        } else {
+            // the microfacet model's f0 is 0.36 reflectance squared
+            let f0 = ((index_of_refraction - 1.0) / (index_of_refraction + 1.0)).powi(2);
+            let pbr = material.pbr_metallic_roughness();
+            let bxdf = MicrofacetReflection::new(
+                pbr.metallic_factor().into(),
+                pbr.roughness_factor().into(),
+                (f0 / 0.36).sqrt(),
+                true,
+            );
+            Box::new(MicrofacetReflectance {
+                albedo: Box::new(albedo.clone()),
+                bxdf: Box::new(bxdf),
+                metallic_roughness: metallic_roughness
+                    .map(|texture| Box::new(texture) as Box<dyn Texture + Send + Sync>),
+            })
+        };
+
+        let (surface, is_light): (Box<dyn Material>, bool) = match emission {
+            Some((emission, intensity)) => (
+                Box::new(Emissive {
+                    material: surface,
+                    emission: Box::new(emission),
+                    intensity,
+                }),
+                true,
+            ),
+            None => (surface, false),
+        };
+
+        // a blended surface lets through as many rays as it is transparent
+        let cutoff = match material.alpha_mode() {
+            AlphaMode::Opaque => return (surface, is_light),
+            AlphaMode::Mask => Some(material.alpha_cutoff().unwrap_or(0.5) as f64),
+            AlphaMode::Blend => None,
+        };
+        let pbr = material.pbr_metallic_roughness();
+        let cutout = Cutout {
+            material: surface,
+            alpha: pbr
+                .base_color_texture()
+                .map(|_| Box::new(albedo) as Box<dyn Texture + Send + Sync>),
+            opacity: pbr.base_color_factor()[3].into(),
+            cutoff,
+        };
+        (Box::new(cutout), is_light)
+    }
+
+    // A material's base colour, tinted by its factor, with its normal map and its alpha when
+    // the material isn't opaque. Then its metallic-roughness texture, and its emission with
+    // the intensity of its brightest channel.
+    fn textures(&self, material: &gltf::Material) -> MaterialTextures {
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let normal_map = material
+            .normal_texture()
+            .map(|normal| self.images[normal.texture().source().index()].clone());
+        let albedo = match pbr.base_color_texture() {
+            Some(base_colour) => {
+                let image = tint(
+                    &self.images[base_colour.texture().source().index()],
+                    [r, g, b],
+                );
+                let alpha = match material.alpha_mode() {
+                    AlphaMode::Opaque => None,
+                    AlphaMode::Mask | AlphaMode::Blend => Some(image.clone()),
+                };
+                SharedTexture(Arc::new(ImageTexture::new(
+                    image, alpha, normal_map, None, false, 1.0,
+                )))
+            }
+            None => SharedTexture(Arc::new(SolidColour::new(
+                Colour::new(r.into(), g.into(), b.into()),
+                normal_map,
+                None,
+            ))),
+        };
+
+        // the factors multiply the texture, so without one they are left as they are
+        let metallic_roughness = pbr.metallic_roughness_texture().map(|texture| {
+            SharedTexture(Arc::new(ImageTexture::new(
+                self.images[texture.texture().source().index()].clone(),
+                None,
+                None,
+                None,
+                false,
+                1.0,
+            )))
+        });
+
+        let strength = material.emissive_strength().unwrap_or(1.0);
+        let emissive = material.emissive_factor().map(|c| c * strength);
+        let brightest = emissive.iter().cloned().fold(0.0, f32::max);
+        let [r, g, b] = emissive.map(|c| c / brightest);
+        let emission = match material.emissive_texture() {
+            _ if brightest <= 0.0 => None,
+            // the texture is squared as it is read, so it is tinted by the root of the factor
+            Some(texture) => Some(SharedTexture(Arc::new(ImageTexture::new(
+                tint(
+                    &self.images[texture.texture().source().index()],
+                    [r.sqrt(), g.sqrt(), b.sqrt()],
+                ),
+                None,
+                None,
+                None,
+                true,
+                1.0,
+            )))),
+            None => Some(SharedTexture(Arc::new(SolidColour::new(
+                Colour::new(r.into(), g.into(), b.into()),
+                None,
+                None,
+            )))),
+        };
+
+        MaterialTextures {
+            albedo,
+            metallic_roughness,
+            emission: emission.map(|emission| (emission, brightest.into())),
+        }
+    }
+}
+
+// Whether any of a mesh's primitives are triangles, rather than all being points or lines
+pub fn has_triangles(mesh: &gltf::Mesh) -> bool {
+    mesh.primitives().any(|primitive| {
+        matches!(
+            primitive.mode(),
+            Mode::Triangles | Mode::TriangleStrip | Mode::TriangleFan
+        )
+    })
+}
+
+// The name a material goes by in the scene's `materials`. Unnamed ones are called by their
+// index, and primitives without a material have the empty name.
+pub fn material_name(material: &gltf::Material) -> String {
+    match (material.name(), material.index()) {
+        (Some(name), _) => name.to_string(),
+        (None, Some(index)) => format!("material {}", index),
+        (None, None) => String::new(),
+    }
+}
+
+// A perspective camera at a node, looking down the node's -z with its +y up. There are no
+// orthographic cameras in the tracer, so those give None.
+pub fn camera(camera: &gltf::Camera, world: &Matrix4, aspect_ratio: f64) -> Option<CameraJSON> {
+    let perspective = match camera.projection() {
+        Projection::Perspective(perspective) => perspective,
+        Projection::Orthographic(_) => return None,
+    };
+
+    let look_from = world.transform_point(&Vec3::new(0.0, 0.0, 0.0));
+    let look_at = look_from + world.transform_vector(&Vec3::new(0.0, 0.0, -1.0)).unit();
+    let v_up = world.transform_vector(&Vec3::new(0.0, 1.0, 0.0)).unit();
+
+    Some(CameraJSON {
+        aspect_ratio,
+        look_from: [look_from.x, look_from.y, look_from.z],
+        look_at: [look_at.x, look_at.y, look_at.z],
+        vfov: (perspective.yfov() as f64).to_degrees(),
+        v_up: [v_up.x, v_up.y, v_up.z],
+        dist_to_focus: 1.0,
+        aperture: 0.0,
+        stereo: None,
+        shutter_open: None,
+        shutter_close: None,
+    })
+}
+
+// Every light in the tracer has a surface, so punctual lights become small glowing shapes.
+// Point and spot lights are spheres of `radius` giving the light's intensity in candela,
+// with a spot's cone left out. Directional lights become a Sun, placed once the scene
+// around it is known.
+pub enum PunctualLight {
+    Shape(Box<dyn Hittable>),
+    Sun(Sun),
+}
+
+pub fn light(
+    light: &gltf::khr_lights_punctual::Light,
+    world: &Matrix4,
+    radius: f64,
+) -> PunctualLight {
+    let colour = light.color();
+    let intensity = light.intensity() as f64;
+
+    match light.kind() {
+        // seen from afar a sphere's intensity is its radiance over a disk of its radius
+        Kind::Point | Kind::Spot { .. } => {
+            let centre = world.transform_point(&Vec3::new(0.0, 0.0, 0.0));
+            PunctualLight::Shape(Box::new(Sphere::new(
+                centre,
+                radius,
+                glow(colour, intensity / (PI * radius * radius)),
+            )))
+        }
+        Kind::Directional => PunctualLight::Sun(Sun {
+            towards_light: -world.transform_vector(&Vec3::new(0.0, 0.0, -1.0)).unit(),
+            colour,
+            illuminance: intensity,
+        }),
+    }
+}
+
+// A directional light, giving its illuminance in lux from the direction it points away from
+pub struct Sun {
+    towards_light: Vec3,
+    colour: [f32; 3],
+    illuminance: f64,
+}
+
+impl Sun {
+    // A disk the size of the sun far beyond `bounds`, whose radiance over the small solid
+    // angle it fills is the illuminance
+    pub fn place(&self, bounds: &AxisAlignedBoundingBox) -> Box<dyn Hittable> {
+        let distance = 100.0 * (bounds.maximum - bounds.minimum).length().max(1.0);
+        let disk_radius = distance * SUN_ANGULAR_RADIUS.tan();
+        let solid_angle = PI * SUN_ANGULAR_RADIUS * SUN_ANGULAR_RADIUS;
+        let disk = Disk::new(
+            disk_radius,
+            0.0,
+            glow(self.colour, self.illuminance / solid_angle),
+        );
+
+        // the disk faces up its y axis, which is turned towards the scene
+        let basis = OrthonormalBasis::build_from_w(&self.towards_light);
+        let (x, y, z) = (basis.v(), basis.w(), basis.u());
+        let centre = bounds.centroid + distance * &self.towards_light;
+        let world_from_disk = Matrix4 {
+            m: [
+                [x.x, y.x, z.x, centre.x],
+                [x.y, y.y, z.y, centre.y],
+                [x.z, y.z, z.z, centre.z],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        };
+        Box::new(Transform::new(Box::new(disk), world_from_disk))
+    }
+}
+
+fn glow([r, g, b]: [f32; 3], intensity: f64) -> Box<dyn Material> {
+    Box::new(Light {
+        albedo: Box::new(SolidColour::new(
+            Colour::new(r.into(), g.into(), b.into()),
+            None,
+            None,
+        )),
+        intensity,
+    })
+}
+
+// glTF matrices are stored by column
+fn node_matrix(node: &Node) -> Matrix4 {
+    let columns = node.transform().matrix();
+    Matrix4 {
+        m: std::array::from_fn(|row| std::array::from_fn(|column| columns[column][row].into())),
+    }
+}
+
+// Multiplies an image by a base colour factor, which image textures have no room for
+fn tint(image: &DynamicImage, factor: [f32; 3]) -> DynamicImage {
+    if factor == [1.0, 1.0, 1.0] {
+        return image.clone();
+    }
+
+    let mut tinted = image.to_rgba8();
+    for pixel in tinted.pixels_mut() {
+        for (channel, factor) in pixel.0.iter_mut().zip(factor) {
+            *channel = (*channel as f32 * factor).round() as u8;
+        }
+    }
+    DynamicImage::ImageRgba8(tinted)
+}
+
+// The bytes of a base64 data URI, or of the file a relative URI names
+fn read_uri(uri: &str, directory: &Path) -> Result<Vec<u8>, String> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        return match data.split_once(";base64,") {
+            Some((_, data)) => base64::decode(data).map_err(|why| why.to_string()),
+            None => Err("only base64 data URIs are supported".to_string()),
+        };
+    }
+
+    let path = directory.join(percent_decode(uri));
+    fs::read(&path).map_err(|why| format!("{}: {}", path.display(), why))
+}
+
+// URIs escape characters like spaces as %20
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes[i] {
+            b'%' => uri
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MATERIALS: &str = r#"{
+        "asset": { "version": "2.0" },
+        "materials": [
+            { "name": "leaf", "alphaMode": "MASK",
+              "pbrMetallicRoughness": { "baseColorFactor": [0.2, 0.6, 0.1, 0.4] } },
+            { "name": "veil", "alphaMode": "BLEND",
+              "pbrMetallicRoughness": { "baseColorFactor": [1, 1, 1, 0.4] } },
+            { "name": "lamp", "emissiveFactor": [1, 0.5, 0] }
+        ]
+    }"#;
+
+    #[test]
+    fn escapes_are_decoded_and_stray_percents_kept() {
+        assert_eq!(percent_decode("my%20model.bin"), "my model.bin");
+        assert_eq!(percent_decode("caf%C3%A9.png"), "café.png");
+        assert_eq!(percent_decode("100%.png"), "100%.png");
+        assert_eq!(percent_decode("50%zz%2"), "50%zz%2");
+    }
+
+    #[test]
+    fn alpha_modes_cut_holes_and_emissive_materials_glow() {
+        let path =
+            std::env::temp_dir().join(format!("tracer-gltf-test-{}.gltf", std::process::id()));
+        fs::write(&path, MATERIALS).unwrap();
+        let file = GltfFile::open(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        let file = file.unwrap();
+        let materials: Vec<gltf::Material> = file.document.materials().collect();
+
+        // a mask is all or nothing at its cutoff, a blend lets through what it doesn't cover
+        let (leaf, is_light) = file.material(&materials[0]);
+        assert!(leaf.has_cutouts() && !is_light);
+        assert_eq!(leaf.opacity(0.5, 0.5), 0.0);
+        let (veil, _) = file.material(&materials[1]);
+        assert!((veil.opacity(0.5, 0.5) - 0.4).abs() < 1e-6);
+
+        let (lamp, is_light) = file.material(&materials[2]);
+        assert!(is_light && !lamp.has_cutouts());
+        let emitted = lamp.emitted(0.5, 0.5, &Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!((emitted.r, emitted.g, emitted.b), (1.0, 0.5, 0.0));
+    }
+}
@@ -0,0 +1,417 @@
+use crate::camera::Camera;
+use crate::colour::Colour;
+use crate::hittable::HitRecord;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vector::Vec3;
+
+use rand::Rng;
+use std::f64::consts::{LN_2, PI};
+
+// Light bounces inside a hair up to this many times before the rest is lumped together
+const P_MAX: usize = 3;
+
+// Absorption of the two pigments in hair per unit of concentration, for red, green and blue
+const EUMELANIN_SIGMA_A: [f64; 3] = [0.419, 0.697, 1.37];
+const PHEOMELANIN_SIGMA_A: [f64; 3] = [0.187, 0.4, 1.05];
+
+// The hair scattering model of Chiang et al. 2016, built on d'Eon et al. 2011, as given in
+// pbrt. Light reflects off the hair's surface, passes straight through it, or reflects once
+// or more inside it, and each of those is spread lengthways by the longitudinal roughness
+// and around the hair by the azimuthal roughness. The hair is expected to be a curve, whose
+// hit records give the direction along it as the tangent and v across it.
+#[derive(Debug)]
+pub struct Hair {
+    // absorption inside the hair per unit of its diameter
+    sigma_a: [f64; 3],
+    eta: f64,
+    // variance of the longitudinal lobe for each number of bounces
+    v: [f64; P_MAX + 1],
+    // scale of the azimuthal logistic
+    s: f64,
+    // sine and cosine of 2^k times the tilt of the cuticle scales
+    sin_2k_alpha: [f64; 3],
+    cos_2k_alpha: [f64; 3],
+}
+
+impl Hair {
+    // beta_m and beta_n are the longitudinal and azimuthal roughness from 0 to 1, and alpha
+    // the tilt of the scales in degrees
+    pub fn new(sigma_a: [f64; 3], eta: f64, beta_m: f64, beta_n: f64, alpha: f64) -> Hair {
+        let mut v = [0.0; P_MAX + 1];
+        v[0] = (0.726 * beta_m + 0.812 * beta_m * beta_m + 3.7 * beta_m.powi(20)).powi(2);
+        v[1] = 0.25 * v[0];
+        v[2] = 4.0 * v[0];
+        for p in 3..=P_MAX {
+            v[p] = v[2];
+        }
+
+        let s = (PI / 8.0).sqrt()
+            * (0.265 * beta_n + 1.194 * beta_n * beta_n + 5.372 * beta_n.powi(22));
+
+        let mut sin_2k_alpha = [alpha.to_radians().sin(), 0.0, 0.0];
+        let mut cos_2k_alpha = [
+            (1.0 - sin_2k_alpha[0] * sin_2k_alpha[0]).max(0.0).sqrt(),
+            0.0,
+            0.0,
+        ];
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1].powi(2) - sin_2k_alpha[i - 1].powi(2);
+        }
+
+        Hair {
+            sigma_a,
+            eta,
+            v,
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    // Absorption for hair with the given concentrations of the dark brown eumelanin and the
+    // red pheomelanin
+    pub fn sigma_a_from_melanin(eumelanin: f64, pheomelanin: f64) -> [f64; 3] {
+        [0, 1, 2].map(|i| eumelanin * EUMELANIN_SIGMA_A[i] + pheomelanin * PHEOMELANIN_SIGMA_A[i])
+    }
+
+    // Absorption that gives roughly the colour asked for once the light has bounced around
+    // many hairs, fitted for the azimuthal roughness
+    pub fn sigma_a_from_colour(colour: [f64; 3], beta_n: f64) -> [f64; 3] {
+        let fit = 5.969 - 0.215 * beta_n + 2.532 * beta_n.powi(2) - 10.73 * beta_n.powi(3)
+            + 5.574 * beta_n.powi(4)
+            + 0.245 * beta_n.powi(5);
+        colour.map(|c| (c.max(1e-4).ln() / fit).powi(2))
+    }
+
+    // The angle of the outgoing direction lengthways turned by the tilt of the scales, which
+    // differs for each number of bounces
+    fn tilt(&self, p: usize, sin_theta_o: f64, cos_theta_o: f64) -> (f64, f64) {
+        let (sin_theta_op, cos_theta_op) = match p {
+            0 => (
+                sin_theta_o * self.cos_2k_alpha[1] - cos_theta_o * self.sin_2k_alpha[1],
+                cos_theta_o * self.cos_2k_alpha[1] + sin_theta_o * self.sin_2k_alpha[1],
+            ),
+            1 => (
+                sin_theta_o * self.cos_2k_alpha[0] + cos_theta_o * self.sin_2k_alpha[0],
+                cos_theta_o * self.cos_2k_alpha[0] - sin_theta_o * self.sin_2k_alpha[0],
+            ),
+            2 => (
+                sin_theta_o * self.cos_2k_alpha[2] + cos_theta_o * self.sin_2k_alpha[2],
+                cos_theta_o * self.cos_2k_alpha[2] - sin_theta_o * self.sin_2k_alpha[2],
+            ),
+            _ => (sin_theta_o, cos_theta_o),
+        };
+
+        (sin_theta_op, cos_theta_op.abs())
+    }
+
+    // How much light leaves after each number of bounces
+    fn attenuation(&self, cos_theta_o: f64, h: f64) -> [[f64; 3]; P_MAX + 1] {
+        let sin_theta_o = safe_sqrt(1.0 - cos_theta_o * cos_theta_o);
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+        let etap = (self.eta * self.eta - sin_theta_o * sin_theta_o).sqrt() / cos_theta_o;
+        let sin_gamma_t = h / etap;
+        let cos_gamma_t = safe_sqrt(1.0 - sin_gamma_t * sin_gamma_t);
+        let transmittance = self
+            .sigma_a
+            .map(|sigma_a| (-sigma_a * (2.0 * cos_gamma_t / cos_theta_t)).exp());
+
+        let cos_gamma_o = safe_sqrt(1.0 - h * h);
+        let f = fresnel_dielectric(cos_theta_o * cos_gamma_o, self.eta);
+
+        let mut ap = [[0.0; 3]; P_MAX + 1];
+        ap[0] = [f; 3];
+        ap[1] = transmittance.map(|t| (1.0 - f) * (1.0 - f) * t);
+        for p in 2..P_MAX {
+            ap[p] = [0, 1, 2].map(|i| ap[p - 1][i] * transmittance[i] * f);
+        }
+        ap[P_MAX] = [0, 1, 2]
+            .map(|i| ap[P_MAX - 1][i] * f * transmittance[i] / (1.0 - transmittance[i] * f));
+
+        ap
+    }
+
+    // The chance of sampling each number of bounces, following how bright each is
+    fn attenuation_pdf(&self, cos_theta_o: f64, h: f64) -> [f64; P_MAX + 1] {
+        let ap = self.attenuation(cos_theta_o, h);
+        let luminance = ap.map(|a| 0.2126 * a[0] + 0.7152 * a[1] + 0.0722 * a[2]);
+        let total: f64 = luminance.iter().sum();
+
+        luminance.map(|y| y / total)
+    }
+
+    // The BSDF times the cosine, with both directions in the hair's frame, where x runs along
+    // the hair
+    fn f(&self, wo: &Vec3, wi: &Vec3, h: f64) -> [f64; 3] {
+        let (sin_theta_o, cos_theta_o, phi_o) = angles(wo);
+        let (sin_theta_i, cos_theta_i, phi_i) = angles(wi);
+
+        let gamma_o = safe_asin(h);
+        let gamma_t = self.gamma_t(sin_theta_o, cos_theta_o, h);
+        let ap = self.attenuation(cos_theta_o, h);
+        let phi = phi_i - phi_o;
+
+        let mut f = [0.0; 3];
+        for (p, ap) in ap.iter().enumerate().take(P_MAX) {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            let lobe = mp(
+                cos_theta_i,
+                cos_theta_op,
+                sin_theta_i,
+                sin_theta_op,
+                self.v[p],
+            ) * np(phi, p, self.s, gamma_o, gamma_t);
+            for i in 0..3 {
+                f[i] += lobe * ap[i];
+            }
+        }
+
+        let lobe = mp(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            self.v[P_MAX],
+        ) / (2.0 * PI);
+        for i in 0..3 {
+            f[i] += lobe * ap[P_MAX][i];
+        }
+
+        f
+    }
+
+    // Where light entering at h is refracted to inside the hair, seen end on
+    fn gamma_t(&self, sin_theta_o: f64, cos_theta_o: f64, h: f64) -> f64 {
+        let etap = (self.eta * self.eta - sin_theta_o * sin_theta_o).sqrt() / cos_theta_o;
+        safe_asin(h / etap)
+    }
+
+    // Picks how many bounces to follow, then samples the lengthways and around the hair lobes
+    // of that many, returning the direction and its pdf over all of them
+    fn sample(&self, wo: &Vec3, h: f64) -> (Vec3, f64) {
+        let mut rng = rand::thread_rng();
+        let (sin_theta_o, cos_theta_o, phi_o) = angles(wo);
+        let ap_pdf = self.attenuation_pdf(cos_theta_o, h);
+
+        let mut choice: f64 = rng.gen();
+        let mut p = 0;
+        while p < P_MAX && choice >= ap_pdf[p] {
+            choice -= ap_pdf[p];
+            p += 1;
+        }
+
+        let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+        let u: f64 = rng.gen::<f64>().max(1e-5);
+        let cos_theta = 1.0 + self.v[p] * (u + (1.0 - u) * (-2.0 / self.v[p]).exp()).ln();
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let cos_phi = (2.0 * PI * rng.gen::<f64>()).cos();
+        let sin_theta_i = -cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op;
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+
+        let gamma_o = safe_asin(h);
+        let gamma_t = self.gamma_t(sin_theta_o, cos_theta_o, h);
+        let dphi = if p < P_MAX {
+            phi_of(p, gamma_o, gamma_t) + sample_trimmed_logistic(rng.gen(), self.s, -PI, PI)
+        } else {
+            2.0 * PI * rng.gen::<f64>()
+        };
+
+        let phi_i = phi_o + dphi;
+        let wi = Vec3::new(
+            sin_theta_i,
+            cos_theta_i * phi_i.cos(),
+            cos_theta_i * phi_i.sin(),
+        );
+
+        let mut pdf = 0.0;
+        for (p, ap_pdf) in ap_pdf.iter().enumerate().take(P_MAX) {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            pdf += mp(
+                cos_theta_i,
+                cos_theta_op,
+                sin_theta_i,
+                sin_theta_op,
+                self.v[p],
+            ) * ap_pdf
+                * np(dphi, p, self.s, gamma_o, gamma_t);
+        }
+        pdf += mp(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            self.v[P_MAX],
+        ) * ap_pdf[P_MAX]
+            / (2.0 * PI);
+
+        (wi, pdf)
+    }
+}
+
+impl Material for Hair {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        _camera: &Camera,
+        _sampled_light_position: Option<Vec3>,
+    ) -> (Ray, Colour, bool) {
+        // anything but a curve gets a frame around its normal, and is lit as if it were
+        // seen straight down the middle
+        let (x, y) = match (hit_record.tangent, hit_record.bitangent) {
+            (Some(tangent), Some(bitangent)) => (tangent.unit(), bitangent.unit()),
+            _ => {
+                let y = hit_record.normal.cross(&ray_in.direction).unit();
+                (y.cross(&hit_record.normal), y)
+            }
+        };
+        let z = x.cross(&y);
+        let h = match (hit_record.tangent, hit_record.bitangent) {
+            (Some(_), Some(_)) => (2.0 * hit_record.v - 1.0).clamp(-1.0, 1.0),
+            _ => 0.0,
+        };
+
+        let to_local = |w: &Vec3| Vec3::new(w.dot(&x), w.dot(&y), w.dot(&z));
+        let wo = to_local(&-ray_in.direction.unit());
+
+        let (wi, pdf) = self.sample(&wo, h);
+        let f = self.f(&wo, &wi, h);
+        if pdf <= 0.0 || !pdf.is_finite() {
+            return (
+                hit_record.spawn_ray(ray_in.direction, ray_in.time),
+                Colour::new(0.0, 0.0, 0.0),
+                true,
+            );
+        }
+
+        let direction = wi.x * &x + wi.y * &y + wi.z * &z;
+        let scattered = hit_record.spawn_ray(direction, ray_in.time);
+
+        (
+            scattered,
+            Colour::new(f[0] / pdf, f[1] / pdf, f[2] / pdf),
+            true,
+        )
+    }
+}
+
+// Sine and cosine of the angle to the plane square to the hair, and the angle around it
+fn angles(w: &Vec3) -> (f64, f64, f64) {
+    let sin_theta = w.x.clamp(-1.0, 1.0);
+    (
+        sin_theta,
+        safe_sqrt(1.0 - sin_theta * sin_theta),
+        w.z.atan2(w.y),
+    )
+}
+
+// The longitudinal scattering lobe
+fn mp(cos_theta_i: f64, cos_theta_o: f64, sin_theta_i: f64, sin_theta_o: f64, v: f64) -> f64 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        // worked out in logs, as the terms overflow for smooth hair
+        (log_i0(a) - b - 1.0 / v + LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        (-b).exp() * i0(a) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+// The modified Bessel function of the first kind, from its series
+fn i0(x: f64) -> f64 {
+    let mut value = 0.0;
+    let mut x2i = 1.0;
+    let mut factorial: f64 = 1.0;
+    let mut four_i = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            factorial *= i as f64;
+        }
+        value += x2i / (four_i * factorial * factorial);
+        x2i *= x * x;
+        four_i *= 4.0;
+    }
+
+    value
+}
+
+fn log_i0(x: f64) -> f64 {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        i0(x).ln()
+    }
+}
+
+// The azimuthal scattering lobe, a logistic around where p bounces would send the light
+fn np(phi: f64, p: usize, s: f64, gamma_o: f64, gamma_t: f64) -> f64 {
+    let mut dphi = phi - phi_of(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2.0 * PI;
+    }
+    while dphi < -PI {
+        dphi += 2.0 * PI;
+    }
+
+    trimmed_logistic(dphi, s, -PI, PI)
+}
+
+// How far around the hair light is turned by p bounces inside it
+fn phi_of(p: usize, gamma_o: f64, gamma_t: f64) -> f64 {
+    let p = p as f64;
+    2.0 * p * gamma_t - 2.0 * gamma_o + p * PI
+}
+
+fn logistic(x: f64, s: f64) -> f64 {
+    let x = x.abs();
+    (-x / s).exp() / (s * (1.0 + (-x / s).exp()).powi(2))
+}
+
+fn logistic_cdf(x: f64, s: f64) -> f64 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+fn trimmed_logistic(x: f64, s: f64, a: f64, b: f64) -> f64 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: f64, s: f64, a: f64, b: f64) -> f64 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(a, s)) - 1.0).ln();
+    x.clamp(a, b)
+}
+
+// Fresnel reflectance of an unpolarised ray arriving from air
+fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let cos_theta_i = cos_theta_i.clamp(-1.0, 1.0);
+    let (eta_i, eta_t, cos_theta_i) = if cos_theta_i > 0.0 {
+        (1.0, eta, cos_theta_i)
+    } else {
+        (eta, 1.0, -cos_theta_i)
+    };
+
+    let sin_theta_i = safe_sqrt(1.0 - cos_theta_i * cos_theta_i);
+    let sin_theta_t = eta_i / eta_t * sin_theta_i;
+    if sin_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+
+    let parallel =
+        (eta_t * cos_theta_i - eta_i * cos_theta_t) / (eta_t * cos_theta_i + eta_i * cos_theta_t);
+    let perpendicular =
+        (eta_i * cos_theta_i - eta_t * cos_theta_t) / (eta_i * cos_theta_i + eta_t * cos_theta_t);
+
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.0).sqrt()
+}
+
+fn safe_asin(x: f64) -> f64 {
+    x.clamp(-1.0, 1.0).asin()
+}
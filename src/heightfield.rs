@@ -0,0 +1,278 @@
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::hittable::{HitRecord, Hittable, Pixel};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sphere::{bounding_sphere, Sphere};
+use crate::triangle::intersect_triangle;
+use crate::utils::gamma;
+use crate::vector::Vec3;
+
+use image::DynamicImage;
+
+// The distance, barycentrics and grid points of the triangle a ray hits
+type GridHit = (f64, [f64; 3], [(usize, usize); 3]);
+
+// Terrain from a greyscale image, one height per pixel, centred on the origin and rising up
+// the y axis. Each square of four neighbouring pixels is a grid cell cut into two triangles
+// along its diagonal, but the triangles are never stored: the ray walks the cells it passes
+// over in order and only those it could hit are intersected, so the first hit is the closest.
+#[derive(Debug)]
+pub struct Heightfield {
+    heights: Vec<f32>,
+    // the number of heights along x and along z
+    columns: usize,
+    rows: usize,
+    width: f64,
+    depth: f64,
+    bounding_box: AxisAlignedBoundingBox,
+    material: Box<dyn Material>,
+}
+
+impl Heightfield {
+    // Black is at y = 0 and white at the height scale. The top of the image is along -z, so
+    // the image lies on the ground the way it looks when seen from above.
+    pub fn new(
+        image: &DynamicImage,
+        width: f64,
+        depth: f64,
+        height_scale: f64,
+        material: Box<dyn Material>,
+    ) -> Heightfield {
+        let image = image.to_luma16();
+        let (columns, rows) = (image.width() as usize, image.height() as usize);
+        if columns < 2 || rows < 2 {
+            panic!(
+                "A heightfield needs an image at least 2 pixels across, not {}x{}",
+                columns, rows
+            );
+        }
+
+        let heights: Vec<f32> = image
+            .pixels()
+            .map(|pixel| (pixel[0] as f64 / 65535.0 * height_scale) as f32)
+            .collect();
+        let (lowest, highest) = heights
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &h| {
+                (lo.min(h), hi.max(h))
+            });
+
+        Heightfield {
+            heights,
+            columns,
+            rows,
+            width,
+            depth,
+            bounding_box: AxisAlignedBoundingBox::new(
+                Vec3::new(-width / 2.0, lowest as f64, -depth / 2.0),
+                Vec3::new(width / 2.0, highest as f64, depth / 2.0),
+            ),
+            material,
+        }
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (
+            self.width / (self.columns - 1) as f64,
+            self.depth / (self.rows - 1) as f64,
+        )
+    }
+
+    fn height(&self, column: usize, row: usize) -> f64 {
+        self.heights[row * self.columns + column] as f64
+    }
+
+    fn point(&self, column: usize, row: usize) -> Vec3 {
+        let (dx, dz) = self.cell_size();
+        Vec3::new(
+            -self.width / 2.0 + column as f64 * dx,
+            self.height(column, row),
+            -self.depth / 2.0 + row as f64 * dz,
+        )
+    }
+
+    // The slope from the neighbouring heights, one sided along the edges
+    fn normal(&self, column: usize, row: usize) -> Vec3 {
+        let (dx, dz) = self.cell_size();
+        let (left, right) = (column.saturating_sub(1), (column + 1).min(self.columns - 1));
+        let (back, front) = (row.saturating_sub(1), (row + 1).min(self.rows - 1));
+
+        let slope_x =
+            (self.height(right, row) - self.height(left, row)) / ((right - left) as f64 * dx);
+        let slope_z =
+            (self.height(column, front) - self.height(column, back)) / ((front - back) as f64 * dz);
+
+        Vec3::new(-slope_x, 1.0, -slope_z).unit()
+    }
+
+    fn uv(&self, column: usize, row: usize) -> (f64, f64) {
+        (
+            column as f64 / (self.columns - 1) as f64,
+            1.0 - row as f64 / (self.rows - 1) as f64,
+        )
+    }
+
+    // The two triangles of a cell, as the grid points at their corners, facing up
+    fn triangles(column: usize, row: usize) -> [[(usize, usize); 3]; 2] {
+        [
+            [(column, row), (column, row + 1), (column + 1, row + 1)],
+            [(column, row), (column + 1, row + 1), (column + 1, row)],
+        ]
+    }
+
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<GridHit> {
+        // clip the ray to the bounds. A NaN from an axis parallel ray is ignored by max and
+        // min, so it only clips on the other axes.
+        let (mut enter, mut exit) = (t_min, t_max);
+        for axis in 0..3 {
+            let origin = ray.origin.get(axis);
+            let inverse_direction = 1.0 / ray.direction.get(axis);
+            let near = (self.bounding_box.minimum.get(axis) - origin) * inverse_direction;
+            let far = (self.bounding_box.maximum.get(axis) - origin) * inverse_direction;
+            enter = enter.max(near.min(far));
+            exit = exit.min(near.max(far));
+        }
+        if enter > exit {
+            return None;
+        }
+
+        // Step from cell to cell with a 2D DDA. Along each axis the ray crosses into the next
+        // cell every `delta` in t, and next is where it next does.
+        let (dx, dz) = self.cell_size();
+        let start = ray.at(enter);
+        let last_column = self.columns as i64 - 2;
+        let last_row = self.rows as i64 - 2;
+        let mut column = (((start.x + self.width / 2.0) / dx).floor() as i64).clamp(0, last_column);
+        let mut row = (((start.z + self.depth / 2.0) / dz).floor() as i64).clamp(0, last_row);
+
+        let axis = |direction: f64, cell: i64, size: f64, origin: f64, half: f64| {
+            if direction == 0.0 {
+                return (0, f64::INFINITY, f64::INFINITY);
+            }
+            let step = if direction > 0.0 { 1 } else { -1 };
+            let boundary = (cell + (step + 1) / 2) as f64 * size - half;
+            (
+                step,
+                (boundary - origin) / direction,
+                size / direction.abs(),
+            )
+        };
+        let (step_x, mut next_x, delta_x) =
+            axis(ray.direction.x, column, dx, ray.origin.x, self.width / 2.0);
+        let (step_z, mut next_z, delta_z) =
+            axis(ray.direction.z, row, dz, ray.origin.z, self.depth / 2.0);
+
+        let mut cell_enter = enter;
+        loop {
+            let cell_exit = next_x.min(next_z).min(exit);
+            let (c, r) = (column as usize, row as usize);
+
+            // skip cells the ray passes wholly above or below
+            let corners = [(c, r), (c + 1, r), (c, r + 1), (c + 1, r + 1)];
+            let (lowest, highest) = corners
+                .iter()
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &(c, r)| {
+                    (lo.min(self.height(c, r)), hi.max(self.height(c, r)))
+                });
+            let y_enter = ray.origin.y + cell_enter * ray.direction.y;
+            let y_exit = ray.origin.y + cell_exit * ray.direction.y;
+            let slack = 1e-9 * (y_enter.abs() + y_exit.abs() + highest.abs() + lowest.abs());
+            if y_enter.max(y_exit) + slack >= lowest && y_enter.min(y_exit) - slack <= highest {
+                let mut closest: Option<GridHit> = None;
+                for triangle in Heightfield::triangles(c, r) {
+                    let points = triangle.map(|(c, r)| self.point(c, r));
+                    let t_end = closest.map_or(t_max, |(t, _, _)| t);
+                    if let Some((t, barycentrics)) = intersect_triangle(t_min, t_end, ray, points) {
+                        closest = Some((t, barycentrics, triangle));
+                    }
+                }
+
+                if closest.is_some() {
+                    return closest;
+                }
+            }
+
+            if cell_exit >= exit {
+                return None;
+            }
+
+            if next_x < next_z {
+                column += step_x;
+                if !(0..=last_column).contains(&column) {
+                    return None;
+                }
+                cell_enter = next_x;
+                next_x += delta_x;
+            } else {
+                row += step_z;
+                if !(0..=last_row).contains(&row) {
+                    return None;
+                }
+                cell_enter = next_z;
+                next_z += delta_z;
+            }
+        }
+    }
+}
+
+impl Hittable for Heightfield {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        _pixel: Option<Pixel>,
+        _first_ray: bool,
+    ) -> Option<HitRecord<'_>> {
+        let (t, [b0, b1, b2], triangle) = self.intersect(ray, t_min, t_max)?;
+        let [p0, p1, p2] = triangle.map(|(c, r)| self.point(c, r));
+
+        // the point from the barycentrics is much closer to the surface than ray.at(t)
+        let p = b0 * &p0 + b1 * &p1 + b2 * &p2;
+        let p_error = gamma(7) * &((b0 * &p0).abs() + (b1 * &p1).abs() + (b2 * &p2).abs());
+
+        let geometric_normal = (p1 - p0).cross(&(p2 - p0)).unit();
+        let front_face = ray.direction.dot(&geometric_normal) <= 0.0;
+
+        let [n0, n1, n2] = triangle.map(|(c, r)| self.normal(c, r));
+        let shading_normal = (b0 * &n0 + b1 * &n1 + b2 * &n2).unit();
+        let [uv0, uv1, uv2] = triangle.map(|(c, r)| self.uv(c, r));
+
+        // u runs along +x and v along -z, following the image
+        let tangent = Vec3::new(1.0, 0.0, 0.0);
+        let tangent = tangent - tangent.dot(&shading_normal) * &shading_normal;
+        let bitangent = Vec3::new(0.0, 0.0, -1.0);
+        let bitangent = bitangent - bitangent.dot(&shading_normal) * &shading_normal;
+
+        Some(HitRecord {
+            p,
+            t,
+            normal: if front_face {
+                shading_normal
+            } else {
+                -shading_normal
+            },
+            tangent: Some(tangent.unit()),
+            bitangent: Some(bitangent.unit()),
+            material: &self.material,
+            front_face,
+            u: b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+            v: b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+            geometric_normal,
+            p_error,
+            colour: None,
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.intersect(ray, 0.0, t_max).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        Some(self.bounding_box.clone())
+    }
+
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        bounding_sphere(&self.bounding_box)
+    }
+}
@@ -1,35 +1,59 @@
 use crate::aabb::{surrounding_box, AxisAlignedBoundingBox};
-use crate::ray::Ray;
+use crate::colour::Colour;
+use crate::material::Material;
+use crate::ray::{Ray, RayPacket};
+use crate::simd::LANES;
 use crate::sphere::Sphere;
+use crate::utils::distance;
 use crate::vector::Vec3;
-use crate::{camera::Camera, material::Material};
 
-use std::sync::{Arc, Mutex};
+use std::sync::Mutex;
 
 pub trait Hittable: Send + Sync + std::fmt::Debug {
     fn hit(
         &self,
         ray: &Ray,
-        camera: &Camera,
         t_min: f64,
         t_max: f64,
-        pixel: Option<(usize, usize)>,
-        zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
+        pixel: Option<Pixel>,
         first_ray: bool,
-    ) -> Option<HitRecord>;
+    ) -> Option<HitRecord<'_>>;
+
+    // Whether anything blocks the ray before t_max. Shadow and occlusion rays only need to
+    // know that something is in the way, so this can stop at the first hit it finds.
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool;
+
+    // Closest hits for a packet of camera rays, each searched for up to its own t_max.
+    // Only meshes gain from tracing the rays together, everything else traces them one
+    // at a time.
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: f64,
+        t_max: [f64; LANES],
+        pixels: [Option<Pixel>; LANES],
+        first_ray: bool,
+    ) -> [Option<HitRecord<'_>>; LANES] {
+        std::array::from_fn(|lane| {
+            if lane >= packet.len {
+                return None;
+            }
+
+            self.hit(
+                &packet.rays[lane],
+                t_min,
+                t_max[lane],
+                pixels[lane],
+                first_ray,
+            )
+        })
+    }
 
     fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
         None
     }
 
-    fn pdf_value(
-        &self,
-        _origin: &Vec3,
-        _v: &Vec3,
-        _camera: &Camera,
-        _pixel: Option<(usize, usize)>,
-        _zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
-    ) -> f64 {
+    fn pdf_value(&self, _origin: &Vec3, _v: &Vec3, _pixel: Option<Pixel>) -> f64 {
         0.0
     }
 
@@ -54,6 +78,16 @@ pub trait Hittable: Send + Sync + std::fmt::Debug {
     }
 }
 
+// The pixel a camera ray is traced for, with the zbuffer of distances to the closest hit
+// found so far for every pixel of the image. Only the first hit of a camera ray is depth
+// tested, so every other ray is traced without one.
+#[derive(Debug, Clone, Copy)]
+pub struct Pixel<'a> {
+    pub row: usize,
+    pub column: usize,
+    pub zbuffer: &'a Mutex<Vec<Vec<f64>>>,
+}
+
 #[derive(Debug)]
 pub struct HitRecord<'a> {
     pub p: Vec3,
@@ -65,9 +99,40 @@ pub struct HitRecord<'a> {
     pub front_face: bool,
     pub u: f64,
     pub v: f64,
+    // Normal of the surface itself rather than the shading normal, and a bound on the
+    // rounding error in p along each axis. Together they say how far a ray leaving the
+    // surface has to start from p to be sure it can't hit the surface again.
+    pub geometric_normal: Vec3,
+    pub p_error: Vec3,
+    // A colour the surface carries itself, like the colour of a point in a point cloud. The
+    // VertexColour texture reads it.
+    pub colour: Option<Colour>,
 }
 
 impl HitRecord<'_> {
+    // Starts a ray at the hit point, pushed past the rounding error in p towards the side
+    // of the surface the ray is heading to
+    pub fn spawn_ray(&self, direction: Vec3, time: f64) -> Ray {
+        let n = &self.geometric_normal;
+        let d = n.abs().dot(&self.p_error);
+        let mut offset = d * n;
+        if direction.dot(n) < 0.0 {
+            offset = -offset;
+        }
+
+        let mut origin = self.p + offset;
+        // round away from p so the offset can't be lost when it's added
+        for axis in 0..3 {
+            if offset.get(axis) > 0.0 {
+                origin.set(axis, origin.get(axis).next_up());
+            } else if offset.get(axis) < 0.0 {
+                origin.set(axis, origin.get(axis).next_down());
+            }
+        }
+
+        Ray::new(origin, direction, time)
+    }
+
     pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: &Vec3) {
         self.front_face = ray.direction.dot(outward_normal) < 0.0;
         self.normal = if self.front_face {
@@ -78,6 +143,24 @@ impl HitRecord<'_> {
     }
 }
 
+// Camera rays record the distance to their closest hit for each pixel, so a hit is
+// only accepted if it is nearer than anything already found for that pixel. Distances are
+// measured from the ray's own origin, which for a stereo eye is off the camera's centre.
+pub fn depth_test(p: &Vec3, origin: &Vec3, pixel: Option<Pixel>) -> bool {
+    if let Some(pixel) = pixel {
+        let z_distance = distance(p, origin);
+        let mut zbuff = pixel.zbuffer.lock().unwrap();
+
+        if z_distance < zbuff[pixel.row][pixel.column] {
+            zbuff[pixel.row][pixel.column] = z_distance;
+        } else {
+            return false;
+        }
+    }
+
+    true
+}
+
 #[derive(Debug)]
 pub struct HittableList {
     pub objects: Vec<Box<dyn Hittable>>,
@@ -107,13 +190,11 @@ impl Hittable for HittableList {
     fn hit(
         &self,
         ray: &Ray,
-        camera: &Camera,
         t_min: f64,
         t_max: f64,
-        pixel: Option<(usize, usize)>,
-        zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
+        pixel: Option<Pixel>,
         first_ray: bool,
-    ) -> Option<HitRecord> {
+    ) -> Option<HitRecord<'_>> {
         let mut hit_anything: Option<HitRecord> = None;
         let mut closest_so_far = t_max;
 
@@ -128,15 +209,7 @@ impl Hittable for HittableList {
                 }
             }
 
-            if let Some(hit_record) = object.hit(
-                ray,
-                camera,
-                t_min,
-                closest_so_far,
-                pixel,
-                Arc::clone(&zbuffer),
-                first_ray,
-            ) {
+            if let Some(hit_record) = object.hit(ray, t_min, closest_so_far, pixel, first_ray) {
                 closest_so_far = hit_record.t;
                 hit_anything = Some(hit_record);
             }
@@ -145,6 +218,18 @@ impl Hittable for HittableList {
         hit_anything
     }
 
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.objects.iter().any(|object| {
+            if let Some(bounding_box) = object.bounding_box() {
+                if let (false, _, _) = bounding_box.hit(ray, 0.0, t_max) {
+                    return false;
+                }
+            }
+
+            object.occluded(ray, t_max)
+        })
+    }
+
     fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
         if self.objects.is_empty() {
             return None;
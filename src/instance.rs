@@ -1,144 +1,231 @@
-use crate::hittable::{HitRecord, Hittable};
-use crate::ray::Ray;
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::hittable::{depth_test, HitRecord, Hittable, Pixel};
+use crate::ray::{Ray, RayPacket};
+use crate::simd::LANES;
+use crate::sphere::Sphere;
+use crate::transform::{AnimatedTransform, Matrix4};
 use crate::vector::Vec3;
-use std::sync::{Arc, Mutex};
 
+// Places an object in the world with an affine transform. Rays are taken into the
+// object's space, so the object's acceleration structure is built once in its own space
+// and can be placed anywhere.
 #[derive(Debug)]
-pub struct Translate {
+pub struct Transform {
     object: Box<dyn Hittable>,
-    offset: Vec3,
+    world_from_object: Matrix4,
+    object_from_world: Matrix4,
+    bounding_box: Option<AxisAlignedBoundingBox>,
 }
 
-#[allow(dead_code)]
-impl Translate {
-    pub fn new(object: Box<dyn Hittable>, offset: Vec3) -> Translate {
-        Translate { object, offset }
+impl Transform {
+    pub fn new(object: Box<dyn Hittable>, world_from_object: Matrix4) -> Transform {
+        let bounding_box = object
+            .bounding_box()
+            .map(|bounds| world_from_object.transform_bounding_box(&bounds));
+
+        Transform {
+            object,
+            world_from_object,
+            object_from_world: world_from_object.inverse(),
+            bounding_box,
+        }
     }
 }
 
-impl Hittable for Translate {
+impl Hittable for Transform {
     fn hit(
         &self,
-        ray: &crate::ray::Ray,
-        camera: &crate::camera::Camera,
+        ray: &Ray,
         t_min: f64,
         t_max: f64,
-        pixel: Option<(usize, usize)>,
-        zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
+        pixel: Option<Pixel>,
         first_ray: bool,
-    ) -> Option<HitRecord> {
-        let ray_moved = Ray::new(ray.origin - self.offset, ray.direction);
+    ) -> Option<HitRecord<'_>> {
+        let object_ray = transform_ray(&self.object_from_world, ray);
+
+        let hit = self
+            .object
+            .hit(&object_ray, t_min, t_max, None, first_ray)?;
+
+        transform_hit(
+            hit,
+            &self.world_from_object,
+            &self.object_from_world,
+            &ray.origin,
+            pixel,
+        )
+    }
 
-        if let Some(hit) = self.object.hit(
-            &ray_moved,
-            camera,
+    // The rays stay coherent in the object's space, so the packet is carried through
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: f64,
+        t_max: [f64; LANES],
+        pixels: [Option<Pixel>; LANES],
+        first_ray: bool,
+    ) -> [Option<HitRecord<'_>>; LANES] {
+        let object_rays: Vec<Ray> = packet.rays[..packet.len]
+            .iter()
+            .map(|ray| transform_ray(&self.object_from_world, ray))
+            .collect();
+
+        let mut hits = self.object.hit_packet(
+            &RayPacket::new(&object_rays),
             t_min,
             t_max,
-            pixel,
-            Arc::clone(&zbuffer),
+            [None; LANES],
             first_ray,
-        ) {
-            let mut hit_record = HitRecord {
-                p: hit.p + self.offset,
-                normal: hit.normal,
-                tangent: None,
-                bitangent: None,
-                material: hit.material,
-                t: hit.t,
-                front_face: hit.front_face,
-                u: hit.u,
-                v: hit.v,
-            };
-            hit_record.set_face_normal(&ray_moved, &hit.normal);
-
-            return Some(hit_record);
-        }
+        );
+
+        std::array::from_fn(|lane| {
+            transform_hit(
+                hits[lane].take()?,
+                &self.world_from_object,
+                &self.object_from_world,
+                &packet.rays[lane].origin,
+                pixels[lane],
+            )
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        let object_ray = transform_ray(&self.object_from_world, ray);
+
+        self.object.occluded(&object_ray, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        self.bounding_box.clone()
+    }
+
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        let mut sphere = self.object.get_light_sampler_sphere();
 
-        None
+        sphere.center = self.world_from_object.transform_point(&sphere.center);
+        sphere.radius *= self.world_from_object.max_scale();
+
+        sphere
     }
 
-    fn bounding_box(&self) -> Option<crate::aabb::AxisAlignedBoundingBox> {
-        None
+    fn should_render(&self) -> bool {
+        self.object.should_render()
     }
 }
 
+// The direction is not normalised so t is the same in both spaces
+fn transform_ray(matrix: &Matrix4, ray: &Ray) -> Ray {
+    Ray::new(
+        matrix.transform_point(&ray.origin),
+        matrix.transform_vector(&ray.direction),
+        ray.time,
+    )
+}
+
+// Takes a hit found in object space back into the world. The zbuffer holds world space
+// distances so the depth test happens here rather than in the object.
+fn transform_hit<'a>(
+    hit: HitRecord<'a>,
+    world_from_object: &Matrix4,
+    object_from_world: &Matrix4,
+    origin: &Vec3,
+    pixel: Option<Pixel>,
+) -> Option<HitRecord<'a>> {
+    let p = world_from_object.transform_point(&hit.p);
+    if !depth_test(&p, origin, pixel) {
+        return None;
+    }
+
+    Some(HitRecord {
+        p,
+        normal: object_from_world.transform_normal(&hit.normal).unit(),
+        tangent: hit
+            .tangent
+            .map(|tangent| world_from_object.transform_vector(&tangent).unit()),
+        bitangent: hit
+            .bitangent
+            .map(|bitangent| world_from_object.transform_vector(&bitangent).unit()),
+        geometric_normal: object_from_world
+            .transform_normal(&hit.geometric_normal)
+            .unit(),
+        p_error: world_from_object.transform_point_error(&hit.p, &hit.p_error),
+        ..hit
+    })
+}
+
+// Moves an object between a start and an end transform over the camera shutter interval.
+// Rays are taken into the object's space at their own time, so the object's acceleration
+// structure is built once and never needs to move.
 #[derive(Debug)]
-pub struct RotateY {
+pub struct Moving {
     object: Box<dyn Hittable>,
-    theta: f64,
+    motion: AnimatedTransform,
+    bounding_box: Option<AxisAlignedBoundingBox>,
 }
 
-#[allow(dead_code)]
-impl RotateY {
-    pub fn new(object: Box<dyn Hittable>, theta: f64) -> RotateY {
-        RotateY {
+impl Moving {
+    pub fn new(object: Box<dyn Hittable>, motion: AnimatedTransform) -> Moving {
+        let bounding_box = object
+            .bounding_box()
+            .map(|bounds| motion.motion_bounds(&bounds));
+
+        Moving {
             object,
-            theta: theta.to_radians(),
+            motion,
+            bounding_box,
         }
     }
 }
 
-impl Hittable for RotateY {
+impl Hittable for Moving {
     fn hit(
         &self,
         ray: &Ray,
-        camera: &crate::camera::Camera,
         t_min: f64,
         t_max: f64,
-        pixel: Option<(usize, usize)>,
-        zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
+        pixel: Option<Pixel>,
         first_ray: bool,
-    ) -> Option<HitRecord> {
-        let mut origin = ray.origin;
-        let mut direction = ray.direction;
+    ) -> Option<HitRecord<'_>> {
+        let motion = self.motion.interpolate(ray.time);
+        let world_from_object = motion.to_matrix();
+        let object_from_world = motion.inverse_matrix();
+        let object_ray = transform_ray(&object_from_world, ray);
+
+        let hit = self
+            .object
+            .hit(&object_ray, t_min, t_max, None, first_ray)?;
+
+        transform_hit(
+            hit,
+            &world_from_object,
+            &object_from_world,
+            &ray.origin,
+            pixel,
+        )
+    }
 
-        origin.x = self.theta.cos() * ray.origin.x - self.theta.sin() * ray.origin.z;
-        origin.z = self.theta.sin() * ray.origin.x + self.theta.cos() * ray.origin.z;
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        let object_from_world = self.motion.interpolate(ray.time).inverse_matrix();
+        let object_ray = transform_ray(&object_from_world, ray);
 
-        direction.x = self.theta.cos() * ray.direction.x - self.theta.sin() * ray.direction.z;
-        direction.z = self.theta.sin() * ray.direction.x + self.theta.cos() * ray.direction.z;
+        self.object.occluded(&object_ray, t_max)
+    }
 
-        let ray_rotated = Ray::new(origin, direction);
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        self.bounding_box.clone()
+    }
 
-        if let Some(hit) = self.object.hit(
-            &ray_rotated,
-            camera,
-            t_min,
-            t_max,
-            pixel,
-            Arc::clone(&zbuffer),
-            first_ray,
-        ) {
-            let mut p = hit.p;
-
-            p.x = self.theta.cos() * hit.p.x + self.theta.sin() * hit.p.z;
-            p.z = -self.theta.sin() * hit.p.x + self.theta.cos() * hit.p.z;
-
-            let mut normal = hit.normal;
-
-            normal.x = self.theta.cos() * hit.normal.x + self.theta.sin() * hit.normal.z;
-            normal.z = -self.theta.sin() * hit.normal.x + self.theta.cos() * hit.normal.z;
-
-            let mut hit_record = HitRecord {
-                p,
-                normal,
-                tangent: None,
-                bitangent: None,
-                material: hit.material,
-                t: hit.t,
-                front_face: hit.front_face,
-                u: hit.u,
-                v: hit.v,
-            };
-            hit_record.set_face_normal(&ray_rotated, &normal);
-
-            return Some(hit_record);
-        }
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        let mut sphere = self.object.get_light_sampler_sphere();
+        let start = self.motion.matrix_at(f64::NEG_INFINITY);
+
+        sphere.center = start.transform_point(&sphere.center);
+        sphere.radius *= start.max_scale();
 
-        None
+        sphere
     }
 
-    fn bounding_box(&self) -> Option<crate::aabb::AxisAlignedBoundingBox> {
-        None
+    fn should_render(&self) -> bool {
+        self.object.should_render()
     }
 }
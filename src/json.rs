@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SkyboxJSON {
@@ -12,15 +13,22 @@ pub enum TextureJSON {
         colour: [f64; 3],
         normal_path: Option<String>,
         normal_scale: Option<f64>,
+        bump_path: Option<String>,
+        bump_strength: Option<f64>,
     },
     ImageTexture {
         image_path: String,
         alpha_path: Option<String>,
         normal_path: Option<String>,
         normal_scale: Option<f64>,
+        bump_path: Option<String>,
+        bump_strength: Option<f64>,
         is_light: bool,
         scale: f64,
     },
+    VertexColour {
+        colour: [f64; 3],
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -61,25 +69,274 @@ pub enum MaterialJSON {
         include_diffuse: Option<bool>,
         fuzziness: f64,
     },
+    Hair {
+        sigma_a: Option<[f64; 3]>,
+        colour: Option<[f64; 3]>,
+        eumelanin: Option<f64>,
+        pheomelanin: Option<f64>,
+        eta: Option<f64>,
+        beta_m: Option<f64>,
+        beta_n: Option<f64>,
+        alpha: Option<f64>,
+    },
+    Cutout {
+        material: Box<MaterialJSON>,
+        alpha_path: Option<String>,
+        opacity: Option<f64>,
+        cutoff: Option<f64>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TransformJSON {
+    pub translate: Option<[f64; 3]>,
+    pub rotate: Option<[f64; 3]>,
+    pub quaternion: Option<[f64; 4]>,
+    pub scale: Option<[f64; 3]>,
+    pub pivot: Option<[f64; 3]>,
+    pub matrix: Option<[[f64; 4]; 4]>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DisplacementJSON {
+    pub height_path: String,
+    pub scale: f64,
+    pub midlevel: Option<f64>,
+    pub edge_length: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MotionJSON {
+    pub start: TransformJSON,
+    pub end: TransformJSON,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum AcceleratorJSON {
+    KDTree,
+    BVH,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum VertexPrecisionJSON {
+    F32,
+    F64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MeshJSON {
+    pub obj_path: String,
+    pub shade_smooth: Option<bool>,
+    pub accelerator: Option<AcceleratorJSON>,
+    pub vertex_precision: Option<VertexPrecisionJSON>,
+    pub subdivision: Option<u32>,
+    pub displacement: Option<DisplacementJSON>,
+    pub material: Option<MaterialJSON>,
+    pub materials: Option<BTreeMap<String, MaterialJSON>>,
+}
+
+// A glTF or GLB file's whole scene, with its cameras and lights
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfJSON {
+    pub gltf_path: String,
+    pub materials: Option<BTreeMap<String, MaterialJSON>>,
+    pub accelerator: Option<AcceleratorJSON>,
+    pub vertex_precision: Option<VertexPrecisionJSON>,
+    pub light_radius: Option<f64>,
+    pub should_render: Option<bool>,
+    pub transform: Option<TransformJSON>,
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Serialize, Deserialize, Debug)]
 pub enum HittablesJSON {
     Model {
         obj_path: String,
-        material: MaterialJSON,
+        material: Option<MaterialJSON>,
+        materials: Option<BTreeMap<String, MaterialJSON>>,
         shade_smooth: Option<bool>,
+        accelerator: Option<AcceleratorJSON>,
+        vertex_precision: Option<VertexPrecisionJSON>,
+        subdivision: Option<u32>,
+        displacement: Option<DisplacementJSON>,
+        should_render: Option<bool>,
+        transform: Option<TransformJSON>,
+        motion: Option<MotionJSON>,
+    },
+    Instance {
+        mesh: String,
+        material: Option<MaterialJSON>,
+        materials: Option<BTreeMap<String, MaterialJSON>>,
         should_render: Option<bool>,
+        transform: Option<TransformJSON>,
+        motion: Option<MotionJSON>,
     },
+    Gltf(GltfJSON),
     Volume {
         box_min: [f64; 3],
         box_max: [f64; 3],
         colour: [f64; 3],
         material: MaterialJSON,
         density: f64,
+        transform: Option<TransformJSON>,
+    },
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: MaterialJSON,
+        transform: Option<TransformJSON>,
+    },
+    Plane {
+        material: MaterialJSON,
+        transform: Option<TransformJSON>,
+    },
+    Rectangle {
+        width: f64,
+        depth: f64,
+        material: MaterialJSON,
+        transform: Option<TransformJSON>,
+    },
+    Box {
+        box_min: [f64; 3],
+        box_max: [f64; 3],
+        material: MaterialJSON,
+        transform: Option<TransformJSON>,
+    },
+    Disk {
+        radius: f64,
+        inner_radius: Option<f64>,
+        material: MaterialJSON,
+        transform: Option<TransformJSON>,
+    },
+    Cylinder {
+        radius: f64,
+        height: f64,
+        capped: Option<bool>,
+        material: MaterialJSON,
+        transform: Option<TransformJSON>,
+    },
+    Cone {
+        radius: f64,
+        height: f64,
+        capped: Option<bool>,
+        material: MaterialJSON,
+        transform: Option<TransformJSON>,
+    },
+    Torus {
+        major_radius: f64,
+        minor_radius: f64,
+        material: MaterialJSON,
+        transform: Option<TransformJSON>,
+    },
+    Sdf {
+        shape: SdfJSON,
+        material: MaterialJSON,
+        transform: Option<TransformJSON>,
+    },
+    Csg {
+        operation: CsgOperationJSON,
+        left: Box<HittablesJSON>,
+        right: Box<HittablesJSON>,
+        transform: Option<TransformJSON>,
+    },
+    Heightfield {
+        height_path: String,
+        width: f64,
+        depth: f64,
+        height_scale: f64,
+        material: MaterialJSON,
+        transform: Option<TransformJSON>,
+    },
+    PointCloud {
+        points_path: String,
+        radius: Option<f64>,
+        material: MaterialJSON,
+        transform: Option<TransformJSON>,
+    },
+    Curves {
+        curves_path: String,
+        curve_type: Option<CurveTypeJSON>,
+        material: MaterialJSON,
+        transform: Option<TransformJSON>,
     },
 }
 
+// Distances are in the shape's own space and angles in degrees
+#[derive(Serialize, Deserialize, Debug)]
+pub enum SdfJSON {
+    Sphere {
+        radius: f64,
+    },
+    Box {
+        size: [f64; 3],
+    },
+    Torus {
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    Capsule {
+        start: [f64; 3],
+        end: [f64; 3],
+        radius: f64,
+    },
+    Translate {
+        shape: Box<SdfJSON>,
+        offset: [f64; 3],
+    },
+    SmoothUnion {
+        left: Box<SdfJSON>,
+        right: Box<SdfJSON>,
+        smoothness: f64,
+    },
+    SmoothSubtraction {
+        left: Box<SdfJSON>,
+        right: Box<SdfJSON>,
+        smoothness: f64,
+    },
+    Twist {
+        shape: Box<SdfJSON>,
+        degrees_per_unit: f64,
+    },
+    Repeat {
+        shape: Box<SdfJSON>,
+        spacing: [f64; 3],
+        copies: [u32; 3],
+    },
+    Noise {
+        shape: Box<SdfJSON>,
+        amplitude: f64,
+        frequency: f64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum CsgOperationJSON {
+    Union,
+    Intersection,
+    Difference,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum CurveTypeJSON {
+    Cylinder,
+    Ribbon,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum StereoLayoutJSON {
+    SideBySide,
+    OverUnder,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StereoJSON {
+    pub interocular_distance: f64,
+    pub convergence_distance: Option<f64>,
+    pub layout: StereoLayoutJSON,
+    pub omni_directional: Option<bool>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CameraJSON {
     pub aspect_ratio: f64,
@@ -89,6 +346,9 @@ pub struct CameraJSON {
     pub v_up: [f64; 3],
     pub dist_to_focus: f64,
     pub aperture: f64,
+    pub stereo: Option<StereoJSON>,
+    pub shutter_open: Option<f64>,
+    pub shutter_close: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -96,12 +356,50 @@ pub struct RenderSettingsJSON {
     pub image_width: u32,
     pub image_height: u32,
     pub samples: u32,
+    pub accelerator: Option<AcceleratorJSON>,
+    pub vertex_precision: Option<VertexPrecisionJSON>,
+    pub ambient_occlusion: Option<AmbientOcclusionJSON>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AmbientOcclusionJSON {
+    pub distance: f64,
+    pub samples: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum InterpolationJSON {
+    Linear,
+    Bezier,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyframeJSON {
+    pub frame: f64,
+    pub value: Vec<f64>,
+    pub interpolation: Option<InterpolationJSON>,
+    pub handle_in: Option<Vec<f64>>,
+    pub handle_out: Option<Vec<f64>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrackJSON {
+    pub target: String,
+    pub keyframes: Vec<KeyframeJSON>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AnimationJSON {
+    pub tracks: Vec<TrackJSON>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SceneJSON {
     pub render_settings: RenderSettingsJSON,
-    pub camera: CameraJSON,
+    pub camera: Option<CameraJSON>,
+    pub cameras: Option<BTreeMap<String, CameraJSON>>,
+    pub meshes: Option<BTreeMap<String, MeshJSON>>,
     pub models: Vec<HittablesJSON>,
     pub skybox: Option<SkyboxJSON>,
+    pub animation: Option<AnimationJSON>,
 }
@@ -1,56 +1,21 @@
 use crate::aabb::AxisAlignedBoundingBox;
-use crate::camera::Camera;
-use crate::ray::Ray;
-use crate::utils::distance;
+use crate::ray::{Ray, RayPacket};
+use crate::simd::{F64x4, LANES};
+use crate::triangle::{
+    triangle_hit, triangle_intersection, triangle_intersection_packet, triangle_occludes,
+    TriangleMesh,
+};
 use crate::vector::Vec3;
 
-use obj::{Obj, TexturedVertex};
-use std::f64::INFINITY;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UVCoord {
     pub u: f64,
     pub v: f64,
 }
 
-#[derive(Debug, Clone)]
-pub struct Face {
-    points: [Vec3; 3],
-    text_coords: [UVCoord; 3],
-    normals: [Vec3; 3],
-    bounds: AxisAlignedBoundingBox,
-    shade_smooth: bool,
-}
-
-impl Face {
-    pub fn new(
-        points: [Vec3; 3],
-        text_coords: [UVCoord; 3],
-        normals: [Vec3; 3],
-        shade_smooth: bool,
-    ) -> Face {
-        let minimum = Vec3::new(
-            points[0].x.min(points[1].x).min(points[2].x),
-            points[0].y.min(points[1].y).min(points[2].y),
-            points[0].z.min(points[1].z).min(points[2].z),
-        );
-        let maximum = Vec3::new(
-            points[0].x.max(points[1].x).max(points[2].x),
-            points[0].y.max(points[1].y).max(points[2].y),
-            points[0].z.max(points[1].z).max(points[2].z),
-        );
-        let bounds = AxisAlignedBoundingBox::new(minimum, maximum);
-        Face {
-            points,
-            text_coords,
-            normals,
-            bounds,
-            shade_smooth,
-        }
-    }
-}
-
 pub struct KDTreeHitRecord {
     pub p: Vec3,
     pub t: f64,
@@ -59,17 +24,92 @@ pub struct KDTreeHitRecord {
     pub bitangent: Vec3,
     pub front_face: bool,
     pub text_coord: UVCoord,
+    pub geometric_normal: Vec3,
+    pub p_error: Vec3,
+    pub triangle: usize,
 }
 
-#[derive(Clone)]
+// Nodes with more triangles than this look for a split among binned edges, smaller nodes
+// sort their edges to find the exact best split
+const EXACT_SWEEP_LIMIT: usize = 1024;
+const SPLIT_BINS: usize = 64;
+// Subtrees with more triangles than this are built on their own rayon task
+const PARALLEL_BUILD_LIMIT: usize = 4096;
+const MAX_TRIANGLES_IN_LEAF: usize = 250;
+const ISECT_COST: f64 = 20.0;
+const TRAVERSAL_COST: f64 = 1.0;
+const EMPTY_BONUS: f64 = 0.5;
+// Deepest a tree is ever built, which bounds the traversal stack
+const MAX_DEPTH: usize = 64;
+
+// The low two bits of a node's flags hold its split axis, or LEAF_FLAG for a leaf
+const LEAF_FLAG: u32 = 3;
+const MAX_FLAGS_VALUE: usize = (u32::MAX >> 2) as usize;
+
+// An 8 byte node. Interior nodes hold their split position and the index of the child
+// above the split, the child below the split is always the next node. Leaves hold where
+// their triangle indices start and how many there are.
+#[derive(Copy, Clone, Serialize, Deserialize)]
+struct KDTreeNode {
+    data: u32,
+    flags: u32,
+}
+
+impl KDTreeNode {
+    fn interior(axis: usize, split: f32, above_child: usize) -> KDTreeNode {
+        if above_child > MAX_FLAGS_VALUE {
+            panic!("Too many KD-tree nodes: {}", above_child);
+        }
+
+        KDTreeNode {
+            data: split.to_bits(),
+            flags: axis as u32 | (above_child as u32) << 2,
+        }
+    }
+
+    fn leaf(first_triangle: usize, triangle_count: usize) -> KDTreeNode {
+        if first_triangle > u32::MAX as usize || triangle_count > MAX_FLAGS_VALUE {
+            panic!(
+                "Too many triangles in KD-tree leaves: {}",
+                first_triangle + triangle_count
+            );
+        }
+
+        KDTreeNode {
+            data: first_triangle as u32,
+            flags: LEAF_FLAG | (triangle_count as u32) << 2,
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.flags & 3 == LEAF_FLAG
+    }
+
+    fn split_axis(&self) -> usize {
+        (self.flags & 3) as usize
+    }
+
+    fn split(&self) -> f64 {
+        f32::from_bits(self.data).into()
+    }
+
+    fn above_child(&self) -> usize {
+        (self.flags >> 2) as usize
+    }
+
+    fn triangles(&self) -> std::ops::Range<usize> {
+        let first = self.data as usize;
+        first..first + (self.flags >> 2) as usize
+    }
+}
+
+// The tree is stored depth first in one contiguous array, with the triangles of every
+// leaf listed by index in another
+#[derive(Serialize, Deserialize)]
 pub struct KDTree {
-    pub split_axis: usize,
-    pub left_child: Option<Box<KDTree>>,
-    pub right_child: Option<Box<KDTree>>,
-    pub split_distance: f64,
-    pub is_leaf: bool,
-    pub faces: Option<Vec<Box<Face>>>,
-    pub bounds: AxisAlignedBoundingBox,
+    nodes: Vec<KDTreeNode>,
+    triangle_indices: Vec<u32>,
+    bounds: AxisAlignedBoundingBox,
 }
 
 impl fmt::Debug for KDTree {
@@ -87,572 +127,559 @@ enum EdgeType {
 #[derive(Clone)]
 struct BoundEdge {
     t: f64,
-    triangle_num: usize,
     edge_type: EdgeType,
 }
 
 impl BoundEdge {
-    fn new(t: f64, triangle_num: usize, starting: bool) -> BoundEdge {
+    fn new(t: f64, starting: bool) -> BoundEdge {
         let edge_type = match starting {
             true => EdgeType::Start,
             false => EdgeType::End,
         };
-        BoundEdge {
-            t,
-            triangle_num,
-            edge_type,
-        }
+        BoundEdge { t, edge_type }
     }
 }
 
+// Tree as it is built, before being flattened into nodes
+enum BuildNode {
+    Interior {
+        axis: usize,
+        split: f32,
+        below: Box<BuildNode>,
+        above: Box<BuildNode>,
+    },
+    Leaf(Vec<u32>),
+}
+
 impl KDTree {
     pub fn traverse(
         &self,
+        triangles: &TriangleMesh,
         ray: &Ray,
-        camera: &Camera,
         t_start: f64,
         t_end: f64,
     ) -> Option<KDTreeHitRecord> {
-        let ray_origin = [ray.origin.x, ray.origin.y, ray.origin.z];
-        let ray_dir = [ray.direction.x, ray.direction.y, ray.direction.z];
-        let mut d_min = f64::INFINITY;
-
-        if let (false, _, _) = self.bounds.hit(ray, 0.001, INFINITY) {
+        let (hit, box_t_min, box_t_max) = self.bounds.hit(ray, t_start, t_end);
+        if !hit {
+            return None;
+        }
+        let mut t_min = box_t_min.max(t_start);
+        let mut t_max = box_t_max.min(t_end);
+        if t_min > t_max {
             return None;
         }
 
-        if self.is_leaf {
-            let mut potential_hit: Option<KDTreeHitRecord> = None;
-            if let Some(faces) = &self.faces {
-                let mut closest_t_so_far = t_end;
-                for triangle in faces {
-                    if let (Some(hit), distance_min) = triangle_intersection(
-                        t_start,
-                        closest_t_so_far,
-                        ray,
-                        triangle,
-                        camera,
-                        d_min,
-                    ) {
-                        closest_t_so_far = hit.t;
-                        potential_hit = Some(hit);
-                        d_min = distance_min;
-                    }
-                }
-            }
+        let mut potential_hit: Option<KDTreeHitRecord> = None;
+        let mut closest_t_so_far = t_end;
 
-            return potential_hit;
-        }
+        // far children still to visit, along with the part of the ray inside them
+        let mut stack = [(0, 0.0, 0.0); MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut node_index = 0;
 
-        let t_split =
-            (self.split_distance - ray_origin[self.split_axis]) / ray_dir[self.split_axis];
-        let flip_front_and_back = ray_dir[self.split_axis].is_sign_negative();
-        if t_split <= t_start {
-            if let (Some(chosen_child), true) = (&self.left_child, flip_front_and_back) {
-                return chosen_child.traverse(ray, camera, t_start, t_end);
-            } else if let (Some(chosen_child), false) = (&self.right_child, flip_front_and_back) {
-                return chosen_child.traverse(ray, camera, t_start, t_end);
+        loop {
+            if closest_t_so_far < t_min {
+                break;
             }
-        } else if t_split >= t_end {
-            if let (Some(chosen_child), true) = (&self.right_child, flip_front_and_back) {
-                return chosen_child.traverse(ray, camera, t_start, t_end);
-            } else if let (Some(chosen_child), false) = (&self.left_child, flip_front_and_back) {
-                return chosen_child.traverse(ray, camera, t_start, t_end);
-            }
-        } else if let (Some(right_child), true) = (&self.right_child, flip_front_and_back) {
-            if let Some(KDTreeHitRecord {
-                p,
-                t: t_hit,
-                normal,
-                tangent,
-                bitangent,
-                front_face,
-                text_coord,
-            }) = right_child.traverse(ray, camera, t_start, t_split)
-            {
-                if t_hit < t_split {
-                    return Some(KDTreeHitRecord {
-                        p,
-                        t: t_hit,
-                        normal,
-                        tangent,
-                        bitangent,
-                        front_face,
-                        text_coord,
-                    });
+
+            let node = &self.nodes[node_index];
+            if !node.is_leaf() {
+                let axis = node.split_axis();
+                let split = node.split();
+                let origin = ray.origin.get(axis);
+                let t_split = (split - origin) * ray.inverse_direction.get(axis);
+
+                let below_first =
+                    origin < split || (origin == split && ray.direction.get(axis) <= 0.0);
+                let (first_child, second_child) = if below_first {
+                    (node_index + 1, node.above_child())
+                } else {
+                    (node.above_child(), node_index + 1)
+                };
+
+                if t_split > t_max || t_split <= 0.0 {
+                    node_index = first_child;
+                } else if t_split < t_min {
+                    node_index = second_child;
+                } else {
+                    stack[stack_len] = (second_child, t_split, t_max);
+                    stack_len += 1;
+                    node_index = first_child;
+                    t_max = t_split;
                 }
+                continue;
             }
 
-            if let Some(left_child) = &self.left_child {
-                return left_child.traverse(ray, camera, t_split, t_end);
-            }
-        } else if let (Some(left_child), false) = (&self.left_child, flip_front_and_back) {
-            if let Some(KDTreeHitRecord {
-                p,
-                t: t_hit,
-                normal,
-                tangent,
-                bitangent,
-                front_face,
-                text_coord,
-            }) = left_child.traverse(ray, camera, t_start, t_split)
-            {
-                if t_hit < t_split {
-                    return Some(KDTreeHitRecord {
-                        p,
-                        t: t_hit,
-                        normal,
-                        tangent,
-                        bitangent,
-                        front_face,
-                        text_coord,
-                    });
+            for &triangle in &self.triangle_indices[node.triangles()] {
+                if let Some(hit) = triangle_intersection(
+                    t_start,
+                    closest_t_so_far,
+                    ray,
+                    triangles,
+                    triangle as usize,
+                ) {
+                    closest_t_so_far = hit.t;
+                    potential_hit = Some(hit);
                 }
             }
 
-            if let Some(right_child) = &self.right_child {
-                return right_child.traverse(ray, camera, t_split, t_end);
+            if stack_len == 0 {
+                break;
             }
+            stack_len -= 1;
+            (node_index, t_min, t_max) = stack[stack_len];
         }
 
-        None
+        potential_hit
     }
 
-    //build KDTree using Surface Area Heuristic
-    pub fn build_sah(
-        triangle_list: &mut [Box<Face>],
-        triangle_list_len: usize,
-        depth: u32,
-        bounds: AxisAlignedBoundingBox,
-        bad_refines: u32,
-    ) -> Option<Box<KDTree>> {
-        if triangle_list_len <= 250 || depth == 0 {
-            return Some(Box::new(KDTree {
-                split_axis: 0,
-                left_child: None,
-                right_child: None,
-                split_distance: 0.0,
-                is_leaf: true,
-                faces: Some(triangle_list.to_vec()),
-                bounds,
-            }));
-        };
-
-        let mut best_axis = -1;
-        let mut best_offset = -1;
-        let mut best_cost = INFINITY;
-        let isect_cost = 20.0;
-        let traversal_cost = 1.0;
-        let old_cost = triangle_list_len as f64 * isect_cost;
-        let total_sa = bounds.surface_area();
-        let inv_total_sa = 1.0 / total_sa;
-        let d = bounds.maximum - bounds.minimum;
-        let mut axis = bounds.maximum_extent() as usize;
-        let mut retries = 0;
-        let empty_bonus = 0.5;
-        let mut edges: [Vec<BoundEdge>; 3] = [
-            vec![BoundEdge::new(0.0, 0, true); 2_usize * triangle_list_len],
-            vec![BoundEdge::new(0.0, 0, true); 2_usize * triangle_list_len],
-            vec![BoundEdge::new(0.0, 0, true); 2_usize * triangle_list_len],
-        ];
-
-        while best_axis == -1 && retries < 2 {
-            for (i, triangle) in triangle_list.iter().enumerate() {
-                let t_bounds = &triangle.bounds;
-                edges[axis][2 * i] = BoundEdge::new(t_bounds.minimum.get(axis), i, true);
-                edges[axis][2 * i + 1] = BoundEdge::new(t_bounds.maximum.get(axis), i, false);
-            }
-
-            edges[axis].sort_by(|edge0, edge1| {
-                if edge0.t == edge1.t {
-                    return (edge0.edge_type as u32).cmp(&(edge1.edge_type as u32));
-                }
-                edge0.t.partial_cmp(&edge1.t).unwrap()
+    // Walks a packet of rays through the tree together, visiting a node if any ray in
+    // the packet needs it. Each lane keeps its own interval, so a ray only tests the
+    // triangles it would have reached on its own. Rays of an incoherent packet are
+    // traced one at a time.
+    pub fn traverse_packet(
+        &self,
+        triangles: &TriangleMesh,
+        packet: &RayPacket,
+        t_start: f64,
+        t_end: [f64; LANES],
+    ) -> [Option<KDTreeHitRecord>; LANES] {
+        if !packet.coherent {
+            return std::array::from_fn(|lane| match lane < packet.len {
+                true => self.traverse(triangles, &packet.rays[lane], t_start, t_end[lane]),
+                false => None,
             });
+        }
 
-            let mut number_below = 0;
-            let mut number_above = triangle_list_len as u32;
-            for i in 0..(2_usize * triangle_list_len) {
-                match edges[axis][i].edge_type {
-                    EdgeType::Start => {}
-                    EdgeType::End => {
-                        number_above -= 1;
+        // lanes that miss the tree get an empty interval
+        let mut t_min = [f64::INFINITY; LANES];
+        let mut t_max = [-f64::INFINITY; LANES];
+        for lane in 0..packet.len {
+            let (hit, box_t_min, box_t_max) =
+                self.bounds.hit(&packet.rays[lane], t_start, t_end[lane]);
+            if hit {
+                t_min[lane] = box_t_min.max(t_start);
+                t_max[lane] = box_t_max.min(t_end[lane]);
+            }
+        }
+        let mut t_min = F64x4::new(t_min);
+        let mut t_max = F64x4::new(t_max);
+        let empty = F64x4::splat(f64::INFINITY);
+
+        let mut closest_t_so_far = F64x4::new(t_end);
+        // the triangle each lane hit last, with the barycentrics of the hit
+        let mut closest_triangle = [None; LANES];
+
+        let mut stack = [(0, empty, empty); MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut node_index = 0;
+
+        loop {
+            let active = t_min.le(t_max) & t_min.le(closest_t_so_far) & packet.active();
+
+            let node = &self.nodes[node_index];
+            if active.any() && !node.is_leaf() {
+                let axis = node.split_axis();
+                let t_split = (F64x4::splat(node.split()) - packet.origin[axis])
+                    * packet.inverse_direction[axis];
+
+                // every ray crosses the split in the same direction, so the child they
+                // enter first is the same for all of them
+                let (near_child, far_child) = if packet.sign[axis] == 0 {
+                    (node_index + 1, node.above_child())
+                } else {
+                    (node.above_child(), node_index + 1)
+                };
+
+                // a NaN split distance, from a ray starting on the split plane, visits
+                // both children like the single ray walk does
+                let needs_near = active & !t_split.le(t_min);
+                let needs_far = active & !t_split.ge(t_max);
+                let near_t_min = F64x4::select(needs_near, t_min, empty);
+                let near_t_max = t_split.min(t_max);
+                let far_t_min = F64x4::select(needs_far, t_split.max(t_min), empty);
+                let far_t_max = t_max;
+
+                if needs_near.any() {
+                    if needs_far.any() {
+                        stack[stack_len] = (far_child, far_t_min, far_t_max);
+                        stack_len += 1;
                     }
+                    node_index = near_child;
+                    (t_min, t_max) = (near_t_min, near_t_max);
+                    continue;
                 }
-
-                let edge_t = edges[axis][i].t;
-                if edge_t > bounds.minimum.get(axis) && edge_t < bounds.maximum.get(axis) {
-                    let other_axis0 = (axis + 1) % 3;
-                    let other_axis1 = (axis + 2) % 3;
-                    let below_sa = 2.0
-                        * (d.get(other_axis0) * d.get(other_axis1)
-                            + (edge_t - bounds.minimum.get(axis))
-                                * (d.get(other_axis0) + d.get(other_axis1)));
-                    let above_sa = 2.0
-                        * (d.get(other_axis0) * d.get(other_axis1)
-                            + (bounds.maximum.get(axis) - edge_t)
-                                * (d.get(other_axis0) + d.get(other_axis1)));
-
-                    let p_below = below_sa * inv_total_sa;
-                    let p_above = above_sa * inv_total_sa;
-                    let eb = if number_above == 0 || number_below == 0 {
-                        empty_bonus
-                    } else {
-                        0.0
-                    };
-                    let cost = traversal_cost
-                        + isect_cost
-                            * (1.0 - eb)
-                            * (p_below * number_below as f64 + p_above * number_above as f64);
-
-                    if cost < best_cost {
-                        best_cost = cost;
-                        best_axis = axis as i32;
-                        best_offset = i as i32;
+                if needs_far.any() {
+                    node_index = far_child;
+                    (t_min, t_max) = (far_t_min, far_t_max);
+                    continue;
+                }
+            } else if active.any() {
+                for &triangle in &self.triangle_indices[node.triangles()] {
+                    let (hit, t, barycentrics) = triangle_intersection_packet(
+                        t_start,
+                        closest_t_so_far,
+                        packet,
+                        triangles,
+                        triangle as usize,
+                    );
+                    if hit.any() {
+                        closest_t_so_far = F64x4::select(hit, t, closest_t_so_far);
+                        let barycentrics = barycentrics.map(F64x4::to_array);
+                        for (lane, closest) in closest_triangle.iter_mut().enumerate() {
+                            if hit.lane(lane) {
+                                let barycentrics = barycentrics.map(|b| b[lane]);
+                                *closest = Some((triangle as usize, barycentrics));
+                            }
+                        }
                     }
                 }
+            }
 
-                match edges[axis][i].edge_type {
-                    EdgeType::Start => number_below += 1,
-                    EdgeType::End => {}
-                }
+            if stack_len == 0 {
+                break;
             }
+            stack_len -= 1;
+            (node_index, t_min, t_max) = stack[stack_len];
+        }
 
-            if best_axis == -1 && retries < 2 {
-                retries += 1;
-                axis = (axis + 1) % 3;
-                continue;
+        // the full hit is only worked out for the triangle each ray ended up on, from the
+        // packet test's own result so it can't be lost to a second test disagreeing
+        let closest_t = closest_t_so_far.to_array();
+        std::array::from_fn(|lane| {
+            let (triangle, barycentrics) = closest_triangle[lane]?;
+            if lane >= packet.len {
+                return None;
             }
 
-            break;
-        }
+            Some(triangle_hit(
+                closest_t[lane],
+                barycentrics,
+                &packet.rays[lane],
+                triangles,
+                triangle,
+            ))
+        })
+    }
 
-        let mut b_refines = bad_refines;
-        if best_cost > old_cost {
-            b_refines += 1;
+    // Same walk as traverse, but stops at the first triangle hit anywhere in the interval
+    pub fn occluded(&self, triangles: &TriangleMesh, ray: &Ray, t_start: f64, t_end: f64) -> bool {
+        let (hit, box_t_min, box_t_max) = self.bounds.hit(ray, t_start, t_end);
+        if !hit {
+            return false;
         }
-        if (best_cost > 4.0 * old_cost && triangle_list_len < 16)
-            || best_axis == -1
-            || b_refines == 3
-        {
-            return Some(Box::new(KDTree {
-                split_axis: axis,
-                left_child: None,
-                right_child: None,
-                split_distance: 0.0,
-                is_leaf: true,
-                faces: Some(triangle_list.to_vec()),
-                bounds,
-            }));
+        let mut t_min = box_t_min.max(t_start);
+        let mut t_max = box_t_max.min(t_end);
+        if t_min > t_max {
+            return false;
         }
 
-        let mut left = vec![];
-        let mut right = vec![];
-        for i in 0..(best_offset as usize) {
-            match edges[best_axis as usize][i].edge_type {
-                EdgeType::Start => {
-                    left.push(triangle_list[edges[best_axis as usize][i].triangle_num].clone());
+        let mut stack = [(0, 0.0, 0.0); MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut node_index = 0;
+
+        loop {
+            let node = &self.nodes[node_index];
+            if !node.is_leaf() {
+                let axis = node.split_axis();
+                let split = node.split();
+                let origin = ray.origin.get(axis);
+                let t_split = (split - origin) * ray.inverse_direction.get(axis);
+
+                let below_first =
+                    origin < split || (origin == split && ray.direction.get(axis) <= 0.0);
+                let (first_child, second_child) = if below_first {
+                    (node_index + 1, node.above_child())
+                } else {
+                    (node.above_child(), node_index + 1)
+                };
+
+                if t_split > t_max || t_split <= 0.0 {
+                    node_index = first_child;
+                } else if t_split < t_min {
+                    node_index = second_child;
+                } else {
+                    stack[stack_len] = (second_child, t_split, t_max);
+                    stack_len += 1;
+                    node_index = first_child;
+                    t_max = t_split;
                 }
-                EdgeType::End => {}
+                continue;
             }
-        }
 
-        for i in (best_offset as usize + 1)..(2 * triangle_list_len) {
-            match edges[best_axis as usize][i].edge_type {
-                EdgeType::End => {
-                    right.push(triangle_list[edges[best_axis as usize][i].triangle_num].clone());
+            for &triangle in &self.triangle_indices[node.triangles()] {
+                if triangle_occludes(t_start, t_end, ray, triangles, triangle as usize) {
+                    return true;
                 }
-                EdgeType::Start => {}
             }
-        }
 
-        // eprintln!("{}/{}", both.len(), triangle_list_len);
-
-        let t_split = edges[best_axis as usize][best_offset as usize].t;
-        let mut bounds_left = bounds.clone();
-        let mut bounds_right = bounds.clone();
-        bounds_left.maximum.set(best_axis as usize, t_split);
-        bounds_right.minimum.set(best_axis as usize, t_split);
-        let left_len = left.len();
-        let right_len = right.len();
-
-        let left_child =
-            KDTree::build_sah(&mut left[..], left_len, depth - 1, bounds_left, b_refines);
-        let right_child = KDTree::build_sah(
-            &mut right[..],
-            right_len,
-            depth - 1,
-            bounds_right,
-            b_refines,
-        );
-
-        Some(Box::new(KDTree {
-            split_axis: best_axis as usize,
-            left_child,
-            right_child,
-            split_distance: t_split,
-            is_leaf: false,
-            faces: None,
-            bounds,
-        }))
+            if stack_len == 0 {
+                return false;
+            }
+            stack_len -= 1;
+            (node_index, t_min, t_max) = stack[stack_len];
+        }
     }
-}
 
-pub fn build_from_obj(
-    object: Obj<TexturedVertex, u32>,
-    shade_smooth: bool,
-) -> (Vec<Box<Face>>, AxisAlignedBoundingBox) {
-    let mut points = vec![];
-    let mut minimum = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
-    let mut maximum = Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
-
-    for indices in object.indices.chunks(3) {
-        let p1 = Vec3::new(
-            object.vertices[indices[0] as usize].position[0].into(),
-            object.vertices[indices[0] as usize].position[1].into(),
-            object.vertices[indices[0] as usize].position[2].into(),
-        );
-        let p2 = Vec3::new(
-            object.vertices[indices[1] as usize].position[0].into(),
-            object.vertices[indices[1] as usize].position[1].into(),
-            object.vertices[indices[1] as usize].position[2].into(),
-        );
-        let p3 = Vec3::new(
-            object.vertices[indices[2] as usize].position[0].into(),
-            object.vertices[indices[2] as usize].position[1].into(),
-            object.vertices[indices[2] as usize].position[2].into(),
+    //build KDTree using Surface Area Heuristic
+    pub fn build_sah(triangles: &TriangleMesh, depth: u32) -> KDTree {
+        let bounds = triangles.bounding_box();
+        let triangle_bounds: Vec<[Vec3; 2]> = (0..triangles.len())
+            .map(|triangle| triangles.triangle_bounds(triangle))
+            .collect();
+
+        let root = build_node(
+            &triangle_bounds,
+            (0..triangles.len() as u32).collect(),
+            depth.min(MAX_DEPTH as u32),
+            bounds.clone(),
+            0,
         );
 
-        let uv1 = UVCoord {
-            u: object.vertices[indices[0] as usize].texture[0] as f64,
-            v: object.vertices[indices[0] as usize].texture[1] as f64,
-        };
-        let uv2 = UVCoord {
-            u: object.vertices[indices[1] as usize].texture[0] as f64,
-            v: object.vertices[indices[1] as usize].texture[1] as f64,
-        };
-        let uv3 = UVCoord {
-            u: object.vertices[indices[2] as usize].texture[0] as f64,
-            v: object.vertices[indices[2] as usize].texture[1] as f64,
+        let mut tree = KDTree {
+            nodes: vec![],
+            triangle_indices: vec![],
+            bounds,
         };
+        tree.flatten(root);
 
-        let normal1 = object.vertices[indices[0] as usize].normal;
-        let normal1 = Vec3::new(normal1[0] as f64, normal1[1] as f64, normal1[2] as f64);
-
-        let normal2 = object.vertices[indices[1] as usize].normal;
-        let normal2 = Vec3::new(normal2[0] as f64, normal2[1] as f64, normal2[2] as f64);
-
-        let normal3 = object.vertices[indices[2] as usize].normal;
-        let normal3 = Vec3::new(normal3[0] as f64, normal3[1] as f64, normal3[2] as f64);
-
-        let face = Face::new(
-            [p1, p2, p3],
-            [uv1, uv2, uv3],
-            [normal1, normal2, normal3],
-            shade_smooth,
-        );
-
-        let min_x = p1.x.min(p2.x).min(p3.x);
-        if min_x < minimum.x {
-            minimum.x = min_x;
-        }
-
-        let min_y = p1.y.min(p2.y).min(p3.y);
-        if min_y < minimum.y {
-            minimum.y = min_y;
-        }
-
-        let min_z = p1.z.min(p2.z).min(p3.z);
-        if min_z < minimum.z {
-            minimum.z = min_z;
-        }
+        tree
+    }
 
-        let max_x = p1.x.max(p2.x).max(p3.x);
-        if max_x > maximum.x {
-            maximum.x = max_x;
+    fn flatten(&mut self, node: BuildNode) {
+        match node {
+            BuildNode::Leaf(triangles) => {
+                self.nodes.push(KDTreeNode::leaf(
+                    self.triangle_indices.len(),
+                    triangles.len(),
+                ));
+                self.triangle_indices.extend(triangles);
+            }
+            BuildNode::Interior {
+                axis,
+                split,
+                below,
+                above,
+            } => {
+                let index = self.nodes.len();
+                // the above child's index is filled in once the below subtree is laid out
+                self.nodes.push(KDTreeNode::interior(axis, split, 0));
+                self.flatten(*below);
+                self.nodes[index] = KDTreeNode::interior(axis, split, self.nodes.len());
+                self.flatten(*above);
+            }
         }
+    }
+}
 
-        let max_y = p1.y.max(p2.y).max(p3.y);
-        if max_y > maximum.y {
-            maximum.y = max_y;
-        }
+fn build_node(
+    triangle_bounds: &[[Vec3; 2]],
+    triangle_list: Vec<u32>,
+    depth: u32,
+    bounds: AxisAlignedBoundingBox,
+    bad_refines: u32,
+) -> BuildNode {
+    let triangle_list_len = triangle_list.len();
+    if triangle_list_len <= MAX_TRIANGLES_IN_LEAF || depth == 0 {
+        return BuildNode::Leaf(triangle_list);
+    };
+
+    let old_cost = triangle_list_len as f64 * ISECT_COST;
+    let mut axis = bounds.maximum_extent() as usize;
+    let mut best_split = None;
+    for _ in 0..2 {
+        best_split = if triangle_list_len > EXACT_SWEEP_LIMIT {
+            binned_split(triangle_bounds, &triangle_list, &bounds, axis)
+        } else {
+            exact_split(triangle_bounds, &triangle_list, &bounds, axis)
+        };
 
-        let max_z = p1.z.max(p2.z).max(p3.z);
-        if max_z > maximum.z {
-            maximum.z = max_z;
+        if best_split.is_some() {
+            break;
         }
-
-        points.push(Box::new(face));
+        axis = (axis + 1) % 3;
     }
 
-    let bounding_box = AxisAlignedBoundingBox::new(minimum, maximum);
-    (points, bounding_box)
-}
+    let (t_split, best_cost) = match best_split {
+        Some(split) => split,
+        None => return BuildNode::Leaf(triangle_list),
+    };
 
-fn triangle_intersection(
-    t_start: f64,
-    t_end: f64,
-    ray: &Ray,
-    face: &Face,
-    camera: &Camera,
-    mut d_min: f64,
-) -> (Option<KDTreeHitRecord>, f64) {
-    let p1 = Vec3::new(face.points[0].x, face.points[0].y, face.points[0].z);
-    let p2 = Vec3::new(face.points[1].x, face.points[1].y, face.points[1].z);
-    let p3 = Vec3::new(face.points[2].x, face.points[2].y, face.points[2].z);
-
-    let p1p2 = p2 - p1;
-    let p1p3 = p3 - p1;
-    let n = p1p2.cross(&p1p3);
-
-    let triangle_ray_dot_product = n.dot(&ray.direction);
-    if triangle_ray_dot_product.abs() == 0.0 {
-        return (None, 0.0);
+    let mut b_refines = bad_refines;
+    if best_cost > old_cost {
+        b_refines += 1;
     }
-
-    let d = -n.dot(&p1);
-
-    let t = -(n.dot(&ray.origin) + d) / triangle_ray_dot_product;
-    if t < 0.0 {
-        return (None, 0.0);
+    if (best_cost > 4.0 * old_cost && triangle_list_len < 16) || b_refines == 3 {
+        return BuildNode::Leaf(triangle_list);
     }
 
-    // TODO IS THIS WRONG?
-    if t_start > t || t > t_end {
-        return (None, 0.0);
+    // nodes store their split in single precision, so the triangles are sorted against
+    // the rounded split that traversal will see
+    let split = t_split as f32;
+    let t_split = split.into();
+    if t_split <= bounds.minimum.get(axis) || t_split >= bounds.maximum.get(axis) {
+        return BuildNode::Leaf(triangle_list);
     }
 
-    let p = ray.at(t);
-
-    let edge0 = p2 - p1;
-    let v_p1 = p - p1;
-    let c0 = edge0.cross(&v_p1);
-    if n.dot(&c0) < 0.0 {
-        return (None, 0.0);
+    // triangles straddling the split go to both sides
+    let mut below = vec![];
+    let mut above = vec![];
+    for triangle in triangle_list {
+        let [minimum, maximum] = &triangle_bounds[triangle as usize];
+        let is_below = minimum.get(axis) < t_split;
+        let is_above = maximum.get(axis) > t_split;
+        match (is_below, is_above) {
+            (true, true) => {
+                below.push(triangle);
+                above.push(triangle);
+            }
+            (false, true) => above.push(triangle),
+            _ => below.push(triangle),
+        }
     }
 
-    let edge1 = p3 - p2;
-    let v_p2 = p - p2;
-    let c1 = edge1.cross(&v_p2);
-    if n.dot(&c1) < 0.0 {
-        return (None, 0.0);
-    }
+    let mut bounds_below = bounds.clone();
+    let mut bounds_above = bounds;
+    bounds_below.maximum.set(axis, t_split);
+    bounds_above.minimum.set(axis, t_split);
 
-    let edge2 = p1 - p3;
-    let v_p3 = p - p3;
-    let c2 = edge2.cross(&v_p3);
-    if n.dot(&c2) < 0.0 {
-        return (None, 0.0);
+    let build_below = || build_node(triangle_bounds, below, depth - 1, bounds_below, b_refines);
+    let build_above = || build_node(triangle_bounds, above, depth - 1, bounds_above, b_refines);
+    let (below, above) = if triangle_list_len > PARALLEL_BUILD_LIMIT {
+        rayon::join(build_below, build_above)
+    } else {
+        (build_below(), build_above())
+    };
+
+    BuildNode::Interior {
+        axis,
+        split,
+        below: Box::new(below),
+        above: Box::new(above),
     }
+}
 
-    let n_norm = n.unit();
-    let mut _front_face = true;
-    if ray.direction.dot(&n_norm) > 0.0 {
-        _front_face = false;
-        //n_norm = -n_norm;
-        // return (None, 0.0);
+// Cost of splitting the node at edge_t, or None if edge_t is not inside the node
+fn split_cost(
+    bounds: &AxisAlignedBoundingBox,
+    axis: usize,
+    edge_t: f64,
+    number_below: usize,
+    number_above: usize,
+) -> Option<f64> {
+    if edge_t <= bounds.minimum.get(axis) || edge_t >= bounds.maximum.get(axis) {
+        return None;
     }
 
-    let z_distance = distance(&p, &camera.origin).abs();
-    if z_distance <= d_min {
-        d_min = z_distance;
+    let d = bounds.maximum - bounds.minimum;
+    let other_axis0 = (axis + 1) % 3;
+    let other_axis1 = (axis + 2) % 3;
+    let below_sa = 2.0
+        * (d.get(other_axis0) * d.get(other_axis1)
+            + (edge_t - bounds.minimum.get(axis)) * (d.get(other_axis0) + d.get(other_axis1)));
+    let above_sa = 2.0
+        * (d.get(other_axis0) * d.get(other_axis1)
+            + (bounds.maximum.get(axis) - edge_t) * (d.get(other_axis0) + d.get(other_axis1)));
+
+    let inv_total_sa = 1.0 / bounds.surface_area();
+    let p_below = below_sa * inv_total_sa;
+    let p_above = above_sa * inv_total_sa;
+    let eb = if number_above == 0 || number_below == 0 {
+        EMPTY_BONUS
     } else {
-        return (None, 0.0);
+        0.0
+    };
+
+    Some(
+        TRAVERSAL_COST
+            + ISECT_COST
+                * (1.0 - eb)
+                * (p_below * number_below as f64 + p_above * number_above as f64),
+    )
+}
+
+// Sweeps over the sorted triangle edges along the axis, returning the best split and its cost
+fn exact_split(
+    triangle_bounds: &[[Vec3; 2]],
+    triangle_list: &[u32],
+    bounds: &AxisAlignedBoundingBox,
+    axis: usize,
+) -> Option<(f64, f64)> {
+    let mut edges = Vec::with_capacity(2 * triangle_list.len());
+    for &triangle in triangle_list {
+        let [minimum, maximum] = &triangle_bounds[triangle as usize];
+        edges.push(BoundEdge::new(minimum.get(axis), true));
+        edges.push(BoundEdge::new(maximum.get(axis), false));
     }
 
-    // Determine the UV coords of the hitpoint
-    let mut text_coord = UVCoord { u: 0.3, v: 0.5 };
-    let mut normal = n_norm;
-    if let Some((b1, b2)) = get_bary_coords(&p1, &p2, &p3, &p) {
-        let b0 = 1.0 - b1 - b2;
-        text_coord.u =
-            b0 * face.text_coords[0].u + b1 * face.text_coords[1].u + b2 * face.text_coords[2].u;
-        text_coord.v =
-            b0 * face.text_coords[0].v + b1 * face.text_coords[1].v + b2 * face.text_coords[2].v;
-
-        if face.shade_smooth {
-            normal = b0 * &face.normals[0].unit()
-                + b1 * &face.normals[1].unit()
-                + b2 * &face.normals[2].unit();
+    edges.sort_unstable_by(|edge0, edge1| {
+        edge0
+            .t
+            .total_cmp(&edge1.t)
+            .then((edge0.edge_type as u32).cmp(&(edge1.edge_type as u32)))
+    });
+
+    let mut best_split: Option<(f64, f64)> = None;
+    let mut number_below = 0;
+    let mut number_above = triangle_list.len();
+    for edge in &edges {
+        if let EdgeType::End = edge.edge_type {
+            number_above -= 1;
         }
-    }
 
-    // remap coordinate basis used for normal mapping
-    let edge1 = p2 - p1;
-    let edge2 = p3 - p1;
-
-    let uv1 = Vec3::new(
-        face.text_coords[1].u - face.text_coords[0].u,
-        face.text_coords[1].v - face.text_coords[0].v,
-        0.0,
-    );
-    let uv2 = Vec3::new(
-        face.text_coords[2].u - face.text_coords[0].u,
-        face.text_coords[2].v - face.text_coords[0].v,
-        0.0,
-    );
-
-    let f = 1.0 / (uv1.x * uv2.y - uv2.x * uv1.y);
-
-    let tangent = Vec3::new(
-        f * (uv2.y * edge1.x - uv1.y * edge2.x),
-        f * (uv2.y * edge1.y - uv1.y * edge2.y),
-        f * (uv2.y * edge1.z - uv1.y * edge2.z),
-    );
-
-    let bitangent = Vec3::new(
-        f * (-uv2.x * edge1.x + uv1.x * edge2.x),
-        f * (-uv2.x * edge1.y + uv1.x * edge2.y),
-        f * (-uv2.x * edge1.z + uv1.x * edge2.z),
-    );
-
-    normal = normal.unit();
-    if !_front_face {
-        normal = -normal;
+        if let Some(cost) = split_cost(bounds, axis, edge.t, number_below, number_above) {
+            if best_split.is_none_or(|(_, best_cost)| cost < best_cost) {
+                best_split = Some((edge.t, cost));
+            }
+        }
+
+        if let EdgeType::Start = edge.edge_type {
+            number_below += 1;
+        }
     }
 
-    (
-        Some(KDTreeHitRecord {
-            p,
-            t,
-            normal,
-            tangent: tangent.unit(),
-            bitangent: bitangent.unit(),
-            front_face: _front_face,
-            text_coord,
-        }),
-        d_min,
-    )
+    best_split
 }
 
-// This is used to work out the UV coordinates of the ray intersection point from the UV coordinates
-// of the three vertices. Bary centric coords seem pretty cool...
-fn get_bary_coords(p0: &Vec3, p1: &Vec3, p2: &Vec3, hit_point: &Vec3) -> Option<(f64, f64)> {
-    let u = p1 - p0;
-    let v = p2 - p0;
-    let w = hit_point - p0;
-
-    let v_cross_w = v.cross(&w);
-    let v_cross_u = v.cross(&u);
-
-    if v_cross_w.dot(&v_cross_u) < 0.0 {
+// Counts triangle edges into equal sized bins along the axis and only considers splits on
+// the bin boundaries, which needs no sorting
+fn binned_split(
+    triangle_bounds: &[[Vec3; 2]],
+    triangle_list: &[u32],
+    bounds: &AxisAlignedBoundingBox,
+    axis: usize,
+) -> Option<(f64, f64)> {
+    let axis_min = bounds.minimum.get(axis);
+    let bin_width = (bounds.maximum.get(axis) - axis_min) / SPLIT_BINS as f64;
+    if bin_width <= 0.0 {
         return None;
     }
 
-    let u_cross_w = u.cross(&w);
-    let u_cross_v = u.cross(&v);
+    // triangles can poke out of the node so their edges are clamped into the end bins
+    let bin_of = |t: f64| (((t - axis_min) / bin_width).max(0.0) as usize).min(SPLIT_BINS - 1);
 
-    if u_cross_w.dot(&u_cross_v) < 0.0 {
-        return None;
+    let mut starts = [0; SPLIT_BINS];
+    let mut ends = [0; SPLIT_BINS];
+    for &triangle in triangle_list {
+        let [minimum, maximum] = &triangle_bounds[triangle as usize];
+        starts[bin_of(minimum.get(axis))] += 1;
+        ends[bin_of(maximum.get(axis))] += 1;
     }
 
-    let denom = u_cross_v.length();
-    let r = v_cross_w.length() / denom;
-    let t = u_cross_w.length() / denom;
-
-    if (r > 1.0) || (t > 1.0) || (r + t > 1.0) {
-        return None;
+    let mut best_split: Option<(f64, f64)> = None;
+    let mut number_below = 0;
+    let mut number_above = triangle_list.len();
+    for bin in 1..SPLIT_BINS {
+        number_below += starts[bin - 1];
+        number_above -= ends[bin - 1];
+
+        let edge_t = axis_min + bin as f64 * bin_width;
+        if let Some(cost) = split_cost(bounds, axis, edge_t, number_below, number_above) {
+            if best_split.is_none_or(|(_, best_cost)| cost < best_cost) {
+                best_split = Some((edge_t, cost));
+            }
+        }
     }
 
-    Some((r, t))
+    best_split
 }
@@ -1,20 +1,41 @@
 pub mod aabb;
+pub mod animation;
+pub mod bvh;
 pub mod bxdf;
+pub mod cache;
 pub mod camera;
 pub mod colour;
+pub mod csg;
+pub mod curve;
+pub mod disk;
+pub mod displacement;
+pub mod gltf_import;
+pub mod hair;
+pub mod heightfield;
 pub mod hittable;
 pub mod instance;
 pub mod json;
 pub mod kdtree;
 pub mod material;
+pub mod mtl;
+pub mod noise;
 pub mod object;
 pub mod onb;
 pub mod pdf;
+pub mod points;
+pub mod quadric;
 pub mod ray;
 pub mod rectangle;
 pub mod scene;
+pub mod sdf;
+pub mod simd;
 pub mod sphere;
+pub mod subdivision;
 pub mod texture;
+pub mod torus;
+pub mod transform;
+pub mod triangle;
+pub mod triangulate;
 pub mod utils;
 pub mod vector;
 pub mod volume;
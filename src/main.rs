@@ -1,4 +1,8 @@
+use std::ops::RangeInclusive;
+
 use clap::Parser;
+use tracer::animation::Animation;
+use tracer::scene::{read_scene_json, MeshCache, Scene};
 use tracer::window::run;
 
 ///  A simple raytracer written in Rust. Runs on the CPU only... for now!
@@ -12,16 +16,103 @@ struct Args {
     /// The output filename of the render. ie 'car.jpg'
     #[arg(short, long)]
     out: Option<String>,
+
+    /// Render an image sequence of the scene's animation, ie '1..=48'. Each frame is written
+    /// out with its frame number, ie 'car_0001.jpg'
+    #[arg(short, long, value_parser = parse_frames)]
+    frames: Option<RangeInclusive<i64>>,
+
+    /// The cameras to render, ie 'front,side' or 'all'. Renders every camera in the scene if
+    /// not given. Each camera is written out with its name when rendering more than one
+    #[arg(short, long, value_delimiter = ',')]
+    camera: Vec<String>,
+
+    /// A directory to keep built acceleration structures in, ie '.tracer-cache'. Later
+    /// renders of the same models load them from here instead of rebuilding them
+    #[arg(long)]
+    cache_dir: Option<String>,
+
+    /// Time tracing the scene's camera rays in packets and one at a time, without opening a
+    /// window or rendering an image
+    #[arg(long)]
+    bench: bool,
+}
+
+fn parse_frames(frames: &str) -> Result<RangeInclusive<i64>, String> {
+    let (start, end, inclusive) = match frames.split_once("..=") {
+        Some((start, end)) => (start, end, true),
+        None => match frames.split_once("..") {
+            Some((start, end)) => (start, end, false),
+            None => return Err(String::from("expected a range like 'start..end'")),
+        },
+    };
+
+    let start: i64 = start
+        .trim()
+        .parse()
+        .map_err(|e| format!("bad start frame: {}", e))?;
+    let end: i64 = end
+        .trim()
+        .parse()
+        .map_err(|e| format!("bad end frame: {}", e))?;
+    let end = if inclusive { end } else { end - 1 };
+
+    if end < start {
+        return Err(format!("'{}' doesn't contain any frames", frames));
+    }
+
+    Ok(start..=end)
 }
 
 fn main() {
     let args = Args::parse();
 
+    if args.bench {
+        bench(&args.scene, &args.camera, args.cache_dir);
+        return;
+    }
+
     let out_file = match args.out {
         Some(file) => file,
         None => format!("untitled_{}.jpg", chrono::offset::Local::now()),
     };
 
     // Opens a window and starts the raytracer
-    pollster::block_on(run(args.scene, out_file));
+    pollster::block_on(run(
+        args.scene,
+        out_file,
+        args.frames,
+        args.camera,
+        args.cache_dir,
+    ));
+}
+
+fn bench(scene_path: &str, cameras: &[String], cache_dir: Option<String>) {
+    let mut meshes = match cache_dir {
+        Some(cache_dir) => MeshCache::with_disk_cache(&cache_dir),
+        None => MeshCache::default(),
+    };
+    let animation = Animation::new(read_scene_json(scene_path));
+    let scene = Scene::from_json(animation.scene_at(0.0), &mut meshes);
+
+    let camera = match cameras.first() {
+        Some(name) => match scene.camera(name) {
+            Some(camera) => camera,
+            None => panic!("No camera named {} in the scene", name),
+        },
+        None => &scene.cameras[0],
+    };
+
+    let (width, height) = camera.image_dimensions(&scene.render_settings);
+    let rays = (width * height) as f64;
+    let (packets, single_rays, mismatches) = scene.bench_camera_rays(camera);
+    println!(
+        "{} camera rays: {:.3}s in packets ({:.2} Mrays/s), {:.3}s one at a time ({:.2} Mrays/s), {} hit differently",
+        rays,
+        packets.as_secs_f64(),
+        rays / packets.as_secs_f64() / 1e6,
+        single_rays.as_secs_f64(),
+        rays / single_rays.as_secs_f64() / 1e6,
+        mismatches,
+    );
 }
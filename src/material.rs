@@ -19,10 +19,10 @@ pub trait Material: Send + Sync + std::fmt::Debug {
         _ray_in: &Ray,
         _hit_record: &HitRecord,
         _camera: &Camera,
-        _sampled_light_position: Vec3,
+        _sampled_light_position: Option<Vec3>,
     ) -> (Ray, Colour, bool) {
         (
-            Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0)),
+            Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0), 0.0),
             Colour::new(0.0, 0.0, 0.0),
             false,
         )
@@ -39,6 +39,16 @@ pub trait Material: Send + Sync + std::fmt::Debug {
     fn use_pdfs(&self) -> bool {
         false
     }
+
+    // Whether there are holes in the surface, which meshes then check each hit against
+    fn has_cutouts(&self) -> bool {
+        false
+    }
+
+    // How much of the surface is there at a point, from 0 for a hole to 1 where it's solid
+    fn opacity(&self, _u: f64, _v: f64) -> f64 {
+        1.0
+    }
 }
 
 #[derive(Debug)]
@@ -49,15 +59,18 @@ impl Material for UnitMaterial {}
 pub struct MicrofacetReflectance {
     pub albedo: Box<dyn Texture + Send + Sync>,
     pub bxdf: Box<dyn BxDF + Send + Sync>,
+    // scales the bxdf's roughness by its green channel and its metallic by its blue, as
+    // glTF's metallic-roughness textures do
+    pub metallic_roughness: Option<Box<dyn Texture + Send + Sync>>,
 }
 
 impl Material for MicrofacetReflectance {
     fn scatter(
         &self,
-        _ray_in: &Ray,
+        ray_in: &Ray,
         hit_record: &HitRecord,
         camera: &Camera,
-        sampled_light_position: Vec3,
+        sampled_light_position: Option<Vec3>,
     ) -> (Ray, Colour, bool) {
         let onb = OrthonormalBasis::build_from_w(&hit_record.normal);
         let normal = match self
@@ -69,7 +82,6 @@ impl Material for MicrofacetReflectance {
         };
 
         let wo = camera.origin - hit_record.p;
-        let light_dir = sampled_light_position - hit_record.p;
 
         let mut scatter_direction = onb.local_vec(&random_cosine_direction());
 
@@ -77,15 +89,31 @@ impl Material for MicrofacetReflectance {
             scatter_direction = Vec3::copy(&normal);
         }
 
-        let scattered = Ray::new(Vec3::copy(&hit_record.p), scatter_direction);
+        // without a light in view the reflectance is found along the scattered ray instead
+        let light_dir = match sampled_light_position {
+            Some(light_position) => light_position - hit_record.p,
+            None => scatter_direction,
+        };
+
+        let scattered = hit_record.spawn_ray(scatter_direction, ray_in.time);
 
-        let mut colour = self.albedo.value(hit_record.u, hit_record.v, &hit_record.p);
+        let mut colour = self.albedo.hit_value(hit_record);
         colour.r = colour.r.powf(2.0);
         colour.g = colour.g.powf(2.0);
         colour.b = colour.b.powf(2.0);
+
+        let scaled;
+        let bxdf = match &self.metallic_roughness {
+            Some(texture) => {
+                let scale = texture.hit_value(hit_record);
+                scaled = self.bxdf.scaled(scale.b, scale.g);
+                &scaled
+            }
+            None => &self.bxdf,
+        };
         (
             scattered,
-            self.bxdf.f(&wo.unit(), &light_dir.unit(), &normal, &colour),
+            bxdf.f(&wo.unit(), &light_dir.unit(), &normal, &colour),
             true,
         )
     }
@@ -110,7 +138,7 @@ impl Material for SpecularReflectance {
         ray_in: &Ray,
         hit_record: &HitRecord,
         camera: &Camera,
-        _sampled_light_position: Vec3,
+        _sampled_light_position: Option<Vec3>,
     ) -> (Ray, Colour, bool) {
         let normal = match self
             .albedo
@@ -134,9 +162,9 @@ impl Material for SpecularReflectance {
             f = 1.0 - normal.unit().dot(&wo.unit());
         }
 
-        let scattered = Ray::new(Vec3::copy(&hit_record.p), scatter_direction);
+        let scattered = hit_record.spawn_ray(scatter_direction, ray_in.time);
         let _scattered_b = scattered.direction.dot(&normal) > 0.0;
-        let colour = self.albedo.value(hit_record.u, hit_record.v, &hit_record.p);
+        let colour = self.albedo.hit_value(hit_record);
         (scattered, f * spec_multi * colour, true)
     }
 
@@ -162,7 +190,7 @@ impl Material for Glossy {
         ray_in: &Ray,
         hit_record: &HitRecord,
         camera: &Camera,
-        _sampled_light_position: Vec3,
+        _sampled_light_position: Option<Vec3>,
     ) -> (Ray, Colour, bool) {
         let normal = match self
             .albedo
@@ -184,12 +212,12 @@ impl Material for Glossy {
             reflect_factor = 0.1;
         }
 
-        let scattered = Ray::new(
-            Vec3::copy(&hit_record.p),
+        let scattered = hit_record.spawn_ray(
             scatter_direction + self.fuzziness.clamp(0.0, 1.0) * &random_in_unit_sphere(),
+            ray_in.time,
         );
         let _scattered_b = scattered.direction.dot(&normal) > 0.0;
-        let mut colour = self.albedo.value(hit_record.u, hit_record.v, &hit_record.p);
+        let mut colour = self.albedo.hit_value(hit_record);
 
         colour.r = colour.r.powf(1.0);
         colour.g = colour.g.powf(1.0);
@@ -218,10 +246,10 @@ pub struct Lambertian {
 impl Material for Lambertian {
     fn scatter(
         &self,
-        _ray_in: &Ray,
+        ray_in: &Ray,
         hit_record: &HitRecord,
         _camera: &Camera,
-        _sampled_light_position: Vec3,
+        _sampled_light_position: Option<Vec3>,
     ) -> (Ray, Colour, bool) {
         let normal = match self
             .albedo
@@ -238,11 +266,11 @@ impl Material for Lambertian {
             scatter_direction = Vec3::copy(&normal);
         }
 
-        let scattered = Ray::new(Vec3::copy(&hit_record.p), scatter_direction);
+        let scattered = hit_record.spawn_ray(scatter_direction, ray_in.time);
 
         (
             scattered,
-            Colour::copy(&self.albedo.value(hit_record.u, hit_record.v, &hit_record.p)),
+            Colour::copy(&self.albedo.hit_value(hit_record)),
             true,
         )
     }
@@ -282,7 +310,7 @@ impl Material for Metal {
         ray_in: &Ray,
         hit_record: &HitRecord,
         _camera: &Camera,
-        _sampled_light_position: Vec3,
+        _sampled_light_position: Option<Vec3>,
     ) -> (Ray, Colour, bool) {
         let normal = match self
             .albedo
@@ -293,16 +321,12 @@ impl Material for Metal {
         };
 
         let reflected = reflect(&ray_in.direction.unit(), &normal);
-        let scattered_ray = Ray::new(
-            Vec3::copy(&hit_record.p),
+        let scattered_ray = hit_record.spawn_ray(
             reflected + self.f.clamp(0.0, 1.0) * &random_in_unit_sphere(),
+            ray_in.time,
         );
         let scattered = scattered_ray.direction.dot(&normal) > 0.0;
-        (
-            scattered_ray,
-            self.albedo.value(hit_record.u, hit_record.v, &hit_record.p),
-            scattered,
-        )
+        (scattered_ray, self.albedo.hit_value(hit_record), scattered)
     }
 }
 
@@ -318,7 +342,7 @@ impl Material for Dielectric {
         ray_in: &Ray,
         hit_record: &HitRecord,
         _camera: &Camera,
-        _sampled_light_position: Vec3,
+        _sampled_light_position: Option<Vec3>,
     ) -> (Ray, Colour, bool) {
         let mut normal = hit_record.normal;
         let mut attenuation = Colour::new(1.0, 1.0, 1.0);
@@ -328,7 +352,7 @@ impl Material for Dielectric {
                 Some(normal) => normal,
                 None => hit_record.normal,
             };
-            attenuation = albedo.value(hit_record.u, hit_record.v, &hit_record.p);
+            attenuation = albedo.hit_value(hit_record);
         }
 
         let refraction_ratio = if hit_record.front_face {
@@ -355,7 +379,7 @@ impl Material for Dielectric {
         attenuation.g = attenuation.g.powf(2.0);
         attenuation.b = attenuation.b.powf(2.0);
         (
-            Ray::new(Vec3::copy(&hit_record.p), direction),
+            hit_record.spawn_ray(direction, ray_in.time),
             attenuation,
             true,
         )
@@ -374,7 +398,7 @@ impl Material for Light {
         ray_in: &Ray,
         hit_record: &HitRecord,
         _camera: &Camera,
-        _sampled_light_position: Vec3,
+        _sampled_light_position: Option<Vec3>,
     ) -> (Ray, Colour, bool) {
         if self.albedo.alpha_value(hit_record.u, hit_record.v) < 0.1 {
             let onb = OrthonormalBasis::build_from_w(&hit_record.normal);
@@ -384,16 +408,20 @@ impl Material for Light {
                 scatter_direction = Vec3::copy(&hit_record.normal);
             }
 
-            let scattered = Ray::new(Vec3::copy(&hit_record.p), scatter_direction);
+            let scattered = hit_record.spawn_ray(scatter_direction, ray_in.time);
 
             return (
                 scattered,
-                Colour::copy(&self.albedo.value(hit_record.u, hit_record.v, &hit_record.p)),
+                Colour::copy(&self.albedo.hit_value(hit_record)),
                 true,
             );
         }
 
-        let ray = Ray::new(Vec3::copy(&ray_in.origin), Vec3::copy(&ray_in.direction));
+        let ray = Ray::new(
+            Vec3::copy(&ray_in.origin),
+            Vec3::copy(&ray_in.direction),
+            ray_in.time,
+        );
         (ray, Colour::new(0.0, 0.0, 0.0), false)
     }
 
@@ -414,17 +442,110 @@ pub struct Isotropic {
 impl Material for Isotropic {
     fn scatter(
         &self,
-        _ray_in: &Ray,
+        ray_in: &Ray,
         hit_record: &HitRecord,
         _camera: &Camera,
-        _sampled_light_position: Vec3,
+        _sampled_light_position: Option<Vec3>,
     ) -> (Ray, Colour, bool) {
-        let ray = Ray::new(Vec3::copy(&hit_record.p), random_in_unit_sphere());
-        (
-            ray,
-            self.albedo.value(hit_record.u, hit_record.v, &hit_record.p),
-            true,
-        )
+        let ray = hit_record.spawn_ray(random_in_unit_sphere(), ray_in.time);
+        (ray, self.albedo.hit_value(hit_record), true)
+    }
+}
+
+// Another material with holes in it, where rays carry on as if nothing was there. The
+// opacity is `opacity` times the alpha of the `alpha` texture, if there is one. Without a
+// cutoff, a partly opaque surface stops that fraction of the rays reaching it, and with one
+// the surface is solid where the opacity is at least the cutoff and missing elsewhere.
+#[derive(Debug)]
+pub struct Cutout {
+    pub material: Box<dyn Material>,
+    pub alpha: Option<Box<dyn Texture + Send + Sync>>,
+    pub opacity: f64,
+    pub cutoff: Option<f64>,
+}
+
+impl Material for Cutout {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        camera: &Camera,
+        sampled_light_position: Option<Vec3>,
+    ) -> (Ray, Colour, bool) {
+        self.material
+            .scatter(ray_in, hit_record, camera, sampled_light_position)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Colour {
+        self.material.emitted(u, v, p)
+    }
+
+    fn scattering_pdf(&self, ray_in: &Ray, hit_record: &HitRecord, scattered: &Ray) -> f64 {
+        self.material.scattering_pdf(ray_in, hit_record, scattered)
+    }
+
+    fn use_pdfs(&self) -> bool {
+        self.material.use_pdfs()
+    }
+
+    fn has_cutouts(&self) -> bool {
+        true
+    }
+
+    fn opacity(&self, u: f64, v: f64) -> f64 {
+        let alpha = self
+            .alpha
+            .as_ref()
+            .map_or(1.0, |alpha| alpha.alpha_value(u, v));
+        let opacity = self.opacity * alpha;
+
+        match self.cutoff {
+            Some(cutoff) if opacity >= cutoff => 1.0,
+            Some(_) => 0.0,
+            None => opacity,
+        }
+    }
+}
+
+// A surface that glows as well as scattering light, such as a screen or a lamp's shade.
+// The emission texture is sampled like a light's and multiplied by the intensity.
+#[derive(Debug)]
+pub struct Emissive {
+    pub material: Box<dyn Material>,
+    pub emission: Box<dyn Texture + Send + Sync>,
+    pub intensity: f64,
+}
+
+impl Material for Emissive {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        camera: &Camera,
+        sampled_light_position: Option<Vec3>,
+    ) -> (Ray, Colour, bool) {
+        self.material
+            .scatter(ray_in, hit_record, camera, sampled_light_position)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Colour {
+        self.intensity * self.emission.value(u, v, p) + self.material.emitted(u, v, p)
+    }
+
+    fn scattering_pdf(&self, ray_in: &Ray, hit_record: &HitRecord, scattered: &Ray) -> f64 {
+        self.material.scattering_pdf(ray_in, hit_record, scattered)
+    }
+
+    fn use_pdfs(&self) -> bool {
+        self.material.use_pdfs()
+    }
+
+    fn has_cutouts(&self) -> bool {
+        self.material.has_cutouts()
+    }
+
+    fn opacity(&self, u: f64, v: f64) -> f64 {
+        self.material.opacity(u, v)
     }
 }
 
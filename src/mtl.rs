@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+// A material from an MTL file, keeping the statements the tracer can make use of
+#[derive(Debug, Default)]
+pub struct MtlMaterial {
+    // Kd and Ks
+    pub diffuse: Option<[f64; 3]>,
+    pub specular: Option<[f64; 3]>,
+    // Ns, the Phong exponent
+    pub shininess: Option<f64>,
+    // Ni
+    pub index_of_refraction: Option<f64>,
+    // d, or one minus Tr
+    pub dissolve: Option<f64>,
+    // illum
+    pub illumination: Option<u32>,
+    // map_Kd and map_Bump (or bump), paths relative to the MTL file, and the bump map's -bm
+    pub diffuse_map: Option<String>,
+    pub bump_map: Option<String>,
+    pub bump_multiplier: Option<f64>,
+    // the PBR extension's Pr and Pm
+    pub roughness: Option<f64>,
+    pub metallic: Option<f64>,
+}
+
+// Reads the materials of an MTL file by name. Statements the tracer has no use for, like
+// Ka and the other texture maps, are skipped.
+pub fn parse_mtl(text: &str) -> Result<HashMap<String, MtlMaterial>, String> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (statement, arguments) = (words[0], &words[1..]);
+        let error = |why: String| format!("line {}: {}: {}", line_number + 1, statement, why);
+
+        if statement == "newmtl" {
+            if arguments.is_empty() {
+                return Err(error("a material needs a name".to_string()));
+            }
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((arguments.join(" "), MtlMaterial::default()));
+            continue;
+        }
+
+        let material = match current.as_mut() {
+            Some((_, material)) => material,
+            None if matches!(statement, "Kd" | "Ks" | "Ns" | "Ni" | "d" | "Tr" | "illum") => {
+                return Err(error("comes before any newmtl".to_string()))
+            }
+            None => continue,
+        };
+
+        match statement {
+            "Kd" => material.diffuse = Some(parse_colour(arguments).map_err(error)?),
+            "Ks" => material.specular = Some(parse_colour(arguments).map_err(error)?),
+            "Ns" => material.shininess = Some(parse_number(arguments).map_err(error)?),
+            "Ni" => material.index_of_refraction = Some(parse_number(arguments).map_err(error)?),
+            "d" => material.dissolve = Some(parse_number(arguments).map_err(error)?),
+            "Tr" => material.dissolve = Some(1.0 - parse_number(arguments).map_err(error)?),
+            "illum" => material.illumination = Some(parse_number(arguments).map_err(error)? as u32),
+            "Pr" => material.roughness = Some(parse_number(arguments).map_err(error)?),
+            "Pm" => material.metallic = Some(parse_number(arguments).map_err(error)?),
+            "map_Kd" => material.diffuse_map = Some(parse_map(arguments).map_err(error)?.0),
+            "map_Bump" | "map_bump" | "bump" => {
+                let (bump_map, bump_multiplier) = parse_map(arguments).map_err(error)?;
+                material.bump_map = Some(bump_map);
+                material.bump_multiplier = bump_multiplier;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+
+    Ok(materials)
+}
+
+fn parse_number(arguments: &[&str]) -> Result<f64, String> {
+    match arguments {
+        [number] => number
+            .parse::<f64>()
+            .map_err(|why| format!("{}: {}", number, why)),
+        _ => Err(format!("expected one number, not {}", arguments.len())),
+    }
+}
+
+// An RGB colour, or one number for a grey
+fn parse_colour(arguments: &[&str]) -> Result<[f64; 3], String> {
+    let numbers = arguments
+        .iter()
+        .map(|number| number.parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| {
+            format!(
+                "only RGB colours are supported, not {}",
+                arguments.join(" ")
+            )
+        })?;
+
+    match numbers[..] {
+        [grey] => Ok([grey, grey, grey]),
+        [r, g, b] => Ok([r, g, b]),
+        _ => Err(format!("expected 1 or 3 numbers, not {}", numbers.len())),
+    }
+}
+
+// The file of a texture map, after any options like -s or -bm, along with the bump
+// multiplier from its -bm. The file name is everything left, so it may have spaces in it.
+fn parse_map(arguments: &[&str]) -> Result<(String, Option<f64>), String> {
+    let mut rest = arguments;
+    let mut bump_multiplier = None;
+    while let Some(option) = rest.first().filter(|word| word.starts_with('-')) {
+        // -o, -s and -t take one to three numbers, the rest a fixed number of values
+        let (least, most) = match *option {
+            "-o" | "-s" | "-t" => (1, 3),
+            "-mm" => (2, 2),
+            "-blendu" | "-blendv" | "-bm" | "-boost" | "-cc" | "-clamp" | "-imfchan"
+            | "-texres" | "-type" => (1, 1),
+            _ => return Err(format!("unknown texture option {}", option)),
+        };
+
+        let values = rest[1..]
+            .iter()
+            .take(most)
+            .enumerate()
+            .take_while(|(i, value)| *i < least || value.parse::<f64>().is_ok())
+            .count();
+        if values < least {
+            return Err(format!("{} needs {} values", option, least));
+        }
+        if *option == "-bm" {
+            bump_multiplier = Some(parse_number(&rest[1..2])?);
+        }
+        rest = &rest[1 + values..];
+    }
+
+    if rest.is_empty() {
+        return Err("no texture file".to_string());
+    }
+
+    Ok((rest.join(" "), bump_multiplier))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn materials_are_read_by_name() {
+        let materials = parse_mtl(
+            "# exported\n\
+             newmtl Painted Steel\n\
+             Kd 0.5 0.25 0\n\
+             Ns 250\n\
+             Tr 0.25\n\
+             illum 2\n\
+             map_Kd -s 2 2 textures/paint base.png\n\
+             map_Bump -bm 0.5 -clamp on dents.png\n\
+             newmtl Chrome\n\
+             Ks 0.9\n\
+             illum 3\n",
+        )
+        .unwrap();
+
+        let steel = &materials["Painted Steel"];
+        assert_eq!(steel.diffuse, Some([0.5, 0.25, 0.0]));
+        assert_eq!(steel.shininess, Some(250.0));
+        assert_eq!(steel.dissolve, Some(0.75));
+        assert_eq!(steel.illumination, Some(2));
+        assert_eq!(
+            steel.diffuse_map.as_deref(),
+            Some("textures/paint base.png")
+        );
+        assert_eq!(steel.bump_map.as_deref(), Some("dents.png"));
+        assert_eq!(steel.bump_multiplier, Some(0.5));
+
+        let chrome = &materials["Chrome"];
+        assert_eq!(chrome.specular, Some([0.9, 0.9, 0.9]));
+        assert_eq!(chrome.illumination, Some(3));
+        assert_eq!(chrome.bump_multiplier, None);
+    }
+
+    #[test]
+    fn bad_statements_say_where_they_are() {
+        let before_newmtl = parse_mtl("Kd 1 1 1\n").unwrap_err();
+        assert!(before_newmtl.starts_with("line 1: Kd"));
+
+        let bad_colour = parse_mtl("newmtl a\nKd 1 1\n").unwrap_err();
+        assert!(bad_colour.starts_with("line 2: Kd"));
+
+        let no_file = parse_mtl("newmtl a\nmap_Kd -bm 1\n").unwrap_err();
+        assert!(no_file.contains("no texture file"));
+    }
+}
@@ -1,23 +1,157 @@
 use crate::aabb::AxisAlignedBoundingBox;
-use crate::camera::Camera;
-use crate::hittable::{HitRecord, Hittable};
-use crate::kdtree::{build_from_obj, KDTree, KDTreeHitRecord};
+use crate::bvh::MeshBVH;
+use crate::hittable::{depth_test, HitRecord, Hittable, Pixel};
+use crate::kdtree::{KDTree, KDTreeHitRecord};
 use crate::material::Material;
 use crate::material::UnitMaterial;
-use crate::ray::Ray;
+use crate::ray::{Ray, RayPacket};
+use crate::simd::LANES;
 use crate::sphere::Sphere;
-use crate::utils::distance;
+use crate::triangle::{TriangleMesh, VertexPrecision};
 use crate::vector::Vec3;
 
 use obj::{Obj, TexturedVertex};
+use serde::{Deserialize, Serialize};
 use std::fmt;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AcceleratorType {
+    KDTree,
+    BVH,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize)]
+pub enum Accelerator {
+    KDTree(Box<KDTree>),
+    BVH(MeshBVH),
+}
+
+impl Accelerator {
+    pub fn traverse(
+        &self,
+        triangles: &TriangleMesh,
+        ray: &Ray,
+        t_start: f64,
+        t_end: f64,
+    ) -> Option<KDTreeHitRecord> {
+        match self {
+            Accelerator::KDTree(tree) => tree.traverse(triangles, ray, t_start, t_end),
+            Accelerator::BVH(bvh) => bvh.traverse(triangles, ray, t_start, t_end),
+        }
+    }
+
+    pub fn traverse_packet(
+        &self,
+        triangles: &TriangleMesh,
+        packet: &RayPacket,
+        t_start: f64,
+        t_end: [f64; LANES],
+    ) -> [Option<KDTreeHitRecord>; LANES] {
+        match self {
+            Accelerator::KDTree(tree) => tree.traverse_packet(triangles, packet, t_start, t_end),
+            Accelerator::BVH(bvh) => std::array::from_fn(|lane| match lane < packet.len {
+                true => bvh.traverse(triangles, &packet.rays[lane], t_start, t_end[lane]),
+                false => None,
+            }),
+        }
+    }
+
+    pub fn occluded(&self, triangles: &TriangleMesh, ray: &Ray, t_start: f64, t_end: f64) -> bool {
+        match self {
+            Accelerator::KDTree(tree) => tree.occluded(triangles, ray, t_start, t_end),
+            Accelerator::BVH(bvh) => bvh.occluded(triangles, ray, t_start, t_end),
+        }
+    }
+}
+
+// The materials an OBJ's faces ask for with usemtl, each given a slot in the order they
+// first appear, and the MTL files it names with mtllib to look them up in
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MeshMaterials {
+    pub names: Vec<String>,
+    pub libraries: Vec<String>,
+    // the slot of every triangle, left empty when they all use the first
+    pub triangles: Vec<u32>,
+}
+
+impl MeshMaterials {
+    pub fn slot(&self, triangle: usize) -> usize {
+        self.triangles
+            .get(triangle)
+            .map_or(0, |&slot| slot as usize)
+    }
+}
+
+// Triangles of a loaded model together with their acceleration structure. Meshes are
+// immutable once built so they can be shared between objects and between frames.
+#[derive(Serialize, Deserialize)]
+pub struct Mesh {
+    pub triangles: TriangleMesh,
+    pub materials: MeshMaterials,
+    pub accelerator: Accelerator,
+    pub bounding_box: AxisAlignedBoundingBox,
+    pub triangle_count: usize,
+    #[serde(skip)]
+    pub build_time: Duration,
+}
+
+impl fmt::Debug for Mesh {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Mesh",)
+    }
+}
+
+impl Mesh {
+    pub fn new(
+        object: Obj<TexturedVertex, u32>,
+        materials: MeshMaterials,
+        shade_smooth: bool,
+        accelerator_type: AcceleratorType,
+        precision: VertexPrecision,
+    ) -> Mesh {
+        let triangles = TriangleMesh::from_obj(object, shade_smooth, precision);
+        Mesh::from_triangles(triangles, materials, accelerator_type)
+    }
+
+    pub fn from_triangles(
+        triangles: TriangleMesh,
+        materials: MeshMaterials,
+        accelerator_type: AcceleratorType,
+    ) -> Mesh {
+        let bounding_box = triangles.bounding_box();
+        let triangle_count = triangles.len();
+
+        let start = Instant::now();
+        let accelerator = match accelerator_type {
+            AcceleratorType::KDTree => {
+                let depth = 8 + (1.3 * (triangle_count as f64).log2()) as u32;
+                Accelerator::KDTree(Box::new(KDTree::build_sah(&triangles, depth)))
+            }
+            AcceleratorType::BVH => Accelerator::BVH(MeshBVH::build(&triangles)),
+        };
+
+        Mesh {
+            triangles,
+            materials,
+            accelerator,
+            bounding_box,
+            triangle_count,
+            build_time: start.elapsed(),
+        }
+    }
+}
 
 pub struct Object {
-    pub tree: Box<KDTree>,
-    material: Box<dyn Material>,
-    bounding_box: AxisAlignedBoundingBox,
+    pub mesh: Arc<Mesh>,
+    // one for each of the mesh's material slots
+    materials: Vec<Box<dyn Material>>,
     render: bool,
+    // whether any of the materials has holes for rays to pass through
+    cutouts: bool,
 }
 
 impl fmt::Debug for Object {
@@ -27,91 +161,148 @@ impl fmt::Debug for Object {
 }
 
 impl Object {
-    pub fn new(
-        object: Obj<TexturedVertex, u32>,
-        material: Box<dyn Material>,
-        shade_smooth: bool,
-        render: bool,
-    ) -> Object {
-        let (mut faces, bounding_box) = build_from_obj(object, shade_smooth);
-
-        let faces_len = faces.len();
-        let depth = 8 + (1.3 * (faces_len as f64).log2()) as u32;
-        if let Some(tree) =
-            KDTree::build_sah(&mut faces[..], faces_len, depth, bounding_box.clone(), 0)
-        {
-            Object {
-                tree,
-                material,
-                bounding_box,
-                render,
+    pub fn new(mesh: Arc<Mesh>, materials: Vec<Box<dyn Material>>, render: bool) -> Object {
+        if materials.len() < mesh.materials.names.len().max(1) {
+            panic!(
+                "A mesh with {} material slots was given {} materials",
+                mesh.materials.names.len(),
+                materials.len()
+            );
+        }
+
+        let cutouts = materials.iter().any(|material| material.has_cutouts());
+        Object {
+            mesh,
+            materials,
+            render,
+            cutouts,
+        }
+    }
+
+    // Whether the material of a hit is there where it was hit. Partly opaque surfaces are
+    // there for that fraction of the rays.
+    fn is_solid(&self, hit: &KDTreeHitRecord) -> bool {
+        let material = &self.materials[self.mesh.materials.slot(hit.triangle)];
+        let opacity = material.opacity(hit.text_coord.u, hit.text_coord.v);
+        opacity >= 1.0 || (opacity > 0.0 && rand::random::<f64>() < opacity)
+    }
+
+    // The closest hit after t_min that isn't in a hole, looking behind each one that is
+    fn solid_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<KDTreeHitRecord> {
+        let mut t_min = t_min;
+        loop {
+            let hit = self
+                .mesh
+                .accelerator
+                .traverse(&self.mesh.triangles, ray, t_min, t_max)?;
+            if !self.cutouts || self.is_solid(&hit) {
+                return Some(hit);
             }
-        } else {
-            panic!("Problem building kdtree");
+            t_min = hit.t;
         }
     }
+
+    fn hit_record(
+        &self,
+        hit: KDTreeHitRecord,
+        origin: &Vec3,
+        pixel: Option<Pixel>,
+    ) -> Option<HitRecord<'_>> {
+        let KDTreeHitRecord {
+            p,
+            t,
+            normal,
+            tangent,
+            bitangent,
+            front_face,
+            text_coord,
+            geometric_normal,
+            p_error,
+            triangle,
+        } = hit;
+
+        // only check the zbuffer at first ray level
+        if !depth_test(&p, origin, pixel) {
+            return None;
+        }
+
+        Some(HitRecord {
+            p,
+            t,
+            normal,
+            tangent: Some(tangent),
+            bitangent: Some(bitangent),
+            material: &self.materials[self.mesh.materials.slot(triangle)],
+            front_face,
+            u: text_coord.u,
+            v: text_coord.v,
+            geometric_normal,
+            p_error,
+            colour: None,
+        })
+    }
 }
 
 impl Hittable for Object {
     fn hit(
         &self,
         ray: &Ray,
-        camera: &Camera,
         t_min: f64,
         t_max: f64,
-        pixel: Option<(usize, usize)>,
-        zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
+        pixel: Option<Pixel>,
         _first_ray: bool,
-    ) -> Option<HitRecord> {
+    ) -> Option<HitRecord<'_>> {
         //eprintln!("Search object");
-        if let Some(KDTreeHitRecord {
-            p,
-            t,
-            normal,
-            tangent,
-            bitangent,
-            front_face,
-            text_coord,
-        }) = self.tree.traverse(ray, camera, t_min, t_max)
-        {
-            // only check the zbuffer at first ray level
-            if let Some(pixel) = pixel {
-                let z_distance = distance(&p, &camera.origin);
-                let mut zbuff = zbuffer.lock().unwrap();
-
-                if z_distance < zbuff[pixel.0][pixel.1] {
-                    zbuff[pixel.0][pixel.1] = z_distance;
-                } else {
-                    return None;
-                }
+        let hit = self.solid_hit(ray, t_min, t_max)?;
+
+        self.hit_record(hit, &ray.origin, pixel)
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: f64,
+        t_max: [f64; LANES],
+        pixels: [Option<Pixel>; LANES],
+        _first_ray: bool,
+    ) -> [Option<HitRecord<'_>>; LANES] {
+        let mut hits =
+            self.mesh
+                .accelerator
+                .traverse_packet(&self.mesh.triangles, packet, t_min, t_max);
+
+        std::array::from_fn(|lane| {
+            let mut hit = hits[lane].take()?;
+            // rays that met a hole carry on by themselves
+            if self.cutouts && !self.is_solid(&hit) {
+                hit = self.solid_hit(&packet.rays[lane], hit.t, t_max[lane])?;
             }
+            self.hit_record(hit, &packet.rays[lane].origin, pixels[lane])
+        })
+    }
 
-            return Some(HitRecord {
-                p,
-                t,
-                normal,
-                tangent: Some(tangent),
-                bitangent: Some(bitangent),
-                material: &self.material,
-                front_face,
-                u: text_coord.u,
-                v: text_coord.v,
-            });
+    // The first hit found can be in a hole, so meshes with them look for a solid hit instead
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        if self.cutouts {
+            return self.solid_hit(ray, 0.0, t_max).is_some();
         }
 
-        None
+        self.mesh
+            .accelerator
+            .occluded(&self.mesh.triangles, ray, 0.0, t_max)
     }
 
     fn get_light_sampler_sphere(&self) -> Sphere {
+        let bounding_box = &self.mesh.bounding_box;
         let mut center = Vec3::new(
-            (self.bounding_box.minimum.x + self.bounding_box.maximum.x) / 2.0,
-            (self.bounding_box.minimum.y + self.bounding_box.maximum.y) / 2.0,
-            (self.bounding_box.minimum.z + self.bounding_box.maximum.z) / 2.0,
+            (bounding_box.minimum.x + bounding_box.maximum.x) / 2.0,
+            (bounding_box.minimum.y + bounding_box.maximum.y) / 2.0,
+            (bounding_box.minimum.z + bounding_box.maximum.z) / 2.0,
         );
 
-        let r1 = self.bounding_box.maximum.x - self.bounding_box.minimum.x;
-        let r2 = self.bounding_box.maximum.y - self.bounding_box.minimum.y;
-        let r3 = self.bounding_box.maximum.z - self.bounding_box.minimum.z;
+        let r1 = bounding_box.maximum.x - bounding_box.minimum.x;
+        let r2 = bounding_box.maximum.y - bounding_box.minimum.y;
+        let r3 = bounding_box.maximum.z - bounding_box.minimum.z;
 
         let radius = r1.abs().max(r2.abs()).max(r3.abs()) / 2.0;
 
@@ -127,8 +318,8 @@ impl Hittable for Object {
 
     fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
         Some(AxisAlignedBoundingBox::new(
-            self.bounding_box.minimum,
-            self.bounding_box.maximum,
+            self.mesh.bounding_box.minimum,
+            self.mesh.bounding_box.maximum,
         ))
     }
 
@@ -1,21 +1,14 @@
 use rand::Rng;
 use std::f64::consts::PI;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
-use crate::camera::Camera;
-use crate::hittable::Hittable;
+use crate::hittable::{Hittable, Pixel};
 use crate::onb::OrthonormalBasis;
 use crate::utils::random_cosine_direction;
 use crate::vector::Vec3;
 
 pub trait ProbabilityDensityFunction {
-    fn value(
-        &self,
-        direction: &Vec3,
-        camera: &Camera,
-        pixel: Option<(usize, usize)>,
-        zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
-    ) -> f64;
+    fn value(&self, direction: &Vec3, pixel: Option<Pixel>) -> f64;
     fn generate(&self) -> Option<Vec3>;
 }
 
@@ -32,13 +25,7 @@ impl CosinePDF {
 }
 
 impl ProbabilityDensityFunction for CosinePDF {
-    fn value(
-        &self,
-        direction: &Vec3,
-        _camera: &Camera,
-        _pixel: Option<(usize, usize)>,
-        _zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
-    ) -> f64 {
+    fn value(&self, direction: &Vec3, _pixel: Option<Pixel>) -> f64 {
         let cosine = direction.unit().dot(&self.uvw.w());
 
         if cosine <= 0.0 {
@@ -68,15 +55,8 @@ impl HittablePDF {
 }
 
 impl ProbabilityDensityFunction for HittablePDF {
-    fn value(
-        &self,
-        direction: &Vec3,
-        camera: &Camera,
-        pixel: Option<(usize, usize)>,
-        zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
-    ) -> f64 {
-        self.hittable
-            .pdf_value(&self.origin, direction, camera, pixel, Arc::clone(&zbuffer))
+    fn value(&self, direction: &Vec3, pixel: Option<Pixel>) -> f64 {
+        self.hittable.pdf_value(&self.origin, direction, pixel)
     }
 
     fn generate(&self) -> Option<Vec3> {
@@ -95,19 +75,10 @@ impl MixturePDF {
 }
 
 impl ProbabilityDensityFunction for MixturePDF {
-    fn value(
-        &self,
-        direction: &Vec3,
-        camera: &Camera,
-        pixel: Option<(usize, usize)>,
-        zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
-    ) -> f64 {
+    fn value(&self, direction: &Vec3, pixel: Option<Pixel>) -> f64 {
         self.pdfs
             .iter()
-            .map(|pdf| {
-                (1.0 / self.pdfs.len() as f64)
-                    * pdf.value(direction, camera, pixel, Arc::clone(&zbuffer)) as f64
-            })
+            .map(|pdf| (1.0 / self.pdfs.len() as f64) * pdf.value(direction, pixel) as f64)
             .fold(0.0, |acc, x| acc + x)
     }
 
@@ -0,0 +1,520 @@
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::bvh::MeshBVH;
+use crate::colour::Colour;
+use crate::hittable::{HitRecord, Hittable, Pixel};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sphere::{bounding_sphere, get_sphere_uv, Sphere};
+use crate::utils::gamma;
+use crate::vector::Vec3;
+
+// Points as read from a file, with a radius and colour for each one if the file has them
+#[derive(Debug)]
+pub struct Points {
+    pub positions: Vec<Vec3>,
+    pub radii: Option<Vec<f64>>,
+    pub colours: Option<Vec<Colour>>,
+}
+
+// A scan or particle simulation drawn as a sphere at every point. The points get a BVH of
+// their own, so the scene's BVH sees the whole cloud as one object however many there are.
+#[derive(Debug)]
+pub struct PointCloud {
+    centres: Vec<Vec3>,
+    radii: Vec<f64>,
+    colours: Option<Vec<Colour>>,
+    bvh: MeshBVH,
+    bounding_box: Option<AxisAlignedBoundingBox>,
+    material: Box<dyn Material>,
+}
+
+impl PointCloud {
+    // Points without a radius of their own are given `radius`
+    pub fn new(points: Points, radius: f64, material: Box<dyn Material>) -> PointCloud {
+        let radii = points
+            .radii
+            .unwrap_or_else(|| vec![radius; points.positions.len()]);
+
+        let bounds: Vec<AxisAlignedBoundingBox> = points
+            .positions
+            .iter()
+            .zip(&radii)
+            .map(|(centre, &radius)| {
+                let extent = Vec3::new(radius, radius, radius);
+                AxisAlignedBoundingBox::new(centre - extent, centre + extent)
+            })
+            .collect();
+        let bounding_box = bounds
+            .iter()
+            .cloned()
+            .reduce(|bounds, point_bounds| bounds.union(&point_bounds));
+
+        PointCloud {
+            bvh: MeshBVH::build_from_bounds(&bounds),
+            centres: points.positions,
+            radii,
+            colours: points.colours,
+            bounding_box,
+            material,
+        }
+    }
+
+    fn intersect(&self, point: usize, ray: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let (centre, radius) = (self.centres[point], self.radii[point]);
+        let oc = ray.origin - centre;
+        let a = ray.direction.length_squared();
+        let half_b = oc.dot(&ray.direction);
+        let c = oc.length_squared() - radius * radius;
+
+        // Points are often tiny next to their distance from the ray origin, which makes
+        // half_b² - ac cancel badly. It is found from how close the ray passes instead.
+        let closest = oc - (half_b / a) * &ray.direction;
+        let discriminant = a * (radius * radius - closest.length_squared());
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let q = -(half_b + half_b.signum() * discriminant.sqrt());
+        let (mut near, mut far) = (q / a, c / q);
+        if near > far {
+            std::mem::swap(&mut near, &mut far);
+        }
+
+        if near > t_min && near <= t_max {
+            Some(near)
+        } else if far > t_min && far <= t_max {
+            Some(far)
+        } else {
+            None
+        }
+    }
+}
+
+impl Hittable for PointCloud {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        _pixel: Option<Pixel>,
+        _first_ray: bool,
+    ) -> Option<HitRecord<'_>> {
+        let mut closest = None;
+        self.bvh.closest_hit(ray, t_min, t_max, |point, t_max| {
+            let t = self.intersect(point, ray, t_min, t_max)?;
+            closest = Some((point, t));
+            Some(t)
+        });
+        let (point, t) = closest?;
+        let (centre, radius) = (self.centres[point], self.radii[point]);
+
+        // project the hit back onto the sphere the same way a Sphere does
+        let offset = ray.at(t) - centre;
+        let offset = (radius / offset.length()) * &offset;
+        let p = centre + offset;
+        let p_error = gamma(5) * &(offset.abs() + centre.abs());
+
+        let geometric_normal = offset / radius;
+        let front_face = ray.direction.dot(&geometric_normal) < 0.0;
+        let (u, v) = get_sphere_uv(&p, &centre);
+
+        Some(HitRecord {
+            p,
+            t,
+            normal: if front_face {
+                geometric_normal
+            } else {
+                -geometric_normal
+            },
+            tangent: None,
+            bitangent: None,
+            material: &self.material,
+            front_face,
+            u,
+            v,
+            geometric_normal,
+            p_error,
+            colour: self
+                .colours
+                .as_ref()
+                .map(|colours| Colour::copy(&colours[point])),
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.bvh.any_hit(ray, 0.0, t_max, |point| {
+            self.intersect(point, ray, 0.0, t_max).is_some()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        self.bounding_box.clone()
+    }
+
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        match &self.bounding_box {
+            Some(bounding_box) => bounding_sphere(bounding_box),
+            None => panic!("A point cloud without any points can't be a light"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Scalar, String> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(format!("unknown property type {}", name)),
+        })
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // What a colour stored as this type is divided by to bring it into 0 to 1
+    fn colour_scale(&self) -> f64 {
+        match self {
+            Scalar::U8 => 255.0,
+            Scalar::U16 => 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PlyProperty {
+    name: String,
+    scalar: Scalar,
+    // the type of the length in front of a list property
+    list_length: Option<Scalar>,
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+// The data after a PLY header, read one number at a time whatever its format
+enum PlyBody<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary {
+        bytes: &'a [u8],
+        offset: usize,
+        big_endian: bool,
+    },
+}
+
+impl PlyBody<'_> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, String> {
+        match self {
+            PlyBody::Ascii(words) => {
+                let word = words.next().ok_or("the file ends early")?;
+                word.parse::<f64>()
+                    .map_err(|why| format!("{}: {}", word, why))
+            }
+            PlyBody::Binary {
+                bytes,
+                offset,
+                big_endian,
+            } => {
+                let size = scalar.size();
+                let mut data = [0; 8];
+                data[..size].copy_from_slice(
+                    bytes
+                        .get(*offset..*offset + size)
+                        .ok_or("the file ends early")?,
+                );
+                *offset += size;
+                if *big_endian {
+                    data[..size].reverse();
+                }
+
+                let two = [data[0], data[1]];
+                let four = [data[0], data[1], data[2], data[3]];
+                Ok(match scalar {
+                    Scalar::I8 => data[0] as i8 as f64,
+                    Scalar::U8 => data[0] as f64,
+                    Scalar::I16 => i16::from_le_bytes(two) as f64,
+                    Scalar::U16 => u16::from_le_bytes(two) as f64,
+                    Scalar::I32 => i32::from_le_bytes(four) as f64,
+                    Scalar::U32 => u32::from_le_bytes(four) as f64,
+                    Scalar::F32 => f32::from_le_bytes(four) as f64,
+                    Scalar::F64 => f64::from_le_bytes(data),
+                })
+            }
+        }
+    }
+}
+
+// Reads the vertices of a PLY file, ASCII or binary. Each vertex needs x, y and z, and can
+// have a radius and a red, green and blue colour. Colours stored as integers are taken out
+// of their largest value and those stored as floats are used as they are. Any other
+// elements, like faces, are skipped.
+pub fn parse_ply(bytes: &[u8]) -> Result<Points, String> {
+    const END_HEADER: &[u8] = b"end_header";
+    let header_end = bytes
+        .windows(END_HEADER.len())
+        .position(|window| window == END_HEADER)
+        .ok_or("no end_header line")?;
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(bytes.len(), |newline| header_end + newline + 1);
+    let header = std::str::from_utf8(&bytes[..header_end])
+        .map_err(|why| format!("the header is not text: {}", why))?;
+
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err("not a PLY file".to_string());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", name, _version] => format = Some(name.to_string()),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|why| format!("element {}: {}", name, why))?,
+                properties: vec![],
+            }),
+            ["property", "list", length, scalar, name] => elements
+                .last_mut()
+                .ok_or("a property before any element")?
+                .properties
+                .push(PlyProperty {
+                    name: name.to_string(),
+                    scalar: Scalar::parse(scalar)?,
+                    list_length: Some(Scalar::parse(length)?),
+                }),
+            ["property", scalar, name] => elements
+                .last_mut()
+                .ok_or("a property before any element")?
+                .properties
+                .push(PlyProperty {
+                    name: name.to_string(),
+                    scalar: Scalar::parse(scalar)?,
+                    list_length: None,
+                }),
+            _ => {}
+        }
+    }
+
+    let mut body = match format.as_deref() {
+        Some("ascii") => PlyBody::Ascii(
+            std::str::from_utf8(&bytes[body_start..])
+                .map_err(|why| format!("the ASCII data is not text: {}", why))?
+                .split_ascii_whitespace(),
+        ),
+        Some("binary_little_endian") => PlyBody::Binary {
+            bytes,
+            offset: body_start,
+            big_endian: false,
+        },
+        Some("binary_big_endian") => PlyBody::Binary {
+            bytes,
+            offset: body_start,
+            big_endian: true,
+        },
+        Some(format) => return Err(format!("unknown format {}", format)),
+        None => return Err("no format line".to_string()),
+    };
+
+    for element in &elements {
+        if element.name != "vertex" {
+            for _ in 0..element.count {
+                for property in &element.properties {
+                    let count = match property.list_length {
+                        Some(length) => body.read(length)? as usize,
+                        None => 1,
+                    };
+                    for _ in 0..count {
+                        body.read(property.scalar)?;
+                    }
+                }
+            }
+            continue;
+        }
+
+        let find = |name: &str| {
+            element
+                .properties
+                .iter()
+                .position(|property| property.name == name)
+        };
+        let (x, y, z) = match (find("x"), find("y"), find("z")) {
+            (Some(x), Some(y), Some(z)) => (x, y, z),
+            _ => return Err("the vertices need x, y and z properties".to_string()),
+        };
+        let radius = find("radius");
+        let colour = match (find("red"), find("green"), find("blue")) {
+            (Some(r), Some(g), Some(b)) => Some((r, g, b)),
+            _ => None,
+        };
+
+        let mut points = Points {
+            positions: Vec::with_capacity(element.count),
+            radii: radius.map(|_| Vec::with_capacity(element.count)),
+            colours: colour.map(|_| Vec::with_capacity(element.count)),
+        };
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            for (value, property) in values.iter_mut().zip(&element.properties) {
+                if let Some(length) = property.list_length {
+                    for _ in 0..body.read(length)? as usize {
+                        body.read(property.scalar)?;
+                    }
+                } else {
+                    *value = body.read(property.scalar)?;
+                }
+            }
+
+            points
+                .positions
+                .push(Vec3::new(values[x], values[y], values[z]));
+            if let (Some(radii), Some(radius)) = (points.radii.as_mut(), radius) {
+                radii.push(values[radius]);
+            }
+            if let (Some(colours), Some((r, g, b))) = (points.colours.as_mut(), colour) {
+                let scale =
+                    |index: usize| values[index] / element.properties[index].scalar.colour_scale();
+                colours.push(Colour::new(scale(r), scale(g), scale(b)));
+            }
+        }
+
+        return Ok(points);
+    }
+
+    Err("no vertex element".to_string())
+}
+
+// Reads points from comma separated values, one point to a line. A first line of names says
+// which column is which, from x, y, z, radius and r, g, b (or red, green, blue). Without
+// one the columns are x y z, x y z radius, x y z r g b or x y z radius r g b by how many
+// there are. Colours go from 0 to 1, unless one is over 1 when they are all taken to go up
+// to 255. Blank lines and lines starting with # are skipped.
+pub fn parse_csv(text: &str) -> Result<Points, String> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(line_number, line)| (line_number + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .peekable();
+
+    let split = |line: &str| -> Vec<String> {
+        line.split(',')
+            .map(|value| value.trim().to_lowercase())
+            .collect()
+    };
+
+    let mut columns = [None; 7];
+    let first = match lines.peek() {
+        Some((_, line)) => split(line),
+        None => return Err("there are no points".to_string()),
+    };
+    if first[0].parse::<f64>().is_err() {
+        for (index, name) in first.iter().enumerate() {
+            let column = match name.as_str() {
+                "x" => 0,
+                "y" => 1,
+                "z" => 2,
+                "radius" => 3,
+                "r" | "red" => 4,
+                "g" | "green" => 5,
+                "b" | "blue" => 6,
+                _ => continue,
+            };
+            columns[column] = Some(index);
+        }
+        lines.next();
+    } else {
+        let order: &[usize] = match first.len() {
+            3 => &[0, 1, 2],
+            4 => &[0, 1, 2, 3],
+            6 => &[0, 1, 2, 4, 5, 6],
+            7 => &[0, 1, 2, 3, 4, 5, 6],
+            count => return Err(format!("line 1: can't tell what {} columns are", count)),
+        };
+        for (index, &column) in order.iter().enumerate() {
+            columns[column] = Some(index);
+        }
+    }
+
+    let (x, y, z) = match (columns[0], columns[1], columns[2]) {
+        (Some(x), Some(y), Some(z)) => (x, y, z),
+        _ => return Err("the points need x, y and z columns".to_string()),
+    };
+    let colour = match (columns[4], columns[5], columns[6]) {
+        (Some(r), Some(g), Some(b)) => Some((r, g, b)),
+        _ => None,
+    };
+
+    let mut points = Points {
+        positions: vec![],
+        radii: columns[3].map(|_| vec![]),
+        colours: colour.map(|_| vec![]),
+    };
+    for (line_number, line) in lines {
+        let values = line
+            .split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|why| format!("line {}: {}", line_number, why))?;
+        let value = |index: usize| {
+            values.get(index).copied().ok_or(format!(
+                "line {}: {} values is too few",
+                line_number,
+                values.len()
+            ))
+        };
+
+        points
+            .positions
+            .push(Vec3::new(value(x)?, value(y)?, value(z)?));
+        if let (Some(radii), Some(radius)) = (points.radii.as_mut(), columns[3]) {
+            radii.push(value(radius)?);
+        }
+        if let (Some(colours), Some((r, g, b))) = (points.colours.as_mut(), colour) {
+            colours.push(Colour::new(value(r)?, value(g)?, value(b)?));
+        }
+    }
+
+    if let Some(colours) = points.colours.as_mut() {
+        if colours.iter().any(|c| c.r > 1.0 || c.g > 1.0 || c.b > 1.0) {
+            for colour in colours.iter_mut() {
+                *colour = Colour::new(colour.r / 255.0, colour.g / 255.0, colour.b / 255.0);
+            }
+        }
+    }
+
+    Ok(points)
+}
@@ -0,0 +1,293 @@
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::hittable::{HitRecord, Hittable, Pixel};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sphere::{bounding_sphere, Sphere};
+use crate::utils::{gamma, quadratic};
+use crate::vector::Vec3;
+
+use std::f64::consts::PI;
+
+// Which surface of a cylinder or cone a ray hit
+#[derive(Debug, Copy, Clone)]
+enum Part {
+    Side,
+    Base,
+    Top,
+}
+
+// A tube around the y axis, running from y = 0 up to the height. Its ends are open unless
+// it is capped, which makes it a closed solid.
+#[derive(Debug)]
+pub struct Cylinder {
+    pub radius: f64,
+    pub height: f64,
+    pub capped: bool,
+    pub material: Box<dyn Material>,
+}
+
+impl Cylinder {
+    pub fn new(radius: f64, height: f64, capped: bool, material: Box<dyn Material>) -> Cylinder {
+        Cylinder {
+            radius,
+            height,
+            capped,
+            material,
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, Part)> {
+        let (o, d) = (&ray.origin, &ray.direction);
+        let a = d.x * d.x + d.z * d.z;
+        let b = 2.0 * (d.x * o.x + d.z * o.z);
+        let c = o.x * o.x + o.z * o.z - self.radius * self.radius;
+
+        let side = quadratic(a, b, c).and_then(|(t0, t1)| {
+            [t0, t1].into_iter().find(|&t| {
+                let y = o.y + t * d.y;
+                t > t_min && t <= t_max && (0.0..=self.height).contains(&y)
+            })
+        });
+
+        let mut closest = side.map(|t| (t, Part::Side));
+        if self.capped {
+            for (y, part) in [(0.0, Part::Base), (self.height, Part::Top)] {
+                if let Some(t) = cap(ray, y, self.radius, t_min, t_max) {
+                    if closest.is_none_or(|(closest, _)| t < closest) {
+                        closest = Some((t, part));
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        _pixel: Option<Pixel>,
+        _first_ray: bool,
+    ) -> Option<HitRecord<'_>> {
+        let (t, part) = self.intersect(ray, t_min, t_max)?;
+
+        let mut p = ray.at(t);
+        let (p_error, geometric_normal, v) = match part {
+            Part::Side => {
+                // push the hit back out onto the tube, the same way the sphere does
+                let scale = self.radius / (p.x * p.x + p.z * p.z).sqrt();
+                p.x *= scale;
+                p.z *= scale;
+                let mut p_error = gamma(3) * &p.abs();
+                p_error.y = gamma(3) * (ray.origin.y.abs() + (t * ray.direction.y).abs());
+
+                let normal = Vec3::new(p.x, 0.0, p.z) / self.radius;
+                (p_error, normal, p.y / self.height)
+            }
+            Part::Base => cap_surface(ray, t, &mut p, 0.0, -1.0, self.radius),
+            Part::Top => cap_surface(ray, t, &mut p, self.height, 1.0, self.radius),
+        };
+
+        let phi = p.z.atan2(p.x).rem_euclid(2.0 * PI);
+        let u = phi / (2.0 * PI);
+
+        Some(hit_record(
+            ray,
+            t,
+            p,
+            p_error,
+            geometric_normal,
+            (u, v),
+            &self.material,
+        ))
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.intersect(ray, 0.0, t_max).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        Some(AxisAlignedBoundingBox::new(
+            Vec3::new(-self.radius, 0.0, -self.radius),
+            Vec3::new(self.radius, self.height, self.radius),
+        ))
+    }
+
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        bounding_sphere(&self.bounding_box().unwrap())
+    }
+}
+
+// A cone around the y axis with its base of the given radius at y = 0 and its tip at the
+// height. Like the cylinder its base is open unless it is capped.
+#[derive(Debug)]
+pub struct Cone {
+    pub radius: f64,
+    pub height: f64,
+    pub capped: bool,
+    pub material: Box<dyn Material>,
+}
+
+impl Cone {
+    pub fn new(radius: f64, height: f64, capped: bool, material: Box<dyn Material>) -> Cone {
+        Cone {
+            radius,
+            height,
+            capped,
+            material,
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, Part)> {
+        // x^2 + z^2 = k (y - height)^2
+        let k = (self.radius / self.height).powi(2);
+        let (o, d) = (&ray.origin, &ray.direction);
+        let oy = o.y - self.height;
+        let a = d.x * d.x + d.z * d.z - k * d.y * d.y;
+        let b = 2.0 * (d.x * o.x + d.z * o.z - k * d.y * oy);
+        let c = o.x * o.x + o.z * o.z - k * oy * oy;
+
+        let side = quadratic(a, b, c).and_then(|(t0, t1)| {
+            [t0, t1].into_iter().find(|&t| {
+                let y = o.y + t * d.y;
+                t > t_min && t <= t_max && (0.0..=self.height).contains(&y)
+            })
+        });
+
+        let mut closest = side.map(|t| (t, Part::Side));
+        if self.capped {
+            if let Some(t) = cap(ray, 0.0, self.radius, t_min, t_max) {
+                if closest.is_none_or(|(closest, _)| t < closest) {
+                    closest = Some((t, Part::Base));
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        _pixel: Option<Pixel>,
+        _first_ray: bool,
+    ) -> Option<HitRecord<'_>> {
+        let (t, part) = self.intersect(ray, t_min, t_max)?;
+
+        let mut p = ray.at(t);
+        let (p_error, geometric_normal, v) = match part {
+            Part::Side => {
+                let p_error = gamma(5) * &(ray.origin.abs() + (t * &ray.direction).abs());
+                let k = (self.radius / self.height).powi(2);
+                let normal = Vec3::new(p.x, k * (self.height - p.y), p.z).unit();
+                (p_error, normal, p.y / self.height)
+            }
+            // the tip is a point, so a cone only ever has a base cap
+            Part::Base | Part::Top => cap_surface(ray, t, &mut p, 0.0, -1.0, self.radius),
+        };
+
+        let phi = p.z.atan2(p.x).rem_euclid(2.0 * PI);
+        let u = phi / (2.0 * PI);
+
+        Some(hit_record(
+            ray,
+            t,
+            p,
+            p_error,
+            geometric_normal,
+            (u, v),
+            &self.material,
+        ))
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.intersect(ray, 0.0, t_max).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        Some(AxisAlignedBoundingBox::new(
+            Vec3::new(-self.radius, 0.0, -self.radius),
+            Vec3::new(self.radius, self.height, self.radius),
+        ))
+    }
+
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        bounding_sphere(&self.bounding_box().unwrap())
+    }
+}
+
+// Where the ray crosses a flat disk cap of the given radius at height y
+fn cap(ray: &Ray, y: f64, radius: f64, t_min: f64, t_max: f64) -> Option<f64> {
+    let t = (y - ray.origin.y) / ray.direction.y;
+    if t <= t_min || t > t_max || t.is_nan() {
+        return None;
+    }
+
+    let x = ray.origin.x + t * ray.direction.x;
+    let z = ray.origin.z + t * ray.direction.z;
+    if x * x + z * z > radius * radius {
+        return None;
+    }
+
+    Some(t)
+}
+
+// Error bound, outward normal and v for a hit on a cap, which lies exactly at its height
+// and faces up or down the y axis. v runs from the rim in to the middle.
+fn cap_surface(
+    ray: &Ray,
+    t: f64,
+    p: &mut Vec3,
+    height: f64,
+    facing: f64,
+    radius: f64,
+) -> (Vec3, Vec3, f64) {
+    let mut p_error = gamma(3) * &(ray.origin.abs() + (t * &ray.direction).abs());
+    p.y = height;
+    p_error.y = 0.0;
+
+    let r = (p.x * p.x + p.z * p.z).sqrt();
+    (p_error, Vec3::new(0.0, facing, 0.0), 1.0 - r / radius)
+}
+
+// hit records hold on to the boxed material
+#[allow(clippy::borrowed_box)]
+fn hit_record<'a>(
+    ray: &Ray,
+    t: f64,
+    p: Vec3,
+    p_error: Vec3,
+    geometric_normal: Vec3,
+    (u, v): (f64, f64),
+    material: &'a Box<dyn Material>,
+) -> HitRecord<'a> {
+    let front_face = ray.direction.dot(&geometric_normal) < 0.0;
+    let normal = if front_face {
+        geometric_normal
+    } else {
+        -geometric_normal
+    };
+
+    HitRecord {
+        p,
+        t,
+        normal,
+        tangent: None,
+        bitangent: None,
+        material,
+        front_face,
+        u,
+        v,
+        geometric_normal,
+        p_error,
+        colour: None,
+    }
+}
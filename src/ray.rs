@@ -1,14 +1,17 @@
+use crate::simd::{F64x4, Mask4, LANES};
 use crate::vector::Vec3;
 
+#[derive(Copy, Clone)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
     pub inverse_direction: Vec3,
     pub sign: [usize; 3],
+    pub time: f64,
 }
 
 impl Ray {
-    pub fn new(origin: Vec3, direction: Vec3) -> Ray {
+    pub fn new(origin: Vec3, direction: Vec3, time: f64) -> Ray {
         // these are optimizations from scratchapixel for AABB intersection
         let inverse_direction = Vec3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
         let mut sign = [0, 0, 0];
@@ -21,6 +24,7 @@ impl Ray {
             direction,
             inverse_direction,
             sign,
+            time,
         }
     }
 
@@ -31,3 +35,64 @@ impl Ray {
         a + t * &b
     }
 }
+
+// Up to LANES rays traced together, with their origins and directions laid out one axis
+// per register. Packets are only walked as a group when every ray is heading the same
+// way, which is nearly always true of neighbouring camera rays.
+pub struct RayPacket {
+    pub rays: [Ray; LANES],
+    pub len: usize,
+    pub origin: [F64x4; 3],
+    pub direction: [F64x4; 3],
+    pub inverse_direction: [F64x4; 3],
+    // whether the rays share their direction signs and their largest direction component
+    pub coherent: bool,
+    pub sign: [usize; 3],
+    pub dominant_axis: usize,
+}
+
+impl RayPacket {
+    // Lanes past the end of `rays` repeat the last ray and their results are ignored
+    pub fn new(rays: &[Ray]) -> RayPacket {
+        if rays.is_empty() || rays.len() > LANES {
+            panic!("A ray packet holds 1 to {} rays, not {}", LANES, rays.len());
+        }
+
+        let len = rays.len();
+        let rays: [Ray; LANES] = std::array::from_fn(|lane| rays[lane.min(len - 1)]);
+        let axis = |v: fn(&Ray) -> Vec3, axis: usize| F64x4::new(rays.map(|ray| v(&ray).get(axis)));
+        let per_axis = |v: fn(&Ray) -> Vec3| [axis(v, 0), axis(v, 1), axis(v, 2)];
+
+        let dominant_axis = max_dimension(&rays[0].direction.abs());
+        let coherent = rays[1..len].iter().all(|ray| {
+            ray.sign == rays[0].sign && max_dimension(&ray.direction.abs()) == dominant_axis
+        });
+
+        RayPacket {
+            origin: per_axis(|ray| ray.origin),
+            direction: per_axis(|ray| ray.direction),
+            inverse_direction: per_axis(|ray| ray.inverse_direction),
+            coherent,
+            sign: rays[0].sign,
+            dominant_axis,
+            rays,
+            len,
+        }
+    }
+
+    // Lanes that hold one of the packet's rays
+    pub fn active(&self) -> Mask4 {
+        Mask4::first(self.len)
+    }
+}
+
+// Axis of the largest component, preferring later axes on ties
+pub fn max_dimension(v: &Vec3) -> usize {
+    if v.x > v.y && v.x > v.z {
+        0
+    } else if v.y > v.z {
+        1
+    } else {
+        2
+    }
+}
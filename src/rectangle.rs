@@ -1,15 +1,12 @@
 use crate::aabb::AxisAlignedBoundingBox;
-use crate::camera::Camera;
-use crate::colour::Colour;
-use crate::hittable::{HitRecord, Hittable, HittableList};
-use crate::material::Lambertian;
+use crate::hittable::{HitRecord, Hittable, HittableList, Pixel};
 use crate::material::Material;
 use crate::ray::Ray;
-use crate::texture::SolidColour;
+use crate::sphere::{bounding_sphere, Sphere};
+use crate::utils::gamma;
 use crate::vector::Vec3;
 
 use rand::Rng;
-use std::sync::{Arc, Mutex};
 
 #[derive(Debug)]
 pub enum PlaneOrientation {
@@ -46,26 +43,16 @@ impl Plane {
             orientation,
         }
     }
-}
 
-impl Hittable for Plane {
-    fn hit(
-        &self,
-        ray: &crate::ray::Ray,
-        _camera: &crate::camera::Camera,
-        t_min: f64,
-        t_max: f64,
-        _pixel: Option<(usize, usize)>,
-        _zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
-        _first_ray: bool,
-    ) -> Option<crate::hittable::HitRecord> {
+    // Distance along the ray to the hit and where on the plane it lands
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
         let t = match &self.orientation {
             PlaneOrientation::XY => (self.k - ray.origin.z) / ray.direction.z,
             PlaneOrientation::XZ => (self.k - ray.origin.y) / ray.direction.y,
             PlaneOrientation::YZ => (self.k - ray.origin.x) / ray.direction.x,
         };
 
-        if t < t_min || t > t_max {
+        if t <= t_min || t > t_max {
             return None;
         }
 
@@ -83,6 +70,21 @@ impl Hittable for Plane {
             return None;
         }
 
+        Some((t, a, b))
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(
+        &self,
+        ray: &crate::ray::Ray,
+        t_min: f64,
+        t_max: f64,
+        _pixel: Option<Pixel>,
+        _first_ray: bool,
+    ) -> Option<crate::hittable::HitRecord<'_>> {
+        let (t, a, b) = self.intersect(ray, t_min, t_max)?;
+
         let u = (a - self.a0) / (self.a1 - self.a0);
         let v = (b - self.b0) / (self.b1 - self.b0);
 
@@ -91,8 +93,18 @@ impl Hittable for Plane {
             PlaneOrientation::XZ => Vec3::new(0.0, 1.0, 0.0),
             PlaneOrientation::YZ => Vec3::new(1.0, 0.0, 0.0),
         };
-        let p = ray.at(t);
+        // the hit lies exactly on the plane, so only the other two axes have any error
+        let mut p = ray.at(t);
+        let mut p_error = gamma(3) * &(ray.origin.abs() + (t * &ray.direction).abs());
+        let axis = match &self.orientation {
+            PlaneOrientation::XY => 2,
+            PlaneOrientation::XZ => 1,
+            PlaneOrientation::YZ => 0,
+        };
+        p.set(axis, self.k);
+        p_error.set(axis, 0.0);
 
+        let geometric_normal = outward_normal;
         let n_norm = outward_normal.unit();
         let mut front_face = true;
         if ray.direction.dot(&n_norm) > 0.0 {
@@ -110,24 +122,22 @@ impl Hittable for Plane {
             front_face,
             u,
             v,
+            geometric_normal,
+            p_error,
+            colour: None,
         })
     }
 
-    fn pdf_value(
-        &self,
-        origin: &Vec3,
-        v: &Vec3,
-        camera: &Camera,
-        pixel: Option<(usize, usize)>,
-        zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
-    ) -> f64 {
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.intersect(ray, 0.0, t_max).is_some()
+    }
+
+    fn pdf_value(&self, origin: &Vec3, v: &Vec3, pixel: Option<Pixel>) -> f64 {
         if let Some(hit) = self.hit(
-            &Ray::new(*origin, *v),
-            camera,
+            &Ray::new(*origin, *v, 0.0),
             0.0001,
             f64::INFINITY,
             pixel,
-            zbuffer,
             false,
         ) {
             let area = (self.a1 - self.a0) * (self.b1 - self.b0);
@@ -151,7 +161,93 @@ impl Hittable for Plane {
     }
 
     fn bounding_box(&self) -> Option<crate::aabb::AxisAlignedBoundingBox> {
-        None
+        let (minimum, maximum) = match &self.orientation {
+            PlaneOrientation::XY => (
+                Vec3::new(self.a0, self.b0, self.k),
+                Vec3::new(self.a1, self.b1, self.k),
+            ),
+            PlaneOrientation::XZ => (
+                Vec3::new(self.a0, self.k, self.b0),
+                Vec3::new(self.a1, self.k, self.b1),
+            ),
+            PlaneOrientation::YZ => (
+                Vec3::new(self.k, self.a0, self.b0),
+                Vec3::new(self.k, self.a1, self.b1),
+            ),
+        };
+
+        Some(AxisAlignedBoundingBox::new(minimum, maximum))
+    }
+
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        bounding_sphere(&self.bounding_box().unwrap())
+    }
+}
+
+// The y = 0 plane facing up the y axis. It has no edges, so its texture repeats every unit
+// along x and z and it can't be used as a light.
+#[derive(Debug)]
+pub struct InfinitePlane {
+    pub material: Box<dyn Material>,
+}
+
+impl InfinitePlane {
+    pub fn new(material: Box<dyn Material>) -> InfinitePlane {
+        InfinitePlane { material }
+    }
+
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let t = -ray.origin.y / ray.direction.y;
+        if t <= t_min || t > t_max || t.is_nan() {
+            return None;
+        }
+
+        Some(t)
+    }
+}
+
+impl Hittable for InfinitePlane {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        _pixel: Option<Pixel>,
+        _first_ray: bool,
+    ) -> Option<HitRecord<'_>> {
+        let t = self.intersect(ray, t_min, t_max)?;
+
+        let mut p = ray.at(t);
+        let mut p_error = gamma(3) * &(ray.origin.abs() + (t * &ray.direction).abs());
+        p.y = 0.0;
+        p_error.y = 0.0;
+
+        let geometric_normal = Vec3::new(0.0, 1.0, 0.0);
+        let front_face = ray.direction.y < 0.0;
+        let normal = if front_face {
+            geometric_normal
+        } else {
+            -geometric_normal
+        };
+
+        Some(HitRecord {
+            p,
+            t,
+            normal,
+            tangent: None,
+            bitangent: None,
+            material: &self.material,
+            front_face,
+            u: p.x - p.x.floor(),
+            v: p.z - p.z.floor(),
+            geometric_normal,
+            p_error,
+            colour: None,
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.intersect(ray, 0.0, t_max).is_some()
     }
 }
 
@@ -162,64 +258,50 @@ pub struct Cube {
 }
 
 impl Cube {
-    pub fn new(box_min: Vec3, box_max: Vec3, colour: Colour) -> Cube {
+    // Every side gets its own copy of the material from make_material
+    pub fn new(
+        box_min: Vec3,
+        box_max: Vec3,
+        make_material: impl Fn() -> Box<dyn Material>,
+    ) -> Cube {
         let mut sides = HittableList::new();
 
-        let side_colour_1 = Lambertian {
-            albedo: Box::new(SolidColour::new(Colour::copy(&colour), None, None)),
-        };
-        let side_colour_2 = Lambertian {
-            albedo: Box::new(SolidColour::new(Colour::copy(&colour), None, None)),
-        };
-        let side_colour_3 = Lambertian {
-            albedo: Box::new(SolidColour::new(Colour::copy(&colour), None, None)),
-        };
-        let side_colour_4 = Lambertian {
-            albedo: Box::new(SolidColour::new(Colour::copy(&colour), None, None)),
-        };
-        let side_colour_5 = Lambertian {
-            albedo: Box::new(SolidColour::new(Colour::copy(&colour), None, None)),
-        };
-        let side_colour_6 = Lambertian {
-            albedo: Box::new(SolidColour::new(Colour::copy(&colour), None, None)),
-        };
-
         sides.objects.push(Box::new(Plane::new(
             (box_min.x, box_max.x, box_min.y, box_max.y),
             box_max.z,
-            Box::new(side_colour_1),
+            make_material(),
             PlaneOrientation::XY,
         )));
         sides.objects.push(Box::new(Plane::new(
             (box_min.x, box_max.x, box_min.y, box_max.y),
             box_min.z,
-            Box::new(side_colour_2),
+            make_material(),
             PlaneOrientation::XY,
         )));
 
         sides.objects.push(Box::new(Plane::new(
             (box_min.x, box_max.x, box_min.z, box_max.z),
             box_max.y,
-            Box::new(side_colour_3),
+            make_material(),
             PlaneOrientation::XZ,
         )));
         sides.objects.push(Box::new(Plane::new(
             (box_min.x, box_max.x, box_min.z, box_max.z),
             box_min.y,
-            Box::new(side_colour_4),
+            make_material(),
             PlaneOrientation::XZ,
         )));
 
         sides.objects.push(Box::new(Plane::new(
             (box_min.y, box_max.y, box_min.z, box_max.z),
             box_max.x,
-            Box::new(side_colour_5),
+            make_material(),
             PlaneOrientation::YZ,
         )));
         sides.objects.push(Box::new(Plane::new(
             (box_min.y, box_max.y, box_min.z, box_max.z),
             box_min.x,
-            Box::new(side_colour_6),
+            make_material(),
             PlaneOrientation::YZ,
         )));
 
@@ -236,22 +318,16 @@ impl Hittable for Cube {
     fn hit(
         &self,
         ray: &crate::ray::Ray,
-        camera: &crate::camera::Camera,
         t_min: f64,
         t_max: f64,
-        pixel: Option<(usize, usize)>,
-        zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
+        pixel: Option<Pixel>,
         first_ray: bool,
-    ) -> Option<HitRecord> {
-        self.sides.hit(
-            ray,
-            camera,
-            t_min,
-            t_max,
-            pixel,
-            Arc::clone(&zbuffer),
-            first_ray,
-        )
+    ) -> Option<HitRecord<'_>> {
+        self.sides.hit(ray, t_min, t_max, pixel, first_ray)
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.sides.occluded(ray, t_max)
     }
 
     fn bounding_box(&self) -> Option<crate::aabb::AxisAlignedBoundingBox> {
@@ -260,4 +336,8 @@ impl Hittable for Cube {
             self.bounding_box.maximum,
         ))
     }
+
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        bounding_sphere(&self.bounding_box)
+    }
 }
@@ -1,38 +1,74 @@
 use rand::seq::IteratorRandom;
-use std::io::BufReader;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, Mutex};
-use std::{fs::File, io::Read};
+use std::time::{Duration, Instant};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
 
 use image::{DynamicImage, Rgba, RgbaImage};
-use obj::{load_obj, Obj, TexturedVertex};
+use obj::raw::parse_obj;
+use obj::{Obj, TexturedVertex};
 use rayon::prelude::*;
 
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::bvh::ObjectBVH;
 use crate::bxdf::MicrofacetReflection;
-use crate::camera::Camera;
+use crate::cache::{cache_key, DiskCache};
+use crate::camera::{Camera, StereoCamera, StereoLayout};
 use crate::colour::Colour;
-use crate::hittable::{Hittable, HittableList};
+use crate::csg::{Csg, CsgOperation};
+use crate::curve::{parse_curves, CurveType, Curves};
+use crate::disk::Disk;
+use crate::displacement::{displace, Displacement};
+use crate::gltf_import::{
+    camera as gltf_camera, has_triangles, light as gltf_light, material_name, GltfFile,
+    PunctualLight, Sun,
+};
+use crate::hair::Hair;
+use crate::heightfield::Heightfield;
+use crate::hittable::{HitRecord, Hittable, Pixel};
+use crate::instance::{Moving, Transform};
 use crate::json::*;
 use crate::material::{
-    Dielectric, Glossy, Isotropic, Lambertian, Light, Material, Metal, MicrofacetReflectance,
-    SpecularReflectance,
+    Cutout, Dielectric, Glossy, Isotropic, Lambertian, Light, Material, Metal,
+    MicrofacetReflectance, SpecularReflectance,
 };
-use crate::object::Object;
+use crate::mtl::{parse_mtl, MtlMaterial};
+use crate::object::{AcceleratorType, Mesh, MeshMaterials, Object};
+use crate::onb::OrthonormalBasis;
 use crate::pdf::CosinePDF;
 use crate::pdf::{HittablePDF, MixturePDF, ProbabilityDensityFunction};
-use crate::ray::Ray;
-use crate::rectangle::Cube;
+use crate::points::{parse_csv, parse_ply, PointCloud};
+use crate::quadric::{Cone, Cylinder};
+use crate::ray::{Ray, RayPacket};
+use crate::rectangle::{Cube, InfinitePlane, Plane, PlaneOrientation};
+use crate::sdf::{Sdf, SdfShape};
+use crate::simd::LANES;
 use crate::sphere::Sphere;
-use crate::texture::{ImageTexture, SolidColour, Texture};
-use crate::utils::random_in_unit_sphere;
+use crate::subdivision::subdivide;
+use crate::texture::{bump_to_normal_map, ImageTexture, SolidColour, Texture, VertexColour};
+use crate::torus::Torus;
+use crate::transform::{AnimatedTransform, Decomposed, Matrix4, Quaternion};
+use crate::triangle::VertexPrecision;
+use crate::triangulate::{face_materials, triangulate};
+use crate::utils::{distance, random_cosine_direction, random_in_unit_sphere};
 use crate::vector::Vec3;
 use crate::volume::Volume;
 
 const INFINITY: f64 = f64::INFINITY;
 const MAX_RAY_DEPTH: u32 = 10;
 
+// Name given to the scene's unnamed `camera`
+pub const DEFAULT_CAMERA: &str = "default";
+// Selects every camera in the scene
+pub const ALL_CAMERAS: &str = "all";
+
 pub struct Scene {
-    pub camera: Camera,
-    pub objects: HittableList,
+    pub cameras: Vec<SceneCamera>,
+    pub objects: ObjectBVH,
     pub lights: Vec<Arc<Box<dyn Hittable>>>,
     pub skybox: Option<Sphere>,
     pub render_settings: RenderSettings,
@@ -42,46 +78,284 @@ pub struct RenderSettings {
     pub samples: u32,
     pub image_width: u32,
     pub image_height: u32,
+    pub ambient_occlusion: Option<AmbientOcclusion>,
 }
 
-impl Scene {
-    pub fn new(filename: String) -> Scene {
-        if filename.is_empty() {
-            panic!("Empty scene filename!");
+// Renders an ambient occlusion pass instead of lighting the scene. Each camera hit casts
+// `samples` rays and counts those that travel `distance` without hitting anything.
+pub struct AmbientOcclusion {
+    pub distance: f64,
+    pub samples: u32,
+}
+
+pub struct SceneCamera {
+    pub name: String,
+    pub camera: Camera,
+    pub stereo: Option<StereoCamera>,
+}
+
+impl SceneCamera {
+    // Size of the rendered image. Stereo rigs pack both eyes into a single image
+    pub fn image_dimensions(&self, render_settings: &RenderSettings) -> (u32, u32) {
+        let width = render_settings.image_width;
+        let height = render_settings.image_height;
+
+        match &self.stereo {
+            Some(stereo) => stereo.image_dimensions(width, height),
+            None => (width, height),
         }
+    }
+
+    // Jittered camera ray through pixel (i, j) of the rendered image
+    fn get_ray(&self, i: u32, j: u32, render_settings: &RenderSettings) -> Ray {
+        let image_width = render_settings.image_width;
+        let image_height = render_settings.image_height;
 
-        let mut file = match File::open(&filename) {
-            Err(why) => panic!("Error opening {}: {}", &filename, why),
-            Ok(file) => file,
+        let (camera, i, j) = match &self.stereo {
+            Some(stereo) => stereo.eye_pixel(i, j, image_width, image_height),
+            None => (&self.camera, i, j),
         };
 
-        let mut s = String::new();
-        if let Err(why) = file.read_to_string(&mut s) {
-            panic!("Error converting file contents to string: {}", why);
+        let u = (i as f64 + rand::random::<f64>()) / (image_width - 1) as f64;
+        let v = (j as f64 + rand::random::<f64>()) / (image_height - 1) as f64;
+
+        camera.get_ray(u, v)
+    }
+}
+
+// The file and settings a model was loaded with, which pick it out in the cache
+type MeshKey = (
+    String,
+    bool,
+    AcceleratorType,
+    VertexPrecision,
+    u32,
+    Option<Displacement>,
+);
+
+// Models loaded so far, keyed by their path, shading, acceleration structure, vertex
+// precision, subdivision and displacement. Rebuilding a scene with the same cache, ie for
+// every frame of an animation, reuses the parsed models and their trees. With a disk cache,
+// built meshes are also kept between runs.
+#[derive(Default)]
+pub struct MeshCache {
+    meshes: HashMap<MeshKey, Arc<Mesh>>,
+    disk_cache: Option<DiskCache>,
+}
+
+impl MeshCache {
+    pub fn with_disk_cache(cache_dir: &str) -> MeshCache {
+        MeshCache {
+            meshes: HashMap::new(),
+            disk_cache: Some(DiskCache::new(cache_dir)),
+        }
+    }
+
+    pub fn load(
+        &mut self,
+        obj_path: &str,
+        shade_smooth: bool,
+        accelerator: AcceleratorType,
+        precision: VertexPrecision,
+        subdivision: u32,
+        displacement: Option<&Displacement>,
+    ) -> Arc<Mesh> {
+        let key = (
+            obj_path.to_string(),
+            shade_smooth,
+            accelerator,
+            precision,
+            subdivision,
+            displacement.cloned(),
+        );
+        if let Some(mesh) = self.meshes.get(&key) {
+            return Arc::clone(mesh);
+        }
+
+        let obj = match fs::read(obj_path) {
+            Err(why) => panic!("Error opening obj {} :{}", obj_path, why),
+            Ok(obj) => obj,
+        };
+
+        let height_image =
+            displacement.map(|displacement| match fs::read(&displacement.height_path) {
+                Err(why) => panic!(
+                    "Error opening height texture {}: {}",
+                    displacement.height_path, why
+                ),
+                Ok(height_image) => height_image,
+            });
+
+        let cache_key = cache_key(
+            &obj,
+            shade_smooth,
+            accelerator,
+            precision,
+            subdivision,
+            displacement.zip(height_image.as_deref()),
+        );
+        let start = Instant::now();
+        if let Some(mesh) = self
+            .disk_cache
+            .as_ref()
+            .and_then(|disk_cache| disk_cache.load(cache_key))
+        {
+            println!(
+                "Loaded {:?} for {} ({} triangles) from the cache in {:.2?}",
+                accelerator,
+                obj_path,
+                mesh.triangle_count,
+                start.elapsed()
+            );
+            let mesh = Arc::new(mesh);
+            self.meshes.insert(key, Arc::clone(&mesh));
+            return mesh;
+        }
+
+        // faces can have any number of corners, so they are read as they are in the file
+        // and then subdivided or split into triangles
+        let raw = match parse_obj(&obj[..]) {
+            Err(why) => panic!("Could not load model {}: {}", obj_path, why),
+            Ok(raw) => raw,
+        };
+        let (names, face_slots) = face_materials(&raw);
+        let libraries = raw.material_libraries.clone();
+        let (object, faces): (Obj<TexturedVertex, u32>, Vec<u32>) = if subdivision > 0 {
+            subdivide(raw, subdivision)
+        } else {
+            match triangulate(raw) {
+                Err(why) => panic!("Could not load model {}: {}", obj_path, why),
+                Ok(model) => model,
+            }
+        };
+
+        let (object, faces) = match (displacement, &height_image) {
+            (Some(displacement), Some(height_image)) => {
+                match image::load_from_memory(height_image) {
+                    Err(why) => panic!(
+                        "Error opening height texture {}: {}",
+                        displacement.height_path, why
+                    ),
+                    Ok(height_image) => displace(object, &faces, displacement, &height_image),
+                }
+            }
+            _ => (object, faces),
+        };
+
+        // a mesh with one material needs no slot for each triangle
+        let triangles = if names.len() > 1 {
+            faces
+                .iter()
+                .map(|&face| face_slots[face as usize])
+                .collect()
+        } else {
+            vec![]
+        };
+        let materials = MeshMaterials {
+            names,
+            libraries,
+            triangles,
         };
 
-        let scene: SceneJSON = match serde_json::from_str(&s) {
+        let mesh = Mesh::new(object, materials, shade_smooth, accelerator, precision);
+        println!(
+            "Built {:?} for {} ({} triangles) in {:.2?}",
+            accelerator, obj_path, mesh.triangle_count, mesh.build_time
+        );
+        if let Some(disk_cache) = &self.disk_cache {
+            disk_cache.store(cache_key, &mesh);
+        }
+
+        let mesh = Arc::new(mesh);
+        self.meshes.insert(key, Arc::clone(&mesh));
+
+        mesh
+    }
+
+    // Meshes that don't come from an OBJ file, like those of a glTF file, are built by
+    // `build` the first time their name is asked for
+    pub fn load_built(
+        &mut self,
+        name: &str,
+        accelerator: AcceleratorType,
+        precision: VertexPrecision,
+        build: impl FnOnce() -> Mesh,
+    ) -> Arc<Mesh> {
+        let key = (name.to_string(), true, accelerator, precision, 0, None);
+        if let Some(mesh) = self.meshes.get(&key) {
+            return Arc::clone(mesh);
+        }
+
+        let mesh = build();
+        println!(
+            "Built {:?} for {} ({} triangles) in {:.2?}",
+            accelerator, name, mesh.triangle_count, mesh.build_time
+        );
+
+        let mesh = Arc::new(mesh);
+        self.meshes.insert(key, Arc::clone(&mesh));
+
+        mesh
+    }
+}
+
+pub fn read_scene_json(filename: &str) -> serde_json::Value {
+    if filename.is_empty() {
+        panic!("Empty scene filename!");
+    }
+
+    let mut file = match File::open(filename) {
+        Err(why) => panic!("Error opening {}: {}", filename, why),
+        Ok(file) => file,
+    };
+
+    let mut s = String::new();
+    if let Err(why) = file.read_to_string(&mut s) {
+        panic!("Error converting file contents to string: {}", why);
+    };
+
+    match serde_json::from_str(&s) {
+        Err(why) => panic!("Error parsing json file: {}", why),
+        Ok(json) => json,
+    }
+}
+
+impl Scene {
+    pub fn new(filename: String) -> Scene {
+        let scene = match serde_json::from_value(read_scene_json(&filename)) {
             Err(why) => panic!("Error parsing json file: {}", why),
             Ok(json) => json,
         };
 
-        let camera = Camera::new(
-            Vec3::new_arr(scene.camera.look_from),
-            Vec3::new_arr(scene.camera.look_at),
-            Vec3::new_arr(scene.camera.v_up),
-            scene.camera.vfov,
-            scene.camera.aspect_ratio,
-            scene.camera.aperture,
-            scene.camera.dist_to_focus,
-        );
+        Scene::from_json(scene, &mut MeshCache::default())
+    }
+
+    pub fn from_json(scene: SceneJSON, meshes: &mut MeshCache) -> Scene {
+        // The single `camera` is rendered alongside any named `cameras`
+        let mut cameras = vec![];
+        if let Some(camera) = &scene.camera {
+            cameras.push(parse_camera(DEFAULT_CAMERA, camera));
+        }
+        for (name, camera) in scene.cameras.iter().flatten() {
+            if (name == DEFAULT_CAMERA && scene.camera.is_some()) || name == ALL_CAMERAS {
+                panic!("The camera name '{}' is reserved", name);
+            }
+            cameras.push(parse_camera(name, camera));
+        }
 
         let render_settings = RenderSettings {
             image_width: scene.render_settings.image_width,
             image_height: scene.render_settings.image_height,
             samples: scene.render_settings.samples,
+            ambient_occlusion: scene.render_settings.ambient_occlusion.as_ref().map(
+                |ambient_occlusion| AmbientOcclusion {
+                    distance: ambient_occlusion.distance,
+                    samples: ambient_occlusion.samples.unwrap_or(16),
+                },
+            ),
         };
 
-        let mut objects = HittableList::new();
+        let mut objects: Vec<Box<dyn Hittable>> = vec![];
         let mut lights: Vec<Arc<Box<dyn Hittable>>> = vec![];
 
         let mut skybox: Option<Sphere> = None;
@@ -96,101 +370,211 @@ impl Scene {
             ));
         }
 
-        for model in scene.models {
-            match model {
-                HittablesJSON::Model {
-                    obj_path,
-                    material,
-                    shade_smooth,
-                    should_render,
-                } => {
-                    let object = match File::open(&obj_path) {
-                        Err(why) => panic!("Error opening obj {} :{}", &obj_path, why),
-                        Ok(object) => BufReader::new(object),
-                    };
-
-                    let object: Obj<TexturedVertex, u32> = match load_obj(object) {
-                        Err(why) => panic!("Could not load model {}: {}", &obj_path, why),
-                        Ok(model) => model,
-                    };
-
-                    let object_material = parse_material(&material);
+        // meshes declared once and placed by any number of instances share their geometry
+        let declared_meshes = scene.meshes.unwrap_or_default();
+        let default_accelerator = scene.render_settings.accelerator;
+        let default_precision = scene.render_settings.vertex_precision;
 
-                    let object = Object::new(
-                        object,
-                        object_material,
-                        shade_smooth.unwrap_or(true),
-                        should_render.unwrap_or(true),
+        let aspect_ratio = render_settings.image_width as f64 / render_settings.image_height as f64;
+        let mut suns = vec![];
+        for model in scene.models {
+            // a glTF file brings its cameras and lights along with its meshes
+            let hittables = match model {
+                HittablesJSON::Gltf(gltf) => {
+                    let imported = parse_gltf(
+                        &gltf,
+                        meshes,
+                        default_accelerator,
+                        default_precision,
+                        aspect_ratio,
                     );
-
-                    if let MaterialJSON::Light { .. } = material {
-                        let light_sampler = Box::new(object.get_light_sampler_sphere());
-                        lights.push(Arc::new(light_sampler));
+                    for camera in imported.cameras {
+                        if cameras.iter().any(|other| other.name == camera.name)
+                            || camera.name == ALL_CAMERAS
+                        {
+                            panic!(
+                                "The camera name '{}' in {} is already taken",
+                                camera.name, gltf.gltf_path
+                            );
+                        }
+                        cameras.push(camera);
                     }
-
-                    objects.objects.push(Box::new(object));
+                    suns.extend(imported.suns);
+                    imported.objects
                 }
-                HittablesJSON::Volume {
-                    box_min,
-                    box_max,
-                    colour,
-                    material,
-                    density,
-                } => {
-                    let colour = Colour::new(colour[0], colour[1], colour[2]);
-                    let cube = Cube::new(
-                        Vec3::new(box_min[0], box_min[1], box_min[2]),
-                        Vec3::new(box_max[0], box_max[1], box_max[2]),
-                        colour,
-                    );
-                    let object_material = parse_material(&material);
-                    let mist = Volume::new(Box::new(cube), density, object_material);
+                model => vec![parse_hittable(
+                    model,
+                    meshes,
+                    &declared_meshes,
+                    default_accelerator,
+                    default_precision,
+                )],
+            };
 
-                    objects.objects.push(Box::new(mist));
+            for (object, is_light) in hittables {
+                if is_light {
+                    let light_sampler = Box::new(object.get_light_sampler_sphere());
+                    lights.push(Arc::new(light_sampler));
                 }
+
+                objects.push(object);
             }
         }
 
+        // suns are placed far outside everything else in the scene
+        let bounds = objects
+            .iter()
+            .filter_map(|object| object.bounding_box())
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or_else(|| {
+                AxisAlignedBoundingBox::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0))
+            });
+        for sun in suns {
+            let sun = sun.place(&bounds);
+            lights.push(Arc::new(Box::new(sun.get_light_sampler_sphere())));
+            objects.push(sun);
+        }
+        if cameras.is_empty() {
+            panic!("The scene needs a camera or some cameras!");
+        }
+
         Scene {
             render_settings,
-            camera,
-            objects,
+            cameras,
+            objects: ObjectBVH::new(objects),
             lights,
             skybox,
         }
     }
 
-    pub fn render(&self, rgba_image: Arc<Mutex<RgbaImage>>) {
-        let zbuffer = Arc::new(Mutex::new(vec![
-            vec![
-                INFINITY;
-                self.render_settings.image_width
-                    as usize
-            ];
-            self.render_settings.image_height as usize
-        ]));
+    pub fn camera(&self, name: &str) -> Option<&SceneCamera> {
+        self.cameras.iter().find(|camera| camera.name == name)
+    }
 
-        for j in 0..=self.render_settings.image_height - 1 {
-            let scanline: Vec<Colour> = (0..self.render_settings.image_width)
-                .into_par_iter()
-                .map(|i| {
-                    let mut pixel_colour = Colour::new(0.0, 0.0, 0.0);
+    // Times finding the closest hit of one camera ray per pixel, first with neighbouring
+    // rays traced together in packets and then with every ray traced on its own. Also
+    // counts the rays whose packet hit differs from their own, which should be none.
+    pub fn bench_camera_rays(&self, scene_camera: &SceneCamera) -> (Duration, Duration, usize) {
+        let (image_width, image_height) = scene_camera.image_dimensions(&self.render_settings);
+
+        // both passes trace the same rays
+        let scanlines: Vec<Vec<Ray>> = (0..image_height)
+            .map(|j| {
+                (0..image_width)
+                    .map(|i| scene_camera.get_ray(i, j, &self.render_settings))
+                    .collect()
+            })
+            .collect();
+
+        let start = Instant::now();
+        scanlines.par_iter().for_each(|rays| {
+            for rays in rays.chunks(LANES) {
+                self.objects.hit_packet(
+                    &RayPacket::new(rays),
+                    0.0,
+                    [INFINITY; LANES],
+                    [None; LANES],
+                    true,
+                );
+            }
+        });
+        let packets = start.elapsed();
+
+        let start = Instant::now();
+        scanlines.par_iter().for_each(|rays| {
+            for ray in rays {
+                self.objects.hit(ray, 0.0, INFINITY, None, true);
+            }
+        });
+        let single_rays = start.elapsed();
+
+        let mismatches = scanlines
+            .par_iter()
+            .map(|rays| {
+                let mut mismatches = 0;
+                for rays in rays.chunks(LANES) {
+                    let packet_hits = self.objects.hit_packet(
+                        &RayPacket::new(rays),
+                        0.0,
+                        [INFINITY; LANES],
+                        [None; LANES],
+                        true,
+                    );
+                    for (ray, packet_hit) in rays.iter().zip(&packet_hits) {
+                        let hit = self.objects.hit(ray, 0.0, INFINITY, None, true);
+                        let same = match (&hit, packet_hit) {
+                            (Some(hit), Some(packet_hit)) => {
+                                (hit.t - packet_hit.t).abs() <= 1e-9 * hit.t.max(1.0)
+                            }
+                            (None, None) => true,
+                            _ => false,
+                        };
+                        mismatches += !same as usize;
+                    }
+                }
+                mismatches
+            })
+            .sum();
+
+        (packets, single_rays, mismatches)
+    }
+
+    pub fn render(&self, scene_camera: &SceneCamera, rgba_image: Arc<Mutex<RgbaImage>>) {
+        let (image_width, image_height) = scene_camera.image_dimensions(&self.render_settings);
+        let zbuffer = Mutex::new(vec![
+            vec![INFINITY; image_width as usize];
+            image_height as usize
+        ]);
+
+        let columns: Vec<u32> = (0..image_width).collect();
+        for j in 0..=image_height - 1 {
+            // neighbouring pixels are traced together as a packet of camera rays
+            let scanline: Vec<Colour> = columns
+                .par_chunks(LANES)
+                .flat_map_iter(|columns| {
+                    let mut pixel_colours: Vec<Colour> =
+                        columns.iter().map(|_| Colour::new(0.0, 0.0, 0.0)).collect();
+                    let pixels = std::array::from_fn(|lane| {
+                        columns.get(lane).map(|&i| Pixel {
+                            row: j as usize,
+                            column: i as usize,
+                            zbuffer: &zbuffer,
+                        })
+                    });
 
                     for _ in 0..self.render_settings.samples {
-                        let u = (i as f64 + rand::random::<f64>())
-                            / (self.render_settings.image_width - 1) as f64;
-                        let v = (j as f64 + rand::random::<f64>())
-                            / (self.render_settings.image_height - 1) as f64;
+                        let rays: Vec<Ray> = columns
+                            .iter()
+                            .map(|&i| scene_camera.get_ray(i, j, &self.render_settings))
+                            .collect();
+                        let hits = self.objects.hit_packet(
+                            &RayPacket::new(&rays),
+                            0.0,
+                            [INFINITY; LANES],
+                            pixels,
+                            true,
+                        );
 
-                        let pixel = Some((j as usize, i as usize));
-                        let ray = self.camera.get_ray(u, v);
-                        pixel_colour +=
-                            self.ray_colour(&ray, MAX_RAY_DEPTH, pixel, Arc::clone(&zbuffer));
+                        for (lane, ray) in rays.iter().enumerate() {
+                            let hit_record = hits[lane].as_ref();
+                            pixel_colours[lane] += match &self.render_settings.ambient_occlusion {
+                                Some(settings) => self.ambient_occlusion(ray, hit_record, settings),
+                                None => self.shade(
+                                    &scene_camera.camera,
+                                    ray,
+                                    hit_record,
+                                    MAX_RAY_DEPTH,
+                                    pixels[lane],
+                                ),
+                            };
+                        }
 
                         let mut zbuff = zbuffer.lock().unwrap();
-                        zbuff[j as usize][i as usize] = INFINITY;
+                        for &i in columns {
+                            zbuff[j as usize][i as usize] = INFINITY;
+                        }
                     }
-                    pixel_colour
+                    pixel_colours
                 })
                 .collect();
 
@@ -199,52 +583,56 @@ impl Scene {
                 let mut image = rgba_image.lock().unwrap();
                 image.put_pixel(
                     x as u32,
-                    self.render_settings.image_height - j - 1,
+                    image_height - j - 1,
                     Rgba([w_colour.0, w_colour.1, w_colour.2, 1]),
                 );
             }
         }
     }
 
-    fn ray_colour(
-        &self,
-        ray: &Ray,
-        depth: u32,
-        pixel: Option<(usize, usize)>,
-        zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
-    ) -> Colour {
+    fn ray_colour(&self, camera: &Camera, ray: &Ray, depth: u32, pixel: Option<Pixel>) -> Colour {
         if depth == 0 {
             return Colour::new(0.0, 0.0, 0.0);
         }
 
-        let mut pixel_tup: Option<(usize, usize)> = None;
+        let mut pixel_tup: Option<Pixel> = None;
         let mut first_ray = false;
         if depth == MAX_RAY_DEPTH {
             pixel_tup = pixel;
             first_ray = true;
         }
 
-        if let Some(hit_record) = &self.objects.hit(
-            ray,
-            &self.camera,
-            0.001,
-            INFINITY,
-            pixel_tup,
-            Arc::clone(&zbuffer),
-            first_ray,
-        ) {
+        let hit_record = self.objects.hit(ray, 0.0, INFINITY, pixel_tup, first_ray);
+
+        self.shade(camera, ray, hit_record.as_ref(), depth, pixel)
+    }
+
+    // Colour seen along a ray given the closest thing it hit
+    fn shade(
+        &self,
+        camera: &Camera,
+        ray: &Ray,
+        hit_record: Option<&HitRecord>,
+        depth: u32,
+        pixel: Option<Pixel>,
+    ) -> Colour {
+        let first_ray = depth == MAX_RAY_DEPTH;
+
+        if let Some(hit_record) = hit_record {
             let mut rng = rand::thread_rng();
             let bxdf_light = self.lights.iter().choose(&mut rng);
-            let mut light_center = Vec3::new(0.0, 0.0, 0.0);
+            let mut light_position = None;
             if let Some(light) = bxdf_light {
-                light_center = random_in_unit_sphere() + light.center();
-                light_center = light.radius() * &light_center;
+                let light_sample = light.center() + light.radius() * &random_in_unit_sphere();
+                if self.light_visible(hit_record, light, &light_sample, ray.time) {
+                    light_position = Some(light_sample);
+                }
             }
 
             let (scattered_ray, albedo, is_scattered) =
                 hit_record
                     .material
-                    .scatter(ray, hit_record, &self.camera, light_center);
+                    .scatter(ray, hit_record, camera, light_position);
 
             let emitted = hit_record
                 .material
@@ -273,15 +661,10 @@ impl Scene {
                 let mixture_pdf = MixturePDF::new(pdfs);
 
                 if let Some(ray) = mixture_pdf.generate() {
-                    scattered_ray = Ray::new(hit_record.p, ray);
+                    scattered_ray = hit_record.spawn_ray(ray, scattered_ray.time);
                 }
 
-                pdf = mixture_pdf.value(
-                    &scattered_ray.direction,
-                    &self.camera,
-                    pixel,
-                    Arc::clone(&zbuffer),
-                );
+                pdf = mixture_pdf.value(&scattered_ray.direction, pixel);
             }
 
             return emitted
@@ -289,23 +672,13 @@ impl Scene {
                     .material
                     .scattering_pdf(ray, hit_record, &scattered_ray)
                     * albedo
-                    * self.ray_colour(&scattered_ray, depth - 1, pixel, Arc::clone(&zbuffer))
+                    * self.ray_colour(camera, &scattered_ray, depth - 1, pixel)
                     / pdf;
         }
 
         if let Some(skybox) = &self.skybox {
-            if let Some(hit) = &skybox.hit(
-                ray,
-                &self.camera,
-                0.0001,
-                INFINITY,
-                pixel,
-                Arc::clone(&zbuffer),
-                first_ray,
-            ) {
-                let (_, albedo, _) =
-                    hit.material
-                        .scatter(ray, hit, &self.camera, Vec3::new(0.0, 0.0, 0.0));
+            if let Some(hit) = &skybox.hit(ray, 0.0001, INFINITY, pixel, first_ray) {
+                let (_, albedo, _) = hit.material.scatter(ray, hit, camera, None);
                 return albedo;
             };
         }
@@ -316,6 +689,951 @@ impl Scene {
 
         (1.0 - t) * Colour::new(1.0, 1.0, 1.0) + t * Colour::new(0.5, 0.7, 1.0)
     }
+
+    // A light's sample sits inside its sampler sphere along with the light's own geometry,
+    // so only something in front of the sphere can shadow the hit
+    fn light_visible(
+        &self,
+        hit_record: &HitRecord,
+        light: &Arc<Box<dyn Hittable>>,
+        light_position: &Vec3,
+        time: f64,
+    ) -> bool {
+        if distance(&hit_record.p, &light.center()) < light.radius() {
+            return true;
+        }
+
+        let shadow_ray = hit_record.spawn_ray(light_position - hit_record.p, time);
+        let t_max = match light.hit(&shadow_ray, 0.0, 1.0, None, false) {
+            Some(hit) => hit.t,
+            None => 1.0,
+        };
+
+        !self.objects.occluded(&shadow_ray, t_max)
+    }
+
+    // Shades the first hit by how much of the hemisphere above it is open within the
+    // occlusion distance, ignoring materials and lights
+    fn ambient_occlusion(
+        &self,
+        ray: &Ray,
+        hit_record: Option<&HitRecord>,
+        settings: &AmbientOcclusion,
+    ) -> Colour {
+        let hit_record = match hit_record {
+            Some(hit_record) => hit_record,
+            None => return Colour::new(1.0, 1.0, 1.0),
+        };
+
+        let onb = OrthonormalBasis::build_from_w(&hit_record.normal);
+        let open = (0..settings.samples)
+            .filter(|_| {
+                let direction = onb.local_vec(&random_cosine_direction());
+                let occlusion_ray = hit_record.spawn_ray(direction, ray.time);
+                !self.objects.occluded(&occlusion_ray, settings.distance)
+            })
+            .count();
+
+        let exposure = open as f64 / settings.samples as f64;
+        Colour::new(exposure, exposure, exposure)
+    }
+}
+
+fn parse_camera(name: &str, camera_json: &CameraJSON) -> SceneCamera {
+    let look_from = Vec3::new_arr(camera_json.look_from);
+    let look_at = Vec3::new_arr(camera_json.look_at);
+
+    let camera = Camera::new(
+        look_from,
+        look_at,
+        Vec3::new_arr(camera_json.v_up),
+        camera_json.vfov,
+        camera_json.aspect_ratio,
+        camera_json.aperture,
+        camera_json.dist_to_focus,
+    )
+    .with_shutter(
+        camera_json.shutter_open.unwrap_or(0.0),
+        camera_json.shutter_close.unwrap_or(1.0),
+    );
+
+    let stereo = camera_json.stereo.as_ref().map(|stereo| {
+        let convergence = stereo
+            .convergence_distance
+            .unwrap_or_else(|| (look_at - look_from).length());
+        let layout = match stereo.layout {
+            StereoLayoutJSON::SideBySide => StereoLayout::SideBySide,
+            StereoLayoutJSON::OverUnder => StereoLayout::OverUnder,
+        };
+
+        StereoCamera::new(
+            &camera,
+            stereo.interocular_distance,
+            convergence,
+            layout,
+            stereo.omni_directional.unwrap_or(false),
+        )
+    });
+
+    SceneCamera {
+        name: name.to_string(),
+        camera,
+        stereo,
+    }
+}
+
+// Builds one entry of the scene's models and says whether it is a light
+fn parse_hittable(
+    model: HittablesJSON,
+    meshes: &mut MeshCache,
+    declared_meshes: &BTreeMap<String, MeshJSON>,
+    default_accelerator: Option<AcceleratorJSON>,
+    default_precision: Option<VertexPrecisionJSON>,
+) -> (Box<dyn Hittable>, bool) {
+    match model {
+        HittablesJSON::Model {
+            obj_path,
+            material,
+            materials,
+            shade_smooth,
+            accelerator,
+            vertex_precision,
+            subdivision,
+            displacement,
+            should_render,
+            transform,
+            motion,
+        } => {
+            let mesh = meshes.load(
+                &obj_path,
+                shade_smooth.unwrap_or(true),
+                parse_accelerator(accelerator.or(default_accelerator)),
+                parse_vertex_precision(vertex_precision.or(default_precision)),
+                subdivision.unwrap_or(0),
+                parse_displacement(displacement.as_ref()).as_ref(),
+            );
+            let (materials, is_light) =
+                parse_mesh_materials(&mesh, &obj_path, material.as_ref(), &[materials.as_ref()]);
+            let object = parse_object(
+                mesh,
+                materials,
+                should_render,
+                transform.as_ref(),
+                motion.as_ref(),
+            );
+
+            (object, is_light)
+        }
+        HittablesJSON::Instance {
+            mesh,
+            material,
+            materials,
+            should_render,
+            transform,
+            motion,
+        } => {
+            let declared = match declared_meshes.get(&mesh) {
+                None => panic!("Instance of undeclared mesh '{}'", mesh),
+                Some(declared) => declared,
+            };
+
+            let mesh = meshes.load(
+                &declared.obj_path,
+                declared.shade_smooth.unwrap_or(true),
+                parse_accelerator(declared.accelerator.or(default_accelerator)),
+                parse_vertex_precision(declared.vertex_precision.or(default_precision)),
+                declared.subdivision.unwrap_or(0),
+                parse_displacement(declared.displacement.as_ref()).as_ref(),
+            );
+            // the instance's materials win over the ones declared with its mesh
+            let (materials, is_light) = parse_mesh_materials(
+                &mesh,
+                &declared.obj_path,
+                material.as_ref().or(declared.material.as_ref()),
+                &[materials.as_ref(), declared.materials.as_ref()],
+            );
+            let object = parse_object(
+                mesh,
+                materials,
+                should_render,
+                transform.as_ref(),
+                motion.as_ref(),
+            );
+
+            (object, is_light)
+        }
+        HittablesJSON::Volume {
+            box_min,
+            box_max,
+            colour,
+            material,
+            density,
+            transform,
+        } => {
+            let cube = Cube::new(Vec3::new_arr(box_min), Vec3::new_arr(box_max), || {
+                Box::new(Lambertian {
+                    albedo: Box::new(SolidColour::new(
+                        Colour::new(colour[0], colour[1], colour[2]),
+                        None,
+                        None,
+                    )),
+                })
+            });
+            let object_material = parse_material(&material);
+            let mist = Volume::new(Box::new(cube), density, object_material);
+
+            (with_transform(Box::new(mist), transform.as_ref()), false)
+        }
+        HittablesJSON::Sphere {
+            center,
+            radius,
+            material,
+            transform,
+        } => {
+            let sphere = Sphere::new(Vec3::new_arr(center), radius, parse_material(&material));
+
+            parse_shape(Box::new(sphere), &material, transform.as_ref())
+        }
+        HittablesJSON::Plane {
+            material,
+            transform,
+        } => {
+            if is_light(&material) {
+                panic!("An infinite plane can't be a light, use a rectangle instead");
+            }
+            let plane = InfinitePlane::new(parse_material(&material));
+
+            parse_shape(Box::new(plane), &material, transform.as_ref())
+        }
+        HittablesJSON::Rectangle {
+            width,
+            depth,
+            material,
+            transform,
+        } => {
+            let rectangle = Plane::new(
+                (-width / 2.0, width / 2.0, -depth / 2.0, depth / 2.0),
+                0.0,
+                parse_material(&material),
+                PlaneOrientation::XZ,
+            );
+
+            parse_shape(Box::new(rectangle), &material, transform.as_ref())
+        }
+        HittablesJSON::Box {
+            box_min,
+            box_max,
+            material,
+            transform,
+        } => {
+            let cube = Cube::new(Vec3::new_arr(box_min), Vec3::new_arr(box_max), || {
+                parse_material(&material)
+            });
+
+            parse_shape(Box::new(cube), &material, transform.as_ref())
+        }
+        HittablesJSON::Disk {
+            radius,
+            inner_radius,
+            material,
+            transform,
+        } => {
+            let disk = Disk::new(
+                radius,
+                inner_radius.unwrap_or(0.0),
+                parse_material(&material),
+            );
+
+            parse_shape(Box::new(disk), &material, transform.as_ref())
+        }
+        HittablesJSON::Cylinder {
+            radius,
+            height,
+            capped,
+            material,
+            transform,
+        } => {
+            let cylinder = Cylinder::new(
+                radius,
+                height,
+                capped.unwrap_or(false),
+                parse_material(&material),
+            );
+
+            parse_shape(Box::new(cylinder), &material, transform.as_ref())
+        }
+        HittablesJSON::Cone {
+            radius,
+            height,
+            capped,
+            material,
+            transform,
+        } => {
+            let cone = Cone::new(
+                radius,
+                height,
+                capped.unwrap_or(false),
+                parse_material(&material),
+            );
+
+            parse_shape(Box::new(cone), &material, transform.as_ref())
+        }
+        HittablesJSON::Torus {
+            major_radius,
+            minor_radius,
+            material,
+            transform,
+        } => {
+            let torus = Torus::new(major_radius, minor_radius, parse_material(&material));
+
+            parse_shape(Box::new(torus), &material, transform.as_ref())
+        }
+        HittablesJSON::Sdf {
+            shape,
+            material,
+            transform,
+        } => {
+            let sdf = Sdf::new(parse_sdf(shape), parse_material(&material));
+
+            parse_shape(Box::new(sdf), &material, transform.as_ref())
+        }
+        HittablesJSON::Heightfield {
+            height_path,
+            width,
+            depth,
+            height_scale,
+            material,
+            transform,
+        } => {
+            let image = match image::open(&height_path) {
+                Err(why) => panic!("Error opening heightfield image {}: {}", height_path, why),
+                Ok(image) => image,
+            };
+            let heightfield = Heightfield::new(
+                &image,
+                width,
+                depth,
+                height_scale,
+                parse_material(&material),
+            );
+
+            parse_shape(Box::new(heightfield), &material, transform.as_ref())
+        }
+        HittablesJSON::PointCloud {
+            points_path,
+            radius,
+            material,
+            transform,
+        } => {
+            let points = match Path::new(&points_path)
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(|extension| extension.to_lowercase())
+                .as_deref()
+            {
+                Some("ply") => match fs::read(&points_path) {
+                    Err(why) => panic!("Error opening point cloud {}: {}", points_path, why),
+                    Ok(bytes) => parse_ply(&bytes),
+                },
+                Some("csv") => match fs::read_to_string(&points_path) {
+                    Err(why) => panic!("Error opening point cloud {}: {}", points_path, why),
+                    Ok(text) => parse_csv(&text),
+                },
+                _ => panic!("Point clouds must be .ply or .csv files: {}", points_path),
+            };
+            let mut points = match points {
+                Err(why) => panic!("Could not load point cloud {}: {}", points_path, why),
+                Ok(points) => points,
+            };
+            // a radius in the scene overrides any the file has, so every point gets it
+            let radius = match (radius, &points.radii) {
+                (Some(radius), _) => {
+                    points.radii = None;
+                    radius
+                }
+                (None, Some(_)) => 0.0,
+                (None, None) => panic!(
+                    "The point cloud {} has no radii so it needs a radius",
+                    points_path
+                ),
+            };
+            let point_cloud = PointCloud::new(points, radius, parse_material(&material));
+
+            parse_shape(Box::new(point_cloud), &material, transform.as_ref())
+        }
+        HittablesJSON::Curves {
+            curves_path,
+            curve_type,
+            material,
+            transform,
+        } => {
+            let text = match fs::read_to_string(&curves_path) {
+                Err(why) => panic!("Error opening curves {}: {}", curves_path, why),
+                Ok(text) => text,
+            };
+            let curves = match parse_curves(&text) {
+                Err(why) => panic!("Could not load curves {}: {}", curves_path, why),
+                Ok(curves) => curves,
+            };
+            let curve_type = match curve_type {
+                Some(CurveTypeJSON::Ribbon) => CurveType::Ribbon,
+                Some(CurveTypeJSON::Cylinder) | None => CurveType::Cylinder,
+            };
+            let curves = Curves::new(curves, curve_type, parse_material(&material));
+
+            parse_shape(Box::new(curves), &material, transform.as_ref())
+        }
+        HittablesJSON::Csg {
+            operation,
+            left,
+            right,
+            transform,
+        } => {
+            let mut parse_operand = |operand: HittablesJSON| {
+                parse_hittable(
+                    operand,
+                    meshes,
+                    declared_meshes,
+                    default_accelerator,
+                    default_precision,
+                )
+            };
+            let (left, left_is_light) = parse_operand(*left);
+            let (right, right_is_light) = parse_operand(*right);
+
+            let operation = match operation {
+                CsgOperationJSON::Union => CsgOperation::Union,
+                CsgOperationJSON::Intersection => CsgOperation::Intersection,
+                CsgOperationJSON::Difference => CsgOperation::Difference,
+            };
+            let csg = Csg::new(operation, left, right);
+
+            // lights inside the combination are sampled through the whole of it
+            (
+                with_transform(Box::new(csg), transform.as_ref()),
+                left_is_light || right_is_light,
+            )
+        }
+        HittablesJSON::Gltf(gltf) => panic!(
+            "The glTF scene {} can only be placed in the scene's models",
+            gltf.gltf_path
+        ),
+    }
+}
+
+// Everything a glTF file brings into the scene
+struct ImportedGltf {
+    objects: Vec<(Box<dyn Hittable>, bool)>,
+    cameras: Vec<SceneCamera>,
+    // placed once the whole scene is known
+    suns: Vec<Sun>,
+}
+
+// Places every mesh of a glTF file's scene where its nodes put it, within the entry's own
+// transform, and brings in the file's perspective cameras and punctual lights. Its
+// materials can be replaced by name in `materials`.
+fn parse_gltf(
+    gltf: &GltfJSON,
+    meshes: &mut MeshCache,
+    default_accelerator: Option<AcceleratorJSON>,
+    default_precision: Option<VertexPrecisionJSON>,
+    aspect_ratio: f64,
+) -> ImportedGltf {
+    let path = &gltf.gltf_path;
+    let file = match GltfFile::open(path) {
+        Err(why) => panic!("Could not load glTF {}: {}", path, why),
+        Ok(file) => file,
+    };
+    let nodes = match file.nodes() {
+        Err(why) => panic!("Could not load glTF {}: {}", path, why),
+        Ok(nodes) => nodes,
+    };
+
+    let scene_from_gltf = match &gltf.transform {
+        Some(transform) => parse_matrix(transform),
+        None => Matrix4::identity(),
+    };
+    let accelerator = parse_accelerator(gltf.accelerator.or(default_accelerator));
+    let precision = parse_vertex_precision(gltf.vertex_precision.or(default_precision));
+
+    // a misspelt name would otherwise quietly leave the material it meant alone
+    let names: Vec<String> = file
+        .document
+        .meshes()
+        .flat_map(|mesh| file.mesh_materials(&mesh))
+        .map(|material| material_name(&material))
+        .collect();
+    for name in gltf.materials.iter().flat_map(|materials| materials.keys()) {
+        if !names.contains(name) {
+            println!(
+                "The glTF scene {} has no material '{}' for its `materials` to replace",
+                path, name
+            );
+        }
+    }
+
+    let mut objects = vec![];
+    for (node, world) in &nodes {
+        let gltf_mesh = match node.mesh() {
+            Some(gltf_mesh) => gltf_mesh,
+            None => continue,
+        };
+        if !has_triangles(&gltf_mesh) {
+            println!(
+                "Skipped mesh {} in {}, which has no triangles",
+                gltf_mesh.index(),
+                path
+            );
+            continue;
+        }
+        let name = format!("{}#mesh{}", path, gltf_mesh.index());
+        let mesh = meshes.load_built(&name, accelerator, precision, || {
+            match file.mesh(&gltf_mesh, accelerator, precision) {
+                Err(why) => panic!("Could not load glTF {}: {}", path, why),
+                Ok(mesh) => mesh,
+            }
+        });
+
+        let mut any_light = false;
+        let materials = file
+            .mesh_materials(&gltf_mesh)
+            .iter()
+            .map(|material| {
+                let replacement = gltf
+                    .materials
+                    .as_ref()
+                    .and_then(|materials| materials.get(&material_name(material)));
+                let (material, emits): (Box<dyn Material>, bool) = match replacement {
+                    Some(replacement) => (parse_material(replacement), is_light(replacement)),
+                    None => file.material(material),
+                };
+                any_light |= emits;
+                material
+            })
+            .collect();
+
+        let object = Object::new(mesh, materials, gltf.should_render.unwrap_or(true));
+        let object = Transform::new(Box::new(object), scene_from_gltf.multiply(world));
+        objects.push((Box::new(object) as Box<dyn Hittable>, any_light));
+    }
+
+    let mut cameras = vec![];
+    for (node, world) in &nodes {
+        let camera = match node.camera() {
+            Some(camera) => camera,
+            None => continue,
+        };
+        let name = match (camera.name(), node.name()) {
+            (Some(name), _) | (None, Some(name)) => name.to_string(),
+            (None, None) => format!("camera{}", camera.index()),
+        };
+        match gltf_camera(&camera, &scene_from_gltf.multiply(world), aspect_ratio) {
+            Some(camera) => cameras.push(parse_camera(&name, &camera)),
+            None => println!("Skipped the orthographic camera {} in {}", name, path),
+        }
+    }
+
+    let mut suns = vec![];
+    for (node, world) in &nodes {
+        if let Some(light) = node.light() {
+            let radius = gltf.light_radius.unwrap_or(0.05);
+            match gltf_light(&light, &scene_from_gltf.multiply(world), radius) {
+                PunctualLight::Shape(light) => objects.push((light, true)),
+                PunctualLight::Sun(sun) => suns.push(sun),
+            }
+        }
+    }
+
+    ImportedGltf {
+        objects,
+        cameras,
+        suns,
+    }
+}
+
+fn parse_object(
+    mesh: Arc<Mesh>,
+    materials: Vec<Box<dyn Material>>,
+    should_render: Option<bool>,
+    transform: Option<&TransformJSON>,
+    motion: Option<&MotionJSON>,
+) -> Box<dyn Hittable> {
+    let object = Object::new(mesh, materials, should_render.unwrap_or(true));
+    let object = with_transform(Box::new(object), transform);
+
+    // models move from their start transform at time 0 to their end transform at time 1,
+    // on top of any static transform
+    match motion {
+        Some(motion) => Box::new(Moving::new(
+            object,
+            AnimatedTransform::new(
+                parse_transform(&motion.start),
+                parse_transform(&motion.end),
+                0.0,
+                1.0,
+            ),
+        )),
+        None => object,
+    }
+}
+
+// The material of each of a mesh's slots, and whether any of them is a light. A slot named
+// in one of the `overrides` (the first to name it wins) takes that material, and the others
+// all take the model's `material`. A model without one looks its slots up by name in the MTL
+// files its OBJ names, which are found next to the OBJ.
+fn parse_mesh_materials(
+    mesh: &Mesh,
+    obj_path: &str,
+    material: Option<&MaterialJSON>,
+    overrides: &[Option<&BTreeMap<String, MaterialJSON>>],
+) -> (Vec<Box<dyn Material>>, bool) {
+    let names = &mesh.materials.names;
+    let slots = names.len().max(1);
+    let obj_directory = Path::new(obj_path).parent().unwrap_or(Path::new(""));
+
+    // MTL files are only read if a slot needs them, so models given a material still load
+    // when their OBJ names MTL files that aren't there
+    let mut libraries: Option<Vec<(HashMap<String, MtlMaterial>, PathBuf)>> = None;
+
+    let mut materials: Vec<Box<dyn Material>> = Vec::with_capacity(slots);
+    let mut any_light = false;
+    for slot in 0..slots {
+        let name = names.get(slot).map_or("", |name| name.as_str());
+        let chosen = overrides
+            .iter()
+            .flatten()
+            .find_map(|overrides| overrides.get(name))
+            .or(material);
+        if let Some(chosen) = chosen {
+            any_light |= is_light(chosen);
+            materials.push(parse_material(chosen));
+            continue;
+        }
+
+        let libraries = libraries.get_or_insert_with(|| {
+            mesh.materials
+                .libraries
+                .iter()
+                .map(|library| {
+                    let path = obj_directory.join(library);
+                    let text = match fs::read_to_string(&path) {
+                        Err(why) => panic!("Error opening mtl {}: {}", path.display(), why),
+                        Ok(text) => text,
+                    };
+                    match parse_mtl(&text) {
+                        Err(why) => panic!("Could not load mtl {}: {}", path.display(), why),
+                        Ok(library) => (library, path.parent().unwrap_or(Path::new("")).into()),
+                    }
+                })
+                .collect()
+        });
+
+        match libraries
+            .iter()
+            .find_map(|(library, directory)| Some((library.get(name)?, directory)))
+        {
+            Some((found, directory)) => {
+                materials.push(parse_material(&mtl_material(found, directory)))
+            }
+            None if name.is_empty() => panic!(
+                "Model {} has faces without a usemtl, so needs a `material` or a \"\" entry in its `materials`",
+                obj_path
+            ),
+            None => panic!(
+                "Model {} uses material '{}', which isn't in its MTL files or its `materials`",
+                obj_path, name
+            ),
+        }
+    }
+
+    // a misspelt name would otherwise quietly leave the material it meant alone
+    for name in overrides
+        .iter()
+        .flatten()
+        .flat_map(|overrides| overrides.keys())
+    {
+        let known =
+            names.iter().any(|known| known == name) || (names.is_empty() && name.is_empty());
+        if !known {
+            println!(
+                "Model {} has no material '{}' for its `materials` to replace",
+                obj_path, name
+            );
+        }
+    }
+
+    (materials, any_light)
+}
+
+// Stands a material from an MTL file in for the nearest material the tracer has. Glass comes
+// from illum 4, 6, 7 and 9, mirrors from illum 3, 5 and 8, and the PBR extension's Pr and Pm,
+// or a specular colour, give a microfacet material. The rest are Lambertian. A dissolve below
+// 1 lets that fraction of the light through the surface, and a bump map's heights are turned
+// into normals, steepened by its -bm.
+fn mtl_material(material: &MtlMaterial, directory: &Path) -> MaterialJSON {
+    let path = |file: &String| directory.join(file).to_string_lossy().into_owned();
+    let bump_path = material.bump_map.as_ref().map(path);
+    let solid_colour = |colour: [f64; 3]| TextureJSON::SolidColour {
+        colour,
+        normal_path: None,
+        normal_scale: None,
+        bump_path: bump_path.clone(),
+        bump_strength: material.bump_multiplier,
+    };
+    let albedo = match &material.diffuse_map {
+        Some(diffuse_map) => TextureJSON::ImageTexture {
+            image_path: path(diffuse_map),
+            alpha_path: None,
+            normal_path: None,
+            normal_scale: None,
+            bump_path: bump_path.clone(),
+            bump_strength: material.bump_multiplier,
+            is_light: false,
+            scale: 1.0,
+        },
+        None => solid_colour(material.diffuse.unwrap_or([0.8, 0.8, 0.8])),
+    };
+
+    // Ns runs from 0 for a rough surface to 1000 for a polished one
+    let roughness = material
+        .roughness
+        .unwrap_or_else(|| match material.shininess {
+            Some(shininess) => 1.0 - (shininess.clamp(0.0, 1000.0) / 1000.0).sqrt(),
+            None => 0.5,
+        });
+    let index_of_refraction = material
+        .index_of_refraction
+        .filter(|&index_of_refraction| index_of_refraction >= 1.0)
+        .unwrap_or(1.5);
+    // the microfacet model's f0 is 0.36 reflectance squared
+    let f0 = ((index_of_refraction - 1.0) / (index_of_refraction + 1.0)).powi(2);
+    let reflectance = (f0 / 0.36).sqrt();
+    let illumination = material.illumination.unwrap_or(2);
+    let specular = material.specular.unwrap_or([0.0, 0.0, 0.0]);
+
+    let surface = if material.roughness.is_some() || material.metallic.is_some() {
+        MaterialJSON::MicrofacetReflectance {
+            albedo,
+            metallic: Some(material.metallic.unwrap_or(0.0)),
+            roughness: Some(roughness),
+            reflectance: Some(reflectance),
+            include_diffuse: None,
+        }
+    } else if matches!(illumination, 4 | 6 | 7 | 9) {
+        MaterialJSON::Dielectric {
+            albedo: None,
+            index_of_refraction,
+        }
+    } else if matches!(illumination, 3 | 5 | 8) {
+        // a mirror without a Ks takes the diffuse colour, or else is white
+        let albedo = match material.specular {
+            Some(colour) => solid_colour(colour),
+            None if material.diffuse.is_none() && material.diffuse_map.is_none() => {
+                solid_colour([1.0, 1.0, 1.0])
+            }
+            None => albedo,
+        };
+        MaterialJSON::Metal {
+            albedo,
+            f: roughness,
+        }
+    } else if illumination >= 2 && specular != [0.0, 0.0, 0.0] {
+        MaterialJSON::MicrofacetReflectance {
+            albedo,
+            metallic: Some(0.0),
+            roughness: Some(roughness),
+            reflectance: Some(reflectance),
+            include_diffuse: None,
+        }
+    } else {
+        MaterialJSON::Lambertian { albedo }
+    };
+
+    // glass is already see-through, so it takes no dissolve
+    let is_glass = matches!(surface, MaterialJSON::Dielectric { .. });
+    match material.dissolve {
+        Some(dissolve) if dissolve < 1.0 && !is_glass => MaterialJSON::Cutout {
+            material: Box::new(surface),
+            alpha_path: None,
+            opacity: Some(dissolve.max(0.0)),
+            cutoff: None,
+        },
+        _ => surface,
+    }
+}
+
+// Shapes other than meshes are built around the origin and placed with their transform
+fn parse_shape(
+    shape: Box<dyn Hittable>,
+    material: &MaterialJSON,
+    transform: Option<&TransformJSON>,
+) -> (Box<dyn Hittable>, bool) {
+    (with_transform(shape, transform), is_light(material))
+}
+
+fn parse_sdf(shape: SdfJSON) -> SdfShape {
+    let parse = |shape: Box<SdfJSON>| Box::new(parse_sdf(*shape));
+
+    match shape {
+        SdfJSON::Sphere { radius } => SdfShape::Sphere { radius },
+        SdfJSON::Box { size } => SdfShape::Box {
+            half_size: 0.5 * &Vec3::new_arr(size),
+        },
+        SdfJSON::Torus {
+            major_radius,
+            minor_radius,
+        } => SdfShape::Torus {
+            major_radius,
+            minor_radius,
+        },
+        SdfJSON::Capsule { start, end, radius } => SdfShape::Capsule {
+            start: Vec3::new_arr(start),
+            end: Vec3::new_arr(end),
+            radius,
+        },
+        SdfJSON::Translate { shape, offset } => SdfShape::Translate {
+            shape: parse(shape),
+            offset: Vec3::new_arr(offset),
+        },
+        SdfJSON::SmoothUnion {
+            left,
+            right,
+            smoothness,
+        } => SdfShape::SmoothUnion {
+            left: parse(left),
+            right: parse(right),
+            smoothness,
+        },
+        SdfJSON::SmoothSubtraction {
+            left,
+            right,
+            smoothness,
+        } => SdfShape::SmoothSubtraction {
+            left: parse(left),
+            right: parse(right),
+            smoothness,
+        },
+        SdfJSON::Twist {
+            shape,
+            degrees_per_unit,
+        } => SdfShape::Twist {
+            shape: parse(shape),
+            angle_per_unit: degrees_per_unit.to_radians(),
+        },
+        SdfJSON::Repeat {
+            shape,
+            spacing,
+            copies,
+        } => SdfShape::Repeat {
+            shape: parse(shape),
+            spacing: Vec3::new_arr(spacing),
+            copies,
+        },
+        SdfJSON::Noise {
+            shape,
+            amplitude,
+            frequency,
+        } => SdfShape::Noise {
+            shape: parse(shape),
+            amplitude,
+            frequency,
+        },
+    }
+}
+
+fn parse_accelerator(accelerator: Option<AcceleratorJSON>) -> AcceleratorType {
+    match accelerator {
+        Some(AcceleratorJSON::KDTree) | None => AcceleratorType::KDTree,
+        Some(AcceleratorJSON::BVH) => AcceleratorType::BVH,
+    }
+}
+
+fn parse_displacement(displacement: Option<&DisplacementJSON>) -> Option<Displacement> {
+    let displacement = displacement?;
+    if displacement.edge_length <= 0.0 {
+        panic!(
+            "Displacement of {} needs a positive edge_length",
+            displacement.height_path
+        );
+    }
+
+    Some(Displacement {
+        height_path: displacement.height_path.clone(),
+        scale: displacement.scale,
+        midlevel: displacement.midlevel.unwrap_or(0.5),
+        edge_length: displacement.edge_length,
+    })
+}
+
+fn parse_vertex_precision(precision: Option<VertexPrecisionJSON>) -> VertexPrecision {
+    match precision {
+        Some(VertexPrecisionJSON::F64) | None => VertexPrecision::Double,
+        Some(VertexPrecisionJSON::F32) => VertexPrecision::Single,
+    }
+}
+
+fn is_light(material: &MaterialJSON) -> bool {
+    match material {
+        MaterialJSON::Light { .. } => true,
+        MaterialJSON::Cutout { material, .. } => is_light(material),
+        _ => false,
+    }
+}
+
+fn with_transform(
+    object: Box<dyn Hittable>,
+    transform: Option<&TransformJSON>,
+) -> Box<dyn Hittable> {
+    match transform {
+        Some(transform) => Box::new(Transform::new(object, parse_matrix(transform))),
+        None => object,
+    }
+}
+
+// A raw matrix is given as rows and replaces the other parts of the transform
+fn parse_matrix(transform: &TransformJSON) -> Matrix4 {
+    match transform.matrix {
+        Some(m) => {
+            if transform.translate.is_some()
+                || transform.rotate.is_some()
+                || transform.quaternion.is_some()
+                || transform.scale.is_some()
+                || transform.pivot.is_some()
+            {
+                panic!("A transform matrix can't be combined with other transform fields");
+            }
+            if m[3] != [0.0, 0.0, 0.0, 1.0] {
+                panic!("The last row of a transform matrix must be [0, 0, 0, 1]");
+            }
+            Matrix4 { m }
+        }
+        None => parse_transform(transform).to_matrix(),
+    }
+}
+
+// Rotations are Euler angles in degrees or a [w, x, y, z] quaternion
+fn parse_transform(transform: &TransformJSON) -> Decomposed {
+    let identity = Decomposed::identity();
+
+    if transform.matrix.is_some() {
+        panic!("A transform matrix can't be interpolated, use translate, rotate and scale");
+    }
+
+    let rotation = match (transform.rotate, transform.quaternion) {
+        (Some(_), Some(_)) => panic!("A transform can't have both rotate and quaternion"),
+        (Some(rotate), None) => Quaternion::from_euler(&Vec3::new_arr(rotate)),
+        (None, Some([w, x, y, z])) => Quaternion { w, x, y, z }.unit(),
+        (None, None) => identity.rotation,
+    };
+
+    Decomposed {
+        translation: transform
+            .translate
+            .map_or(identity.translation, Vec3::new_arr),
+        rotation,
+        scale: transform.scale.map_or(identity.scale, Vec3::new_arr),
+        pivot: transform.pivot.map_or(identity.pivot, Vec3::new_arr),
+    }
 }
 
 fn parse_material(material: &MaterialJSON) -> Box<dyn Material + Send + Sync + 'static> {
@@ -388,6 +1706,7 @@ fn parse_material(material: &MaterialJSON) -> Box<dyn Material + Send + Sync + '
             Box::new(MicrofacetReflectance {
                 albedo: texture,
                 bxdf: Box::new(microfacet_brdf),
+                metallic_roughness: None,
             })
         }
         MaterialJSON::Glossy {
@@ -423,6 +1742,63 @@ fn parse_material(material: &MaterialJSON) -> Box<dyn Material + Send + Sync + '
                 fuzziness: *fuzziness,
             })
         }
+        MaterialJSON::Hair {
+            sigma_a,
+            colour,
+            eumelanin,
+            pheomelanin,
+            eta,
+            beta_m,
+            beta_n,
+            alpha,
+        } => {
+            let beta_n = beta_n.unwrap_or(0.3);
+            // absorption given outright wins over a colour, which wins over pigments
+            let sigma_a = match (sigma_a, colour) {
+                (Some(sigma_a), _) => *sigma_a,
+                (None, Some(colour)) => Hair::sigma_a_from_colour(*colour, beta_n),
+                (None, None) => {
+                    Hair::sigma_a_from_melanin(eumelanin.unwrap_or(1.3), pheomelanin.unwrap_or(0.0))
+                }
+            };
+
+            Box::new(Hair::new(
+                sigma_a,
+                eta.unwrap_or(1.55),
+                beta_m.unwrap_or(0.3),
+                beta_n,
+                alpha.unwrap_or(2.0),
+            ))
+        }
+        MaterialJSON::Cutout {
+            material,
+            alpha_path,
+            opacity,
+            cutoff,
+        } => {
+            // the alpha comes from the image's alpha channel, as for image textures
+            let alpha = alpha_path.as_ref().map(|alpha_path| {
+                let image = match image::open(alpha_path) {
+                    Err(why) => panic!("Error opening alpha map {}: {}", alpha_path, why),
+                    Ok(image) => image,
+                };
+                Box::new(ImageTexture::new(
+                    image.clone(),
+                    Some(image),
+                    None,
+                    None,
+                    false,
+                    1.0,
+                )) as Box<dyn Texture + Send + Sync>
+            });
+
+            Box::new(Cutout {
+                material: parse_material(material),
+                alpha,
+                opacity: opacity.unwrap_or(1.0),
+                cutoff: *cutoff,
+            })
+        }
     }
 }
 
@@ -433,6 +1809,8 @@ fn parse_texture(texture: &TextureJSON) -> Box<dyn Texture + Send + Sync + 'stat
             alpha_path,
             normal_path,
             normal_scale,
+            bump_path,
+            bump_strength,
             is_light,
             scale,
         } => {
@@ -452,13 +1830,7 @@ fn parse_texture(texture: &TextureJSON) -> Box<dyn Texture + Send + Sync + 'stat
                 };
             }
 
-            let mut normal_img: Option<DynamicImage> = None;
-            if let Some(normal) = normal_path {
-                normal_img = match image::open(normal) {
-                    Err(why) => panic!("Error opening skybox image texture {}: {}", &normal, why),
-                    Ok(image) => Some(image),
-                };
-            }
+            let normal_img = parse_normal_map(normal_path, bump_path, *bump_strength);
 
             Box::new(ImageTexture::new(
                 img,
@@ -473,14 +1845,10 @@ fn parse_texture(texture: &TextureJSON) -> Box<dyn Texture + Send + Sync + 'stat
             colour,
             normal_path,
             normal_scale,
+            bump_path,
+            bump_strength,
         } => {
-            let mut normal_img: Option<DynamicImage> = None;
-            if let Some(normal) = normal_path {
-                normal_img = match image::open(normal) {
-                    Err(why) => panic!("Error opening skybox image texture {}: {}", &normal, why),
-                    Ok(image) => Some(image),
-                };
-            }
+            let normal_img = parse_normal_map(normal_path, bump_path, *bump_strength);
 
             Box::new(SolidColour::new(
                 Colour::new(colour[0], colour[1], colour[2]),
@@ -488,5 +1856,86 @@ fn parse_texture(texture: &TextureJSON) -> Box<dyn Texture + Send + Sync + 'stat
                 *normal_scale,
             ))
         }
+        TextureJSON::VertexColour { colour } => Box::new(VertexColour::new(Colour::new(
+            colour[0], colour[1], colour[2],
+        ))),
+    }
+}
+
+// A texture's normal map, read from `normal_path` or else worked out from the heights of the
+// bump map at `bump_path`
+fn parse_normal_map(
+    normal_path: &Option<String>,
+    bump_path: &Option<String>,
+    bump_strength: Option<f64>,
+) -> Option<DynamicImage> {
+    match (normal_path, bump_path) {
+        (Some(normal), None) => match image::open(normal) {
+            Err(why) => panic!("Error opening normal map {}: {}", normal, why),
+            Ok(image) => Some(image),
+        },
+        (None, Some(bump)) => match image::open(bump) {
+            Err(why) => panic!("Error opening bump map {}: {}", bump, why),
+            Ok(image) => Some(bump_to_normal_map(&image, bump_strength.unwrap_or(1.0))),
+        },
+        (Some(normal), Some(bump)) => panic!(
+            "A texture can have the normal map {} or the bump map {}, not both",
+            normal, bump
+        ),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mtl_json(mtl: &str) -> MaterialJSON {
+        let materials = parse_mtl(mtl).unwrap();
+        mtl_material(&materials["a"], Path::new("models"))
+    }
+
+    #[test]
+    fn mtl_dissolve_cuts_out_all_but_glass() {
+        match mtl_json("newmtl a\nKd 1 0 0\nd 0.25\n") {
+            MaterialJSON::Cutout {
+                material, opacity, ..
+            } => {
+                assert_eq!(opacity, Some(0.25));
+                assert!(matches!(*material, MaterialJSON::Lambertian { .. }));
+            }
+            other => panic!("expected a cutout, not {:?}", other),
+        }
+
+        assert!(matches!(
+            mtl_json("newmtl a\nillum 4\nd 0.25\nNi 1.33\n"),
+            MaterialJSON::Dielectric {
+                index_of_refraction: 1.33,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn mtl_bump_maps_are_heights_next_to_the_mtl() {
+        match mtl_json("newmtl a\nmap_Kd wood.png\nbump -bm 3 grain.png\n") {
+            MaterialJSON::Lambertian {
+                albedo:
+                    TextureJSON::ImageTexture {
+                        image_path,
+                        normal_path,
+                        bump_path,
+                        bump_strength,
+                        ..
+                    },
+            } => {
+                let in_models = |file: &str| Path::new("models").join(file);
+                assert_eq!(Path::new(&image_path), in_models("wood.png"));
+                assert_eq!(normal_path, None);
+                assert_eq!(bump_path.map(PathBuf::from), Some(in_models("grain.png")));
+                assert_eq!(bump_strength, Some(3.0));
+            }
+            other => panic!("expected a textured Lambertian, not {:?}", other),
+        }
     }
 }
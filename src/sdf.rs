@@ -0,0 +1,385 @@
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::hittable::{HitRecord, Hittable, Pixel};
+use crate::material::Material;
+use crate::noise::{perlin, PERLIN_LIPSCHITZ, PERLIN_RANGE};
+use crate::ray::Ray;
+use crate::sphere::{bounding_sphere, Sphere};
+use crate::vector::Vec3;
+
+use std::f64::consts::PI;
+
+const MAX_STEPS: usize = 512;
+
+// A tree of signed distance functions. Shapes sit at the origin and the other nodes
+// combine or bend the shapes below them.
+#[derive(Debug)]
+pub enum SdfShape {
+    Sphere {
+        radius: f64,
+    },
+    Box {
+        half_size: Vec3,
+    },
+    // around the y axis, like the analytic torus
+    Torus {
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    Capsule {
+        start: Vec3,
+        end: Vec3,
+        radius: f64,
+    },
+    Translate {
+        shape: Box<SdfShape>,
+        offset: Vec3,
+    },
+    // blends the two together over a distance of about the smoothness
+    SmoothUnion {
+        left: Box<SdfShape>,
+        right: Box<SdfShape>,
+        smoothness: f64,
+    },
+    // cuts the right shape out of the left one
+    SmoothSubtraction {
+        left: Box<SdfShape>,
+        right: Box<SdfShape>,
+        smoothness: f64,
+    },
+    // rotates around the y axis by an angle in radians that grows with the height
+    Twist {
+        shape: Box<SdfShape>,
+        angle_per_unit: f64,
+    },
+    // copies the shape `copies` times either side of itself along each axis. The shape has
+    // to fit in its spacing or the copies cut into each other.
+    Repeat {
+        shape: Box<SdfShape>,
+        spacing: Vec3,
+        copies: [u32; 3],
+    },
+    Noise {
+        shape: Box<SdfShape>,
+        amplitude: f64,
+        frequency: f64,
+    },
+}
+
+impl SdfShape {
+    pub fn distance(&self, p: &Vec3) -> f64 {
+        match self {
+            SdfShape::Sphere { radius } => p.length() - radius,
+            SdfShape::Box { half_size } => {
+                let q = p.abs() - half_size;
+                let outside = Vec3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).length();
+                outside + q.x.max(q.y).max(q.z).min(0.0)
+            }
+            SdfShape::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (ring * ring + p.y * p.y).sqrt() - minor_radius
+            }
+            SdfShape::Capsule { start, end, radius } => {
+                let pa = p - start;
+                let ba = end - start;
+                let h = (pa.dot(&ba) / ba.length_squared()).clamp(0.0, 1.0);
+                (pa - h * &ba).length() - radius
+            }
+            SdfShape::Translate { shape, offset } => shape.distance(&(p - offset)),
+            SdfShape::SmoothUnion {
+                left,
+                right,
+                smoothness,
+            } => {
+                let (a, b) = (left.distance(p), right.distance(p));
+                if *smoothness <= 0.0 {
+                    return a.min(b);
+                }
+                let h = (0.5 + 0.5 * (b - a) / smoothness).clamp(0.0, 1.0);
+                b + h * (a - b) - smoothness * h * (1.0 - h)
+            }
+            SdfShape::SmoothSubtraction {
+                left,
+                right,
+                smoothness,
+            } => {
+                let (a, b) = (left.distance(p), right.distance(p));
+                if *smoothness <= 0.0 {
+                    return a.max(-b);
+                }
+                let h = (0.5 - 0.5 * (a + b) / smoothness).clamp(0.0, 1.0);
+                a + h * (-b - a) + smoothness * h * (1.0 - h)
+            }
+            SdfShape::Twist {
+                shape,
+                angle_per_unit,
+            } => {
+                let (sin, cos) = (-angle_per_unit * p.y).sin_cos();
+                let q = Vec3::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z);
+                shape.distance(&q)
+            }
+            SdfShape::Repeat {
+                shape,
+                spacing,
+                copies,
+            } => {
+                let mut q = *p;
+                for (axis, copies) in copies.iter().enumerate() {
+                    let spacing = spacing.get(axis);
+                    if spacing > 0.0 {
+                        let limit = *copies as f64;
+                        let cell = (q.get(axis) / spacing).round().clamp(-limit, limit);
+                        q.set(axis, q.get(axis) - spacing * cell);
+                    }
+                }
+                shape.distance(&q)
+            }
+            SdfShape::Noise {
+                shape,
+                amplitude,
+                frequency,
+            } => shape.distance(p) + amplitude * perlin(&(*frequency * p)),
+        }
+    }
+
+    pub fn bounding_box(&self) -> AxisAlignedBoundingBox {
+        match self {
+            SdfShape::Sphere { radius } => {
+                let r = Vec3::new(*radius, *radius, *radius);
+                AxisAlignedBoundingBox::new(-r, r)
+            }
+            SdfShape::Box { half_size } => AxisAlignedBoundingBox::new(-*half_size, *half_size),
+            SdfShape::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let extent = major_radius + minor_radius;
+                AxisAlignedBoundingBox::new(
+                    Vec3::new(-extent, -minor_radius, -extent),
+                    Vec3::new(extent, *minor_radius, extent),
+                )
+            }
+            SdfShape::Capsule { start, end, radius } => {
+                let r = Vec3::new(*radius, *radius, *radius);
+                AxisAlignedBoundingBox::new(*start - r, *start + r)
+                    .union(&AxisAlignedBoundingBox::new(*end - r, *end + r))
+            }
+            SdfShape::Translate { shape, offset } => {
+                let bounds = shape.bounding_box();
+                AxisAlignedBoundingBox::new(bounds.minimum + *offset, bounds.maximum + *offset)
+            }
+            // the blend adds at most a quarter of the smoothness to the shapes
+            SdfShape::SmoothUnion {
+                left,
+                right,
+                smoothness,
+            } => grow(
+                &left.bounding_box().union(&right.bounding_box()),
+                smoothness.max(0.0) / 4.0,
+            ),
+            SdfShape::SmoothSubtraction { left, .. } => left.bounding_box(),
+            SdfShape::Twist { shape, .. } => {
+                let bounds = shape.bounding_box();
+                let radius = radial_extent(&bounds);
+                AxisAlignedBoundingBox::new(
+                    Vec3::new(-radius, bounds.minimum.y, -radius),
+                    Vec3::new(radius, bounds.maximum.y, radius),
+                )
+            }
+            SdfShape::Repeat {
+                shape,
+                spacing,
+                copies,
+            } => {
+                let bounds = shape.bounding_box();
+                let mut reach = Vec3::new(0.0, 0.0, 0.0);
+                for (axis, copies) in copies.iter().enumerate() {
+                    reach.set(axis, spacing.get(axis).max(0.0) * *copies as f64);
+                }
+                AxisAlignedBoundingBox::new(bounds.minimum - reach, bounds.maximum + reach)
+            }
+            SdfShape::Noise {
+                shape, amplitude, ..
+            } => grow(&shape.bounding_box(), amplitude.abs() * PERLIN_RANGE),
+        }
+    }
+
+    // How much faster than the true distance the function can change. Steps along the ray
+    // are divided by this so that none of them pass through the surface.
+    pub fn lipschitz(&self) -> f64 {
+        match self {
+            SdfShape::Sphere { .. }
+            | SdfShape::Box { .. }
+            | SdfShape::Torus { .. }
+            | SdfShape::Capsule { .. } => 1.0,
+            SdfShape::Translate { shape, .. } | SdfShape::Repeat { shape, .. } => shape.lipschitz(),
+            SdfShape::SmoothUnion { left, right, .. }
+            | SdfShape::SmoothSubtraction { left, right, .. } => {
+                left.lipschitz().max(right.lipschitz())
+            }
+            SdfShape::Twist {
+                shape,
+                angle_per_unit,
+            } => {
+                let stretch = angle_per_unit * radial_extent(&shape.bounding_box());
+                shape.lipschitz() * (1.0 + stretch * stretch).sqrt()
+            }
+            SdfShape::Noise {
+                shape,
+                amplitude,
+                frequency,
+            } => shape.lipschitz() + (amplitude * frequency).abs() * PERLIN_LIPSCHITZ,
+        }
+    }
+}
+
+fn grow(bounds: &AxisAlignedBoundingBox, distance: f64) -> AxisAlignedBoundingBox {
+    let d = Vec3::new(distance, distance, distance);
+    AxisAlignedBoundingBox::new(bounds.minimum - d, bounds.maximum + d)
+}
+
+// Furthest the box reaches from the y axis
+fn radial_extent(bounds: &AxisAlignedBoundingBox) -> f64 {
+    let x = bounds.minimum.x.abs().max(bounds.maximum.x.abs());
+    let z = bounds.minimum.z.abs().max(bounds.maximum.z.abs());
+    (x * x + z * z).sqrt()
+}
+
+// Ray marches a signed distance function. Each step moves as far along the ray as the
+// distance function says is empty, until the ray is close enough to call it a hit.
+#[derive(Debug)]
+pub struct Sdf {
+    shape: SdfShape,
+    material: Box<dyn Material>,
+    bounding_box: AxisAlignedBoundingBox,
+    lipschitz: f64,
+    // how close to the surface counts as on it, scaled to the size of the shape
+    epsilon: f64,
+}
+
+impl Sdf {
+    pub fn new(shape: SdfShape, material: Box<dyn Material>) -> Sdf {
+        let bounds = shape.bounding_box();
+        let epsilon = 1e-5 * (bounds.maximum - bounds.minimum).length();
+
+        Sdf {
+            bounding_box: grow(&bounds, 2.0 * epsilon),
+            lipschitz: shape.lipschitz(),
+            shape,
+            material,
+            epsilon,
+        }
+    }
+
+    fn march(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let (hit, t_enter, t_exit) = self.bounding_box.hit(ray, t_min, t_max);
+        if !hit || t_exit <= t_min || t_enter > t_max {
+            return None;
+        }
+
+        let length = ray.direction.length();
+        let t_end = t_exit.min(t_max);
+        let mut t = t_enter.max(t_min);
+
+        // a ray leaving the surface starts within epsilon of it, so it has to get clear
+        // before anything counts as a hit. Which side it comes clear on says whether it is
+        // looking for the way in or the way out.
+        let mut side = None;
+        for _ in 0..MAX_STEPS {
+            if t > t_end {
+                return None;
+            }
+
+            let distance = self.shape.distance(&ray.at(t));
+            let sign = match side {
+                Some(sign) => sign,
+                None if distance.abs() < self.epsilon => {
+                    t += self.epsilon / length;
+                    continue;
+                }
+                None => *side.insert(distance.signum()),
+            };
+
+            let distance = sign * distance;
+            if distance < self.epsilon {
+                return Some(t);
+            }
+
+            t += distance / (self.lipschitz * length);
+        }
+
+        None
+    }
+
+    // Gradient of the distance function from four samples around p
+    fn normal(&self, p: &Vec3) -> Vec3 {
+        let h = self.epsilon;
+        [
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ]
+        .iter()
+        .fold(Vec3::new(0.0, 0.0, 0.0), |normal, k| {
+            normal + self.shape.distance(&(p + h * k)) * k
+        })
+        .unit()
+    }
+}
+
+impl Hittable for Sdf {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        _pixel: Option<Pixel>,
+        _first_ray: bool,
+    ) -> Option<HitRecord<'_>> {
+        let t = self.march(ray, t_min, t_max)?;
+
+        let p = ray.at(t);
+        let geometric_normal = self.normal(&p);
+        let front_face = ray.direction.dot(&geometric_normal) < 0.0;
+        let normal = if front_face {
+            geometric_normal
+        } else {
+            -geometric_normal
+        };
+
+        // wrapped around the shape like a sphere's
+        let n = (p - self.bounding_box.centroid).unit();
+        let u = n.x.atan2(n.z) / (2.0 * PI) + 0.5;
+        let v = n.y.asin() / PI + 0.5;
+
+        Some(HitRecord {
+            p,
+            t,
+            normal,
+            tangent: None,
+            bitangent: None,
+            material: &self.material,
+            front_face,
+            u,
+            v,
+            geometric_normal,
+            p_error: Vec3::new(self.epsilon, self.epsilon, self.epsilon),
+            colour: None,
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.march(ray, 0.0, t_max).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        Some(self.bounding_box.clone())
+    }
+
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        bounding_sphere(&self.bounding_box)
+    }
+}
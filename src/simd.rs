@@ -0,0 +1,323 @@
+use std::ops;
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+pub const LANES: usize = 4;
+
+// Four f64 lanes. On x86_64 these are a pair of SSE2 registers, which every x86_64 CPU
+// has, elsewhere they are a plain array that the compiler is left to vectorise.
+#[derive(Copy, Clone)]
+pub struct F64x4 {
+    #[cfg(target_arch = "x86_64")]
+    lanes: [__m128d; 2],
+    #[cfg(not(target_arch = "x86_64"))]
+    lanes: [f64; LANES],
+}
+
+// One bit per lane, set where a comparison held
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Mask4(u8);
+
+impl Mask4 {
+    pub const ALL: Mask4 = Mask4(0b1111);
+
+    // The first `count` lanes
+    pub fn first(count: usize) -> Mask4 {
+        Mask4(((1u32 << count.min(LANES)) - 1) as u8)
+    }
+
+    // Sets the lanes for which `f` is true
+    pub fn from_fn(f: impl Fn(usize) -> bool) -> Mask4 {
+        let mut bits = 0;
+        for lane in 0..LANES {
+            if f(lane) {
+                bits |= 1 << lane;
+            }
+        }
+        Mask4(bits)
+    }
+
+    pub fn lane(&self, lane: usize) -> bool {
+        self.0 & (1 << lane) != 0
+    }
+
+    pub fn any(&self) -> bool {
+        self.0 != 0
+    }
+}
+
+impl ops::BitAnd for Mask4 {
+    type Output = Mask4;
+
+    fn bitand(self, rhs: Mask4) -> Mask4 {
+        Mask4(self.0 & rhs.0)
+    }
+}
+
+impl ops::BitOr for Mask4 {
+    type Output = Mask4;
+
+    fn bitor(self, rhs: Mask4) -> Mask4 {
+        Mask4(self.0 | rhs.0)
+    }
+}
+
+impl ops::Not for Mask4 {
+    type Output = Mask4;
+
+    fn not(self) -> Mask4 {
+        Mask4(!self.0 & Mask4::ALL.0)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl F64x4 {
+    pub fn splat(value: f64) -> F64x4 {
+        unsafe {
+            F64x4 {
+                lanes: [_mm_set1_pd(value), _mm_set1_pd(value)],
+            }
+        }
+    }
+
+    pub fn new(values: [f64; LANES]) -> F64x4 {
+        unsafe {
+            F64x4 {
+                lanes: [
+                    _mm_set_pd(values[1], values[0]),
+                    _mm_set_pd(values[3], values[2]),
+                ],
+            }
+        }
+    }
+
+    pub fn to_array(self) -> [f64; LANES] {
+        let mut values = [0.0; LANES];
+        unsafe {
+            _mm_storeu_pd(values.as_mut_ptr(), self.lanes[0]);
+            _mm_storeu_pd(values.as_mut_ptr().add(2), self.lanes[1]);
+        }
+        values
+    }
+
+    fn map(self, rhs: F64x4, f: impl Fn(__m128d, __m128d) -> __m128d) -> F64x4 {
+        F64x4 {
+            lanes: [
+                f(self.lanes[0], rhs.lanes[0]),
+                f(self.lanes[1], rhs.lanes[1]),
+            ],
+        }
+    }
+
+    fn compare(self, rhs: F64x4, f: impl Fn(__m128d, __m128d) -> __m128d) -> Mask4 {
+        unsafe {
+            let low = _mm_movemask_pd(f(self.lanes[0], rhs.lanes[0]));
+            let high = _mm_movemask_pd(f(self.lanes[1], rhs.lanes[1]));
+            Mask4((low | high << 2) as u8)
+        }
+    }
+
+    pub fn abs(self) -> F64x4 {
+        unsafe {
+            let sign = _mm_set1_pd(-0.0);
+            F64x4 {
+                lanes: [
+                    _mm_andnot_pd(sign, self.lanes[0]),
+                    _mm_andnot_pd(sign, self.lanes[1]),
+                ],
+            }
+        }
+    }
+
+    // Lane wise `if self < rhs { self } else { rhs }`, so a NaN in self gives rhs
+    pub fn min(self, rhs: F64x4) -> F64x4 {
+        self.map(rhs, |a, b| unsafe { _mm_min_pd(a, b) })
+    }
+
+    // Lane wise `if self > rhs { self } else { rhs }`, so a NaN in self gives rhs
+    pub fn max(self, rhs: F64x4) -> F64x4 {
+        self.map(rhs, |a, b| unsafe { _mm_max_pd(a, b) })
+    }
+
+    pub fn lt(self, rhs: F64x4) -> Mask4 {
+        self.compare(rhs, |a, b| unsafe { _mm_cmplt_pd(a, b) })
+    }
+
+    pub fn le(self, rhs: F64x4) -> Mask4 {
+        self.compare(rhs, |a, b| unsafe { _mm_cmple_pd(a, b) })
+    }
+
+    pub fn gt(self, rhs: F64x4) -> Mask4 {
+        self.compare(rhs, |a, b| unsafe { _mm_cmpgt_pd(a, b) })
+    }
+
+    pub fn ge(self, rhs: F64x4) -> Mask4 {
+        self.compare(rhs, |a, b| unsafe { _mm_cmpge_pd(a, b) })
+    }
+
+    pub fn eq(self, rhs: F64x4) -> Mask4 {
+        self.compare(rhs, |a, b| unsafe { _mm_cmpeq_pd(a, b) })
+    }
+
+    // Takes lanes from if_true where the mask is set and from if_false elsewhere
+    pub fn select(mask: Mask4, if_true: F64x4, if_false: F64x4) -> F64x4 {
+        let bits = |lane: usize| if mask.lane(lane) { -1 } else { 0 };
+        unsafe {
+            let low = _mm_castsi128_pd(_mm_set_epi64x(bits(1), bits(0)));
+            let high = _mm_castsi128_pd(_mm_set_epi64x(bits(3), bits(2)));
+            F64x4 {
+                lanes: [
+                    _mm_or_pd(
+                        _mm_and_pd(low, if_true.lanes[0]),
+                        _mm_andnot_pd(low, if_false.lanes[0]),
+                    ),
+                    _mm_or_pd(
+                        _mm_and_pd(high, if_true.lanes[1]),
+                        _mm_andnot_pd(high, if_false.lanes[1]),
+                    ),
+                ],
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+impl F64x4 {
+    pub fn splat(value: f64) -> F64x4 {
+        F64x4 {
+            lanes: [value; LANES],
+        }
+    }
+
+    pub fn new(values: [f64; LANES]) -> F64x4 {
+        F64x4 { lanes: values }
+    }
+
+    pub fn to_array(self) -> [f64; LANES] {
+        self.lanes
+    }
+
+    fn map(self, rhs: F64x4, f: impl Fn(f64, f64) -> f64) -> F64x4 {
+        let mut lanes = [0.0; LANES];
+        for (lane, value) in lanes.iter_mut().enumerate() {
+            *value = f(self.lanes[lane], rhs.lanes[lane]);
+        }
+        F64x4 { lanes }
+    }
+
+    fn compare(self, rhs: F64x4, f: impl Fn(f64, f64) -> bool) -> Mask4 {
+        Mask4::from_fn(|lane| f(self.lanes[lane], rhs.lanes[lane]))
+    }
+
+    pub fn abs(self) -> F64x4 {
+        F64x4 {
+            lanes: self.lanes.map(f64::abs),
+        }
+    }
+
+    // Lane wise `if self < rhs { self } else { rhs }`, so a NaN in self gives rhs
+    pub fn min(self, rhs: F64x4) -> F64x4 {
+        self.map(rhs, |a, b| if a < b { a } else { b })
+    }
+
+    // Lane wise `if self > rhs { self } else { rhs }`, so a NaN in self gives rhs
+    pub fn max(self, rhs: F64x4) -> F64x4 {
+        self.map(rhs, |a, b| if a > b { a } else { b })
+    }
+
+    pub fn lt(self, rhs: F64x4) -> Mask4 {
+        self.compare(rhs, |a, b| a < b)
+    }
+
+    pub fn le(self, rhs: F64x4) -> Mask4 {
+        self.compare(rhs, |a, b| a <= b)
+    }
+
+    pub fn gt(self, rhs: F64x4) -> Mask4 {
+        self.compare(rhs, |a, b| a > b)
+    }
+
+    pub fn ge(self, rhs: F64x4) -> Mask4 {
+        self.compare(rhs, |a, b| a >= b)
+    }
+
+    pub fn eq(self, rhs: F64x4) -> Mask4 {
+        self.compare(rhs, |a, b| a == b)
+    }
+
+    // Takes lanes from if_true where the mask is set and from if_false elsewhere
+    pub fn select(mask: Mask4, if_true: F64x4, if_false: F64x4) -> F64x4 {
+        let mut lanes = if_false.lanes;
+        for (lane, value) in lanes.iter_mut().enumerate() {
+            if mask.lane(lane) {
+                *value = if_true.lanes[lane];
+            }
+        }
+        F64x4 { lanes }
+    }
+}
+
+impl ops::Add for F64x4 {
+    type Output = F64x4;
+
+    #[cfg(target_arch = "x86_64")]
+    fn add(self, rhs: F64x4) -> F64x4 {
+        self.map(rhs, |a, b| unsafe { _mm_add_pd(a, b) })
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn add(self, rhs: F64x4) -> F64x4 {
+        self.map(rhs, |a, b| a + b)
+    }
+}
+
+impl ops::Sub for F64x4 {
+    type Output = F64x4;
+
+    #[cfg(target_arch = "x86_64")]
+    fn sub(self, rhs: F64x4) -> F64x4 {
+        self.map(rhs, |a, b| unsafe { _mm_sub_pd(a, b) })
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn sub(self, rhs: F64x4) -> F64x4 {
+        self.map(rhs, |a, b| a - b)
+    }
+}
+
+impl ops::Mul for F64x4 {
+    type Output = F64x4;
+
+    #[cfg(target_arch = "x86_64")]
+    fn mul(self, rhs: F64x4) -> F64x4 {
+        self.map(rhs, |a, b| unsafe { _mm_mul_pd(a, b) })
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn mul(self, rhs: F64x4) -> F64x4 {
+        self.map(rhs, |a, b| a * b)
+    }
+}
+
+impl ops::Div for F64x4 {
+    type Output = F64x4;
+
+    #[cfg(target_arch = "x86_64")]
+    fn div(self, rhs: F64x4) -> F64x4 {
+        self.map(rhs, |a, b| unsafe { _mm_div_pd(a, b) })
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn div(self, rhs: F64x4) -> F64x4 {
+        self.map(rhs, |a, b| a / b)
+    }
+}
+
+impl ops::Mul<F64x4> for f64 {
+    type Output = F64x4;
+
+    fn mul(self, rhs: F64x4) -> F64x4 {
+        F64x4::splat(self) * rhs
+    }
+}
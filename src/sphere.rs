@@ -1,14 +1,12 @@
 use crate::aabb::AxisAlignedBoundingBox;
-use crate::camera::Camera;
-use crate::hittable::{HitRecord, Hittable};
-use crate::material::Material;
+use crate::hittable::{HitRecord, Hittable, Pixel};
+use crate::material::{Material, UnitMaterial};
 use crate::onb::OrthonormalBasis;
 use crate::ray::Ray;
-use crate::utils::{distance, random_to_sphere};
+use crate::utils::{distance, gamma, random_to_sphere};
 use crate::vector::Vec3;
 
 use std::f64::consts::PI;
-use std::sync::{Arc, Mutex};
 
 #[derive(Debug)]
 pub struct Sphere {
@@ -25,19 +23,9 @@ impl Sphere {
             material,
         }
     }
-}
 
-impl Hittable for Sphere {
-    fn hit(
-        &self,
-        ray: &Ray,
-        _camera: &Camera,
-        t_min: f64,
-        t_max: f64,
-        _pixel: Option<(usize, usize)>,
-        _zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
-        _first_ray: bool,
-    ) -> Option<HitRecord> {
+    // Nearest root of the ray sphere equation inside the interval
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
         let oc = ray.origin - self.center;
         let a = ray.direction.length_squared();
         let half_b = oc.dot(&ray.direction);
@@ -49,21 +37,42 @@ impl Hittable for Sphere {
         }
 
         let mut root = (-half_b - discriminant.sqrt()) / a;
-        if root < t_min || t_max < root {
+        if root <= t_min || t_max < root {
             root = (-half_b + discriminant.sqrt()) / a;
-            if root < t_min || t_max < root {
+            if root <= t_min || t_max < root {
                 return None;
             }
         }
 
-        let mut outward_normal = (ray.at(root) - self.center) / self.radius;
+        Some(root)
+    }
+}
+
+impl Hittable for Sphere {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        _pixel: Option<Pixel>,
+        _first_ray: bool,
+    ) -> Option<HitRecord<'_>> {
+        let root = self.intersect(ray, t_min, t_max)?;
+
+        // project the hit back onto the sphere, which leaves a much smaller error than
+        // ray.at(t) does
+        let offset = ray.at(root) - self.center;
+        let offset = (self.radius / offset.length()) * &offset;
+        let p = self.center + offset;
+        let p_error = gamma(5) * &(offset.abs() + self.center.abs());
+
+        let geometric_normal = offset / self.radius;
+        let mut outward_normal = geometric_normal;
         let front_face = ray.direction.dot(&outward_normal) < 0.0;
         if !front_face {
             outward_normal = -outward_normal;
         }
 
-        let p = ray.at(root);
-
         let (u, v) = get_sphere_uv(&p, &self.center);
 
         Some(HitRecord {
@@ -76,9 +85,16 @@ impl Hittable for Sphere {
             front_face,
             u,
             v,
+            geometric_normal,
+            p_error,
+            colour: None,
         })
     }
 
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.intersect(ray, 0.0, t_max).is_some()
+    }
+
     fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
         let point_a = self.center - Vec3::new(self.radius, self.radius, self.radius);
         let point_b = self.center + Vec3::new(self.radius, self.radius, self.radius);
@@ -86,14 +102,7 @@ impl Hittable for Sphere {
         Some(AxisAlignedBoundingBox::new(point_a, point_b))
     }
 
-    fn pdf_value(
-        &self,
-        origin: &Vec3,
-        v: &Vec3,
-        camera: &Camera,
-        pixel: Option<(usize, usize)>,
-        zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
-    ) -> f64 {
+    fn pdf_value(&self, origin: &Vec3, v: &Vec3, pixel: Option<Pixel>) -> f64 {
         // if the point is inside the light sampler we can't choose a point on the sphere
         // or else we get some weird behaviour
         if distance(origin, &self.center) < self.radius {
@@ -101,12 +110,10 @@ impl Hittable for Sphere {
         }
 
         if let Some(_hit) = self.hit(
-            &Ray::new(*origin, *v),
-            camera,
+            &Ray::new(*origin, *v, 0.0),
             0.0001,
             f64::INFINITY,
             pixel,
-            zbuffer,
             false,
         ) {
             let cos_theta_max =
@@ -134,6 +141,14 @@ impl Hittable for Sphere {
         Some(uvw.local_vec(&random_to_sphere(self.radius, distance_squared)))
     }
 
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        Sphere {
+            center: self.center,
+            radius: self.radius,
+            material: Box::new(UnitMaterial {}),
+        }
+    }
+
     fn center(&self) -> Vec3 {
         self.center
     }
@@ -143,7 +158,17 @@ impl Hittable for Sphere {
     }
 }
 
-fn get_sphere_uv(p: &Vec3, center: &Vec3) -> (f64, f64) {
+// Sphere that encloses a bounding box, for sampling lights whose shape has no cheaper way
+// of being sampled
+pub fn bounding_sphere(bounding_box: &AxisAlignedBoundingBox) -> Sphere {
+    Sphere {
+        center: bounding_box.centroid,
+        radius: (bounding_box.maximum - bounding_box.minimum).length() / 2.0,
+        material: Box::new(UnitMaterial {}),
+    }
+}
+
+pub fn get_sphere_uv(p: &Vec3, center: &Vec3) -> (f64, f64) {
     // let theta = -p.y.acos();
     // let phi = -p.z.atan2(p.x) + std::f64::consts::PI;
 
@@ -0,0 +1,544 @@
+use crate::vector::Vec3;
+
+use obj::raw::object::{Polygon, RawObj};
+use obj::{Obj, TexturedVertex};
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::PI;
+
+// Subdivision surfaces for cage meshes. Meshes made only of triangles are refined with Loop
+// subdivision, anything with quads or larger faces with Catmull-Clark. Edges on the
+// boundary of the mesh, and between faces in different smoothing groups, are kept as sharp
+// creases. UVs are refined with the same rules as the positions, with seams in the UV
+// layout treated as boundaries so they stay where they are.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Scheme {
+    Loop,
+    CatmullClark,
+}
+
+// One set of values attached to the corners of the faces. The positions and the UVs each
+// get their own, with the same faces in the same order but their own indexing.
+struct Channel {
+    faces: Vec<Vec<usize>>,
+    points: Vec<Vec3>,
+    // creases other than the boundary, which is always sharp
+    sharp: HashSet<EdgeKey>,
+}
+
+struct Edge {
+    faces: Vec<usize>,
+    // the face corners either side of the edge, used by Loop's edge rule
+    opposite: Vec<usize>,
+}
+
+// Edges are keyed by their two point indices, smallest first
+type EdgeKey = (usize, usize);
+
+fn edge_key(a: usize, b: usize) -> EdgeKey {
+    (a.min(b), a.max(b))
+}
+
+impl Channel {
+    fn edges(&self) -> (Vec<EdgeKey>, HashMap<EdgeKey, Edge>) {
+        let mut order = vec![];
+        let mut edges: HashMap<EdgeKey, Edge> = HashMap::new();
+        for (face, corners) in self.faces.iter().enumerate() {
+            let n = corners.len();
+            for i in 0..n {
+                let key = edge_key(corners[i], corners[(i + 1) % n]);
+                let edge = edges.entry(key).or_insert_with(|| {
+                    order.push(key);
+                    Edge {
+                        faces: vec![],
+                        opposite: vec![],
+                    }
+                });
+                edge.faces.push(face);
+                edge.opposite.push(corners[(i + 2) % n]);
+            }
+        }
+
+        (order, edges)
+    }
+
+    fn is_sharp(&self, key: &EdgeKey, edge: &Edge) -> bool {
+        edge.faces.len() != 2 || self.sharp.contains(key)
+    }
+
+    // The other ends of each vertex's edges, and which of them are sharp
+    fn neighbours(
+        &self,
+        order: &[EdgeKey],
+        edges: &HashMap<EdgeKey, Edge>,
+    ) -> Vec<(Vec<usize>, Vec<usize>)> {
+        let mut neighbours = vec![(vec![], vec![]); self.points.len()];
+        for key in order {
+            let sharp = self.is_sharp(key, &edges[key]);
+            for (vertex, other) in [(key.0, key.1), (key.1, key.0)] {
+                let (all, creases): &mut (Vec<usize>, Vec<usize>) = &mut neighbours[vertex];
+                all.push(other);
+                if sharp {
+                    creases.push(other);
+                }
+            }
+        }
+
+        neighbours
+    }
+
+    fn subdivide(&self, scheme: Scheme) -> Channel {
+        match scheme {
+            Scheme::CatmullClark => self.catmull_clark(),
+            Scheme::Loop => self.loop_subdivide(),
+        }
+    }
+
+    fn catmull_clark(&self) -> Channel {
+        let (order, edges) = self.edges();
+        let neighbours = self.neighbours(&order, &edges);
+        let vertex_count = self.points.len();
+        let edge_base = vertex_count;
+        let face_base = vertex_count + order.len();
+
+        let face_points: Vec<Vec3> = self
+            .faces
+            .iter()
+            .map(|corners| average(corners.iter().map(|&c| self.points[c])))
+            .collect();
+
+        let edge_index: HashMap<EdgeKey, usize> = order
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (*key, edge_base + i))
+            .collect();
+        let edge_points: Vec<Vec3> = order
+            .iter()
+            .map(|key| {
+                let edge = &edges[key];
+                let (a, b) = (self.points[key.0], self.points[key.1]);
+                if self.is_sharp(key, edge) {
+                    0.5 * &(a + b)
+                } else {
+                    0.25 * &(a + b + face_points[edge.faces[0]] + face_points[edge.faces[1]])
+                }
+            })
+            .collect();
+
+        let mut vertex_faces = vec![vec![]; vertex_count];
+        for (face, corners) in self.faces.iter().enumerate() {
+            for &corner in corners {
+                vertex_faces[corner].push(face);
+            }
+        }
+        let vertex_points = (0..vertex_count).map(|v| {
+            let p = self.points[v];
+            let (all, creases) = &neighbours[v];
+            match creases.len() {
+                _ if all.is_empty() => p,
+                0 | 1 => {
+                    let n = all.len() as f64;
+                    let f = average(vertex_faces[v].iter().map(|&face| face_points[face]));
+                    let r = average(all.iter().map(|&other| 0.5 * &(p + self.points[other])));
+                    (1.0 / n) * &(f + 2.0 * &r + (n - 3.0) * &p)
+                }
+                2 => crease_rule(p, self.points[creases[0]], self.points[creases[1]]),
+                _ => p,
+            }
+        });
+
+        let mut points: Vec<Vec3> = vertex_points.collect();
+        points.extend(edge_points);
+        points.extend(face_points);
+
+        let mut faces = vec![];
+        for (face, corners) in self.faces.iter().enumerate() {
+            let n = corners.len();
+            for i in 0..n {
+                let (prev, here, next) =
+                    (corners[(i + n - 1) % n], corners[i], corners[(i + 1) % n]);
+                faces.push(vec![
+                    here,
+                    edge_index[&edge_key(here, next)],
+                    face_base + face,
+                    edge_index[&edge_key(prev, here)],
+                ]);
+            }
+        }
+
+        Channel {
+            faces,
+            points,
+            sharp: self.split_creases(&edge_index),
+        }
+    }
+
+    fn loop_subdivide(&self) -> Channel {
+        let (order, edges) = self.edges();
+        let neighbours = self.neighbours(&order, &edges);
+        let vertex_count = self.points.len();
+
+        let edge_index: HashMap<EdgeKey, usize> = order
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (*key, vertex_count + i))
+            .collect();
+        let edge_points = order.iter().map(|key| {
+            let edge = &edges[key];
+            let (a, b) = (self.points[key.0], self.points[key.1]);
+            if self.is_sharp(key, edge) {
+                0.5 * &(a + b)
+            } else {
+                let (c, d) = (self.points[edge.opposite[0]], self.points[edge.opposite[1]]);
+                0.375 * &(a + b) + 0.125 * &(c + d)
+            }
+        });
+
+        let vertex_points = (0..vertex_count).map(|v| {
+            let p = self.points[v];
+            let (all, creases) = &neighbours[v];
+            match creases.len() {
+                _ if all.is_empty() => p,
+                0 | 1 => {
+                    let beta = loop_beta(all.len());
+                    let sum = all.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, &other| {
+                        sum + self.points[other]
+                    });
+                    (1.0 - all.len() as f64 * beta) * &p + beta * &sum
+                }
+                2 => crease_rule(p, self.points[creases[0]], self.points[creases[1]]),
+                _ => p,
+            }
+        });
+
+        let mut points: Vec<Vec3> = vertex_points.collect();
+        points.extend(edge_points);
+
+        let mut faces = vec![];
+        for corners in &self.faces {
+            let [a, b, c] = [corners[0], corners[1], corners[2]];
+            let ab = edge_index[&edge_key(a, b)];
+            let bc = edge_index[&edge_key(b, c)];
+            let ca = edge_index[&edge_key(c, a)];
+            faces.push(vec![a, ab, ca]);
+            faces.push(vec![b, bc, ab]);
+            faces.push(vec![c, ca, bc]);
+            faces.push(vec![ab, bc, ca]);
+        }
+
+        Channel {
+            faces,
+            points,
+            sharp: self.split_creases(&edge_index),
+        }
+    }
+
+    // Each half of a sharp edge stays sharp
+    fn split_creases(&self, edge_index: &HashMap<EdgeKey, usize>) -> HashSet<EdgeKey> {
+        self.sharp
+            .iter()
+            .filter_map(|key| edge_index.get(key).map(|&middle| (key, middle)))
+            .flat_map(|(key, middle)| [edge_key(key.0, middle), edge_key(middle, key.1)])
+            .collect()
+    }
+
+    // Where each vertex ends up on the limit surface and the surface normal there. Smooth
+    // vertices use the exact limit masks of their scheme, vertices on creases are moved
+    // onto the limit curve of the crease and take the average normal of their faces.
+    fn limit(&self, scheme: Scheme) -> (Vec<Vec3>, Vec<Vec3>) {
+        let (order, edges) = self.edges();
+        let neighbours = self.neighbours(&order, &edges);
+
+        let mut vertex_faces = vec![vec![]; self.points.len()];
+        for (face, corners) in self.faces.iter().enumerate() {
+            for (corner, &vertex) in corners.iter().enumerate() {
+                vertex_faces[vertex].push((face, corner));
+            }
+        }
+
+        let mut positions = vec![];
+        let mut normals = vec![];
+        for (v, &p) in self.points.iter().enumerate() {
+            let face_normal = vertex_faces[v]
+                .iter()
+                .fold(Vec3::new(0.0, 0.0, 0.0), |sum, &(face, _)| {
+                    sum + self.face_normal(face)
+                });
+            let creases = &neighbours[v].1;
+            let ring = match creases.len() {
+                0 => self.ring(&vertex_faces[v]),
+                _ => None,
+            };
+
+            let (position, normal) = match (ring, creases.len()) {
+                (Some(ring), _) => {
+                    let (position, normal) = match scheme {
+                        Scheme::Loop => loop_limit(p, &ring),
+                        Scheme::CatmullClark => catmull_clark_limit(p, &ring),
+                    };
+                    // the masks don't know which way the faces wind
+                    if normal.dot(&face_normal) < 0.0 {
+                        (position, -normal)
+                    } else {
+                        (position, normal)
+                    }
+                }
+                (None, 2) => {
+                    let (a, b) = (self.points[creases[0]], self.points[creases[1]]);
+                    ((1.0 / 6.0) * &(a + 4.0 * &p + b), face_normal)
+                }
+                _ => (p, face_normal),
+            };
+
+            positions.push(position);
+            normals.push(if normal.near_zero() {
+                normal
+            } else {
+                normal.unit()
+            });
+        }
+
+        (positions, normals)
+    }
+
+    // The vertices around an interior vertex in winding order: for each face the corner
+    // after the vertex, then the one opposite it (the same as the next corner for a
+    // triangle), so each face lies between its own first vertex and the one before. None
+    // if the faces don't close up into a single fan.
+    fn ring(&self, faces: &[(usize, usize)]) -> Option<Vec<(Vec3, Vec3)>> {
+        let after = |&(face, corner): &(usize, usize)| {
+            let corners = &self.faces[face];
+            corners[(corner + 1) % corners.len()]
+        };
+        let before = |&(face, corner): &(usize, usize)| {
+            let corners = &self.faces[face];
+            corners[(corner + corners.len() - 1) % corners.len()]
+        };
+
+        let by_before: HashMap<usize, &(usize, usize)> =
+            faces.iter().map(|face| (before(face), face)).collect();
+        if by_before.len() != faces.len() || faces.is_empty() {
+            return None;
+        }
+
+        let mut ring = vec![];
+        let mut face = &faces[0];
+        for _ in 0..faces.len() {
+            let corners = &self.faces[face.0];
+            let opposite = corners[(face.1 + corners.len() / 2) % corners.len()];
+            ring.push((self.points[after(face)], self.points[opposite]));
+            face = by_before.get(&after(face))?;
+        }
+
+        if face != &faces[0] {
+            return None;
+        }
+
+        Some(ring)
+    }
+
+    fn face_normal(&self, face: usize) -> Vec3 {
+        // summed around the face so it also copes with faces that aren't flat, and weighted
+        // by the face's area
+        let corners = &self.faces[face];
+        let mut normal = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..corners.len() {
+            let a = self.points[corners[i]];
+            let b = self.points[corners[(i + 1) % corners.len()]];
+            normal += a.cross(&b);
+        }
+        normal
+    }
+}
+
+fn average(points: impl Iterator<Item = Vec3>) -> Vec3 {
+    let mut count = 0;
+    let sum = points.fold(Vec3::new(0.0, 0.0, 0.0), |sum, p| {
+        count += 1;
+        sum + p
+    });
+    (1.0 / count as f64) * &sum
+}
+
+// A vertex on a crease only feels the two vertices along the crease
+fn crease_rule(p: Vec3, a: Vec3, b: Vec3) -> Vec3 {
+    0.125 * &(a + 6.0 * &p + b)
+}
+
+fn loop_beta(valence: usize) -> f64 {
+    if valence == 3 {
+        3.0 / 16.0
+    } else {
+        3.0 / (8.0 * valence as f64)
+    }
+}
+
+fn loop_limit(p: Vec3, ring: &[(Vec3, Vec3)]) -> (Vec3, Vec3) {
+    let n = ring.len() as f64;
+    let weight = 3.0 / (8.0 * loop_beta(ring.len()));
+    let sum = ring
+        .iter()
+        .fold(Vec3::new(0.0, 0.0, 0.0), |sum, (q, _)| sum + *q);
+    let position = (1.0 / (weight + n)) * &(weight * &p + sum);
+
+    let mut t1 = Vec3::new(0.0, 0.0, 0.0);
+    let mut t2 = Vec3::new(0.0, 0.0, 0.0);
+    for (i, (q, _)) in ring.iter().enumerate() {
+        let angle = 2.0 * PI * i as f64 / n;
+        t1 += angle.cos() * q;
+        t2 += angle.sin() * q;
+    }
+
+    (position, t1.cross(&t2))
+}
+
+fn catmull_clark_limit(p: Vec3, ring: &[(Vec3, Vec3)]) -> (Vec3, Vec3) {
+    let n = ring.len() as f64;
+    let (edges, diagonals) = ring.iter().fold(
+        (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0)),
+        |(edges, diagonals), (e, f)| (edges + *e, diagonals + *f),
+    );
+    let position = (1.0 / (n * (n + 5.0))) * &(n * n * &p + 4.0 * &edges + diagonals);
+
+    let a =
+        1.0 + (2.0 * PI / n).cos() + (PI / n).cos() * (2.0 * (9.0 + (2.0 * PI / n).cos())).sqrt();
+    let mut t1 = Vec3::new(0.0, 0.0, 0.0);
+    let mut t2 = Vec3::new(0.0, 0.0, 0.0);
+    for (i, (e, f)) in ring.iter().enumerate() {
+        let angle = 2.0 * PI * i as f64 / n;
+        let previous = 2.0 * PI * (i as f64 - 1.0) / n;
+        t1 += a * angle.cos() * e + (angle.cos() + previous.cos()) * f;
+        t2 += a * angle.sin() * e + (angle.sin() + previous.sin()) * f;
+    }
+
+    (position, t1.cross(&t2))
+}
+
+// Reads the faces of an OBJ with their smoothing groups, refines them `levels` times and
+// triangulates the result with the limit positions, normals and UVs on its vertices. Every
+// triangle comes with the OBJ face it was refined from.
+pub fn subdivide(raw: RawObj, levels: u32) -> (Obj<TexturedVertex, u32>, Vec<u32>) {
+    let mut smoothing_group = vec![0; raw.polygons.len()];
+    for (&id, group) in &raw.smoothing_groups {
+        for range in &group.polygons {
+            for face in range.start..range.end.min(smoothing_group.len()) {
+                smoothing_group[face] = id;
+            }
+        }
+    }
+
+    // faces without UVs all share one at the origin
+    let missing_uv = raw.tex_coords.len();
+    let mut position_faces = vec![];
+    let mut uv_faces = vec![];
+    let mut groups = vec![];
+    let mut origins = vec![];
+    for (face, (polygon, group)) in raw.polygons.iter().zip(&smoothing_group).enumerate() {
+        let corners: Vec<(usize, usize)> = match polygon {
+            Polygon::P(corners) => corners.iter().map(|&p| (p, missing_uv)).collect(),
+            Polygon::PT(corners) => corners.clone(),
+            Polygon::PN(corners) => corners.iter().map(|&(p, _)| (p, missing_uv)).collect(),
+            Polygon::PTN(corners) => corners.iter().map(|&(p, t, _)| (p, t)).collect(),
+        };
+        if corners.len() < 3 {
+            continue;
+        }
+
+        position_faces.push(corners.iter().map(|&(p, _)| p).collect::<Vec<_>>());
+        uv_faces.push(corners.iter().map(|&(_, t)| t).collect::<Vec<_>>());
+        groups.push(*group);
+        origins.push(face as u32);
+    }
+
+    let scheme = if position_faces.iter().all(|face| face.len() == 3) {
+        Scheme::Loop
+    } else {
+        Scheme::CatmullClark
+    };
+
+    let mut positions = Channel {
+        points: raw
+            .positions
+            .iter()
+            .map(|&(x, y, z, _)| Vec3::new(x.into(), y.into(), z.into()))
+            .collect(),
+        sharp: HashSet::new(),
+        faces: position_faces,
+    };
+    let (order, edges) = positions.edges();
+    positions.sharp = order
+        .into_iter()
+        .filter(|key| {
+            let faces = &edges[key].faces;
+            faces.len() == 2 && groups[faces[0]] != groups[faces[1]]
+        })
+        .collect();
+
+    let mut uvs = Channel {
+        points: raw
+            .tex_coords
+            .iter()
+            .map(|&(u, v, _)| Vec3::new(u.into(), v.into(), 0.0))
+            .chain(std::iter::once(Vec3::new(0.0, 0.0, 0.0)))
+            .collect(),
+        sharp: HashSet::new(),
+        faces: uv_faces,
+    };
+
+    for _ in 0..levels {
+        // each face is replaced by its children in turn, a quad for every corner with
+        // Catmull-Clark and four triangles with Loop
+        origins = positions
+            .faces
+            .iter()
+            .zip(&origins)
+            .flat_map(|(face, &origin)| {
+                let children = match scheme {
+                    Scheme::CatmullClark => face.len(),
+                    Scheme::Loop => 4,
+                };
+                std::iter::repeat_n(origin, children)
+            })
+            .collect();
+        positions = positions.subdivide(scheme);
+        uvs = uvs.subdivide(scheme);
+    }
+
+    let (limit_positions, limit_normals) = positions.limit(scheme);
+
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut faces = vec![];
+    let mut vertex_index: HashMap<(usize, usize), u32> = HashMap::new();
+    for ((position_face, uv_face), &origin) in positions.faces.iter().zip(&uvs.faces).zip(&origins)
+    {
+        let mut corner = |i: usize| {
+            let key = (position_face[i], uv_face[i]);
+            *vertex_index.entry(key).or_insert_with(|| {
+                let p = limit_positions[key.0];
+                let n = limit_normals[key.0];
+                let uv = uvs.points[key.1];
+                vertices.push(TexturedVertex {
+                    position: [p.x as f32, p.y as f32, p.z as f32],
+                    normal: [n.x as f32, n.y as f32, n.z as f32],
+                    texture: [uv.x as f32, uv.y as f32, 0.0],
+                });
+                (vertices.len() - 1) as u32
+            })
+        };
+
+        // fan out from the first corner, faces are quads or triangles once subdivided
+        for i in 1..position_face.len() - 1 {
+            let triangle = [corner(0), corner(i), corner(i + 1)];
+            indices.extend(triangle);
+            faces.push(origin);
+        }
+    }
+
+    let object = Obj {
+        name: raw.name,
+        vertices,
+        indices,
+    };
+    (object, faces)
+}
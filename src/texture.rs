@@ -1,7 +1,9 @@
 use crate::colour::Colour;
+use crate::hittable::HitRecord;
 use crate::vector::Vec3;
 
 use image::{DynamicImage, GenericImageView, Pixel};
+use std::sync::Arc;
 
 pub trait Texture: std::fmt::Debug + Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Vec3) -> Colour;
@@ -11,6 +13,12 @@ pub trait Texture: std::fmt::Debug + Send + Sync {
     fn alpha_value(&self, _u: f64, _v: f64) -> f64 {
         1.0
     }
+
+    // The colour at a hit, which materials use so textures can read more of the surface
+    // than its uv and position
+    fn hit_value(&self, hit_record: &HitRecord) -> Colour {
+        self.value(hit_record.u, hit_record.v, &hit_record.p)
+    }
 }
 
 #[derive(Debug)]
@@ -44,6 +52,56 @@ impl Texture for SolidColour {
     }
 }
 
+// The colour carried by the surface that was hit, such as a point cloud's per point colours,
+// and the fallback colour anywhere the surface has none
+#[derive(Debug)]
+pub struct VertexColour {
+    colour: Colour,
+}
+
+impl VertexColour {
+    pub fn new(colour: Colour) -> VertexColour {
+        VertexColour { colour }
+    }
+}
+
+impl Texture for VertexColour {
+    fn value(&self, _u: f64, _v: f64, _p: &Vec3) -> Colour {
+        Colour::copy(&self.colour)
+    }
+
+    fn normal_value(&self, _u: f64, _v: f64, _p: &Vec3) -> Option<Vec3> {
+        None
+    }
+
+    fn hit_value(&self, hit_record: &HitRecord) -> Colour {
+        Colour::copy(hit_record.colour.as_ref().unwrap_or(&self.colour))
+    }
+}
+
+// A texture used by several materials, which share its images rather than each keeping a
+// copy
+#[derive(Debug, Clone)]
+pub struct SharedTexture(pub Arc<dyn Texture>);
+
+impl Texture for SharedTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Colour {
+        self.0.value(u, v, p)
+    }
+
+    fn normal_value(&self, u: f64, v: f64, p: &Vec3) -> Option<Vec3> {
+        self.0.normal_value(u, v, p)
+    }
+
+    fn alpha_value(&self, u: f64, v: f64) -> f64 {
+        self.0.alpha_value(u, v)
+    }
+
+    fn hit_value(&self, hit_record: &HitRecord) -> Colour {
+        self.0.hit_value(hit_record)
+    }
+}
+
 #[derive(Debug)]
 pub struct ImageTexture {
     image: DynamicImage,
@@ -142,6 +200,31 @@ impl Texture for ImageTexture {
     }
 }
 
+// Turns a bump map of heights, black low and white high, into a tangent space normal map
+// like the ones normal_sample reads. The slope at each texel is taken from its neighbours,
+// wrapping round the edges, and is steepened by `strength`.
+pub fn bump_to_normal_map(bump_map: &DynamicImage, strength: f64) -> DynamicImage {
+    let heights = bump_map.to_luma16();
+    let (width, height) = heights.dimensions();
+    let height_at = |i: i64, j: i64| {
+        let i = i.rem_euclid(width as i64) as u32;
+        let j = j.rem_euclid(height as i64) as u32;
+        heights.get_pixel(i, j)[0] as f64 / 65535.0
+    };
+
+    // rows run down the image while v runs up it
+    let normals = image::RgbImage::from_fn(width, height, |i, j| {
+        let (i, j) = (i as i64, j as i64);
+        let du = (height_at(i + 1, j) - height_at(i - 1, j)) / 2.0;
+        let dv = (height_at(i, j - 1) - height_at(i, j + 1)) / 2.0;
+        let normal = Vec3::new(-strength * du, -strength * dv, 1.0).unit();
+        let encode = |n: f64| ((n + 1.0) / 2.0 * 255.0).round() as u8;
+        image::Rgb([encode(normal.x), encode(normal.y), encode(normal.z)])
+    });
+
+    DynamicImage::ImageRgb8(normals)
+}
+
 fn normal_sample(
     u: f64,
     v: f64,
@@ -173,3 +256,21 @@ fn normal_sample(
 
     Some(Vec3::new(x, y, z))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn bumps_tilt_normals_away_from_the_slope() {
+        // heights rise to the right, so along u
+        let heights = GrayImage::from_fn(8, 8, |i, _| Luma([(i * 32) as u8]));
+        let normal_map = bump_to_normal_map(&DynamicImage::ImageLuma8(heights), 2.0);
+
+        let normal = normal_sample(0.5, 0.5, Some(&normal_map), None).unwrap();
+        assert!(normal.x < -0.1);
+        assert!(normal.y.abs() < 0.01);
+        assert!(normal.z > 0.5);
+    }
+}
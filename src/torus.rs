@@ -0,0 +1,357 @@
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::hittable::{HitRecord, Hittable, Pixel};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sphere::{bounding_sphere, Sphere};
+use crate::utils::gamma;
+use crate::vector::Vec3;
+
+use std::f64::consts::PI;
+
+// A ring around the y axis lying in the y = 0 plane. The major radius is from the origin to
+// the middle of the tube and the minor radius is the tube's own.
+#[derive(Debug)]
+pub struct Torus {
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Box<dyn Material>,
+    bounding_box: AxisAlignedBoundingBox,
+}
+
+impl Torus {
+    pub fn new(major_radius: f64, minor_radius: f64, material: Box<dyn Material>) -> Torus {
+        let extent = major_radius + minor_radius;
+
+        Torus {
+            major_radius,
+            minor_radius,
+            material,
+            bounding_box: AxisAlignedBoundingBox::new(
+                Vec3::new(-extent, -minor_radius, -extent),
+                Vec3::new(extent, minor_radius, extent),
+            ),
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let (hit, t_enter, t_exit) = self.bounding_box.hit(ray, t_min, t_max);
+        if !hit || t_exit <= t_min || t_enter > t_max {
+            return None;
+        }
+
+        // solve from where the ray enters the box along a unit direction, which keeps the
+        // quartic's coefficients close to the size of the torus however far away the ray
+        // starts or however it was scaled
+        let t_start = t_enter.max(t_min);
+        let o = ray.at(t_start);
+        let length = ray.direction.length();
+        let d = ray.direction / length;
+
+        let r2 = self.major_radius * self.major_radius;
+        let e = o.length_squared() - r2 - self.minor_radius * self.minor_radius;
+        let f = o.dot(&d);
+
+        let coefficients = [
+            e * e - 4.0 * r2 * (self.minor_radius * self.minor_radius - o.y * o.y),
+            4.0 * f * e + 8.0 * r2 * o.y * d.y,
+            2.0 * e + 4.0 * f * f + 4.0 * r2 * d.y * d.y,
+            4.0 * f,
+            1.0,
+        ];
+
+        // the roots are only good to a small fraction of the torus' size, so one this close
+        // to the start is the surface a spawned ray is leaving
+        let epsilon = 1e-9 * (self.major_radius + self.minor_radius);
+
+        solve_quartic(coefficients)
+            .into_iter()
+            .filter(|&s| s > epsilon || t_start > t_min)
+            .map(|s| t_start + s / length)
+            .filter(|&t| t > t_min && t <= t_max)
+            .reduce(f64::min)
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        _pixel: Option<Pixel>,
+        _first_ray: bool,
+    ) -> Option<HitRecord<'_>> {
+        let t = self.intersect(ray, t_min, t_max)?;
+
+        // project the hit onto the tube around the nearest point of the ring
+        let hit = ray.at(t);
+        let phi = hit.z.atan2(hit.x);
+        let ring = Vec3::new(
+            self.major_radius * phi.cos(),
+            0.0,
+            self.major_radius * phi.sin(),
+        );
+        let offset = hit - ring;
+        let offset = (self.minor_radius / offset.length()) * &offset;
+        let p = ring + offset;
+        let p_error = gamma(7) * &(ring.abs() + offset.abs());
+
+        // u goes around the ring and v around the tube, starting from its inside
+        let theta = offset
+            .y
+            .atan2((p.x * p.x + p.z * p.z).sqrt() - self.major_radius);
+        let u = phi.rem_euclid(2.0 * PI) / (2.0 * PI);
+        let v = (theta + PI) / (2.0 * PI);
+
+        let geometric_normal = offset / self.minor_radius;
+        let front_face = ray.direction.dot(&geometric_normal) < 0.0;
+        let normal = if front_face {
+            geometric_normal
+        } else {
+            -geometric_normal
+        };
+
+        Some(HitRecord {
+            p,
+            t,
+            normal,
+            tangent: None,
+            bitangent: None,
+            material: &self.material,
+            front_face,
+            u,
+            v,
+            geometric_normal,
+            p_error,
+            colour: None,
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.intersect(ray, 0.0, t_max).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        Some(self.bounding_box.clone())
+    }
+
+    fn get_light_sampler_sphere(&self) -> Sphere {
+        bounding_sphere(&self.bounding_box)
+    }
+}
+
+// Real roots of c[0] + c[1] t + c[2] t^2 + c[3] t^3 + c[4] t^4 by Ferrari's method, each
+// polished with a few Newton steps since the closed form loses a lot of precision
+fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    let a = c[3] / c[4];
+    let b = c[2] / c[4];
+    let cc = c[1] / c[4];
+    let d = c[0] / c[4];
+
+    // substitute t = y - a/4 to get y^4 + p y^2 + q y + r
+    let a2 = a * a;
+    let p = -3.0 / 8.0 * a2 + b;
+    let q = a2 * a / 8.0 - a * b / 2.0 + cc;
+    let r = -3.0 / 256.0 * a2 * a2 + a2 * b / 16.0 - a * cc / 4.0 + d;
+
+    // the coefficients grow with the size of the roots to the power of their degree, so
+    // the tolerances do too, which keeps a tiny torus and a huge one on the same branches
+    let size = root_size(&[a, b, cc, d]);
+    let tolerance = |degree: i32| 1e-12 * size.powi(degree);
+
+    let mut roots = vec![];
+    if r.abs() <= tolerance(4) {
+        // y (y^3 + p y + q) = 0
+        roots.push(0.0);
+        roots.extend(solve_cubic([q, p, 0.0, 1.0]));
+    } else {
+        // any real root of the resolvent cubic splits the quartic into two quadratics
+        let z = solve_cubic([r * p / 2.0 - q * q / 8.0, -r, -p / 2.0, 1.0])[0];
+
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        if u < -tolerance(4) || v < -tolerance(2) {
+            return vec![];
+        }
+        let u = u.max(0.0).sqrt();
+        let v = if q < 0.0 {
+            -v.max(0.0).sqrt()
+        } else {
+            v.max(0.0).sqrt()
+        };
+
+        roots.extend(solve_monic_quadratic(v, z - u));
+        roots.extend(solve_monic_quadratic(-v, z + u));
+    }
+
+    roots
+        .into_iter()
+        .map(|y| {
+            let mut t = y - a / 4.0;
+            for _ in 0..3 {
+                let value = (((c[4] * t + c[3]) * t + c[2]) * t + c[1]) * t + c[0];
+                let slope = ((4.0 * c[4] * t + 3.0 * c[3]) * t + 2.0 * c[2]) * t + c[1];
+                if slope == 0.0 {
+                    break;
+                }
+                t -= value / slope;
+            }
+            t
+        })
+        .collect()
+}
+
+// Real roots of c[0] + c[1] t + c[2] t^2 + c[3] t^3, always at least one
+fn solve_cubic(c: [f64; 4]) -> Vec<f64> {
+    let a = c[2] / c[3];
+    let b = c[1] / c[3];
+    let cc = c[0] / c[3];
+
+    // substitute t = y - a/3 to get y^3 + 3p y + 2q
+    let a2 = a * a;
+    let p = (-a2 / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * a2 - a * b / 3.0 + cc) / 2.0;
+    let p3 = p * p * p;
+    let discriminant = q * q + p3;
+
+    let size = root_size(&[a, b, cc]);
+    let roots = if discriminant.abs() <= 1e-12 * size.powi(6) {
+        if q.abs() <= 1e-12 * size.powi(3) {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if discriminant < 0.0 {
+        let phi = (-q / (-p3).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![
+            t * phi.cos(),
+            -t * (phi + PI / 3.0).cos(),
+            -t * (phi - PI / 3.0).cos(),
+        ]
+    } else {
+        let root = discriminant.sqrt();
+        vec![(root - q).cbrt() - (root + q).cbrt()]
+    };
+
+    roots.into_iter().map(|y| y - a / 3.0).collect()
+}
+
+// The size of the roots of a monic polynomial from its other coefficients, highest degree
+// first, since the nth of them grows with the roots to the nth power
+fn root_size(coefficients: &[f64]) -> f64 {
+    coefficients
+        .iter()
+        .enumerate()
+        .map(|(i, c)| c.abs().powf(1.0 / (i + 1) as f64))
+        .fold(0.0, f64::max)
+}
+
+// Real roots of t^2 + b t + c
+fn solve_monic_quadratic(b: f64, c: f64) -> Vec<f64> {
+    let discriminant = b * b / 4.0 - c;
+    if discriminant < 0.0 {
+        return vec![];
+    }
+
+    let root = discriminant.sqrt();
+    vec![-b / 2.0 - root, -b / 2.0 + root]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::UnitMaterial;
+
+    // The coefficients of (t - roots[0]) (t - roots[1]) (t - roots[2]) (t - roots[3])
+    fn quartic_with_roots(roots: [f64; 4]) -> [f64; 5] {
+        let mut c = [1.0, 0.0, 0.0, 0.0, 0.0];
+        for (degree, root) in roots.iter().enumerate() {
+            for i in (0..=degree + 1).rev() {
+                let lower = if i > 0 { c[i - 1] } else { 0.0 };
+                c[i] = lower - root * c[i];
+            }
+        }
+        c
+    }
+
+    #[test]
+    fn quartic_roots_at_every_scale() {
+        for scale in [1e-4, 1e-2, 1.0, 1e2, 1e4] {
+            let expected = [1.0, 2.0, 3.0, 4.0].map(|root| root * scale);
+            let mut roots = solve_quartic(quartic_with_roots(expected));
+            roots.sort_by(f64::total_cmp);
+
+            assert_eq!(roots.len(), 4, "scale {}: {:?}", scale, roots);
+            for (root, expected) in roots.iter().zip(expected) {
+                assert!(
+                    (root - expected).abs() <= 1e-9 * expected,
+                    "scale {}: {:?}",
+                    scale,
+                    roots
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn quartic_without_real_roots() {
+        for scale in [1e-4, 1.0, 1e4] {
+            // (t^2 + scale^2) (t^2 + 4 scale^2)
+            let s2 = scale * scale;
+            let roots = solve_quartic([4.0 * s2 * s2, 0.0, 5.0 * s2, 0.0, 1.0]);
+            assert!(roots.is_empty(), "scale {}: {:?}", scale, roots);
+        }
+    }
+
+    #[test]
+    fn torus_hits_at_every_scale() {
+        for scale in [1e-3, 1.0, 1e3] {
+            let torus = Torus::new(2.0 * scale, 0.5 * scale, Box::new(UnitMaterial {}));
+            let ray = |origin: Vec3, direction: Vec3| Ray::new(origin, direction, 0.0);
+
+            // straight through the tube on the far side of the ring from the origin
+            let t = torus
+                .intersect(
+                    &ray(
+                        scale * &Vec3::new(-10.0, 0.0, 0.0),
+                        Vec3::new(1.0, 0.0, 0.0),
+                    ),
+                    0.0,
+                    f64::INFINITY,
+                )
+                .expect("the ray crosses the tube");
+            assert!(
+                (t - 7.5 * scale).abs() <= 1e-9 * scale,
+                "scale {}: {}",
+                scale,
+                t
+            );
+
+            // just above the tube, and down through the hole in the middle
+            assert_eq!(
+                torus.intersect(
+                    &ray(
+                        scale * &Vec3::new(-10.0, 0.6, 0.0),
+                        Vec3::new(1.0, 0.0, 0.0)
+                    ),
+                    0.0,
+                    f64::INFINITY
+                ),
+                None
+            );
+            assert_eq!(
+                torus.intersect(
+                    &ray(
+                        scale * &Vec3::new(0.0, 10.0, 0.0),
+                        Vec3::new(0.0, -1.0, 0.0)
+                    ),
+                    0.0,
+                    f64::INFINITY
+                ),
+                None
+            );
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::utils::gamma;
+use crate::vector::Vec3;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn identity() -> Matrix4 {
+        Matrix4 {
+            m: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn translation(offset: &Vec3) -> Matrix4 {
+        let mut matrix = Matrix4::identity();
+        matrix.m[0][3] = offset.x;
+        matrix.m[1][3] = offset.y;
+        matrix.m[2][3] = offset.z;
+        matrix
+    }
+
+    pub fn scale(scale: &Vec3) -> Matrix4 {
+        let mut matrix = Matrix4::identity();
+        matrix.m[0][0] = scale.x;
+        matrix.m[1][1] = scale.y;
+        matrix.m[2][2] = scale.z;
+        matrix
+    }
+
+    pub fn multiply(&self, rhs: &Matrix4) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Matrix4 { m }
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Matrix4 { m }
+    }
+
+    pub fn inverse(&self) -> Matrix4 {
+        match self.try_inverse() {
+            Some(inverse) => inverse,
+            None => panic!("Singular matrix in transform: {:?}", self.m),
+        }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, giving None for a singular matrix
+    pub fn try_inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inv = Matrix4::identity().m;
+
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&r0, &r1| a[r0][column].abs().total_cmp(&a[r1][column].abs()))
+                .unwrap();
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inv.swap(column, pivot);
+
+            let scale = 1.0 / a[column][column];
+            for k in 0..4 {
+                a[column][k] *= scale;
+                inv[column][k] *= scale;
+            }
+
+            for row in 0..4 {
+                if row == column {
+                    continue;
+                }
+                let factor = a[row][column];
+                for k in 0..4 {
+                    a[row][k] -= factor * a[column][k];
+                    inv[row][k] -= factor * inv[column][k];
+                }
+            }
+        }
+
+        Some(Matrix4 { m: inv })
+    }
+
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+
+        if w == 1.0 {
+            Vec3::new(x, y, z)
+        } else {
+            Vec3::new(x / w, y / w, z / w)
+        }
+    }
+
+    // Bounds the error of a point with error p_error after it has been transformed, which
+    // includes the rounding of the transform itself. Expects an affine matrix.
+    pub fn transform_point_error(&self, p: &Vec3, p_error: &Vec3) -> Vec3 {
+        let m = &self.m;
+        let bound = |row: usize, v: &Vec3| {
+            (m[row][0] * v.x).abs() + (m[row][1] * v.y).abs() + (m[row][2] * v.z).abs()
+        };
+
+        let error = |row: usize| {
+            (gamma(3) + 1.0) * bound(row, p_error) + gamma(3) * (bound(row, p) + m[row][3].abs())
+        };
+
+        Vec3::new(error(0), error(1), error(2))
+    }
+
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    // Normals transform with the inverse transpose, so this expects to be called on the
+    // inverse of the matrix that transforms points
+    pub fn transform_normal(&self, n: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+
+    // The largest factor the matrix stretches an axis by, used to grow radii. This is exact
+    // for rotations and scales but can underestimate a sheared matrix.
+    pub fn max_scale(&self) -> f64 {
+        (0..3)
+            .map(|column| {
+                Vec3::new(self.m[0][column], self.m[1][column], self.m[2][column]).length()
+            })
+            .fold(0.0, f64::max)
+    }
+
+    pub fn transform_bounding_box(
+        &self,
+        bounds: &AxisAlignedBoundingBox,
+    ) -> AxisAlignedBoundingBox {
+        let mut minimum = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut maximum = Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+
+        for corner in 0..8 {
+            let p = Vec3::new(
+                bounds.bounds[corner & 1].x,
+                bounds.bounds[(corner >> 1) & 1].y,
+                bounds.bounds[(corner >> 2) & 1].z,
+            );
+            let p = self.transform_point(&p);
+
+            for axis in 0..3 {
+                minimum.set(axis, minimum.get(axis).min(p.get(axis)));
+                maximum.set(axis, maximum.get(axis).max(p.get(axis)));
+            }
+        }
+
+        AxisAlignedBoundingBox::new(minimum, maximum)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    pub fn identity() -> Quaternion {
+        Quaternion {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    pub fn from_axis_angle(axis: &Vec3, degrees: f64) -> Quaternion {
+        let axis = axis.unit();
+        let half = degrees.to_radians() / 2.0;
+        Quaternion {
+            w: half.cos(),
+            x: axis.x * half.sin(),
+            y: axis.y * half.sin(),
+            z: axis.z * half.sin(),
+        }
+    }
+
+    // Euler angles in degrees, applied about X, then Y, then Z
+    pub fn from_euler(degrees: &Vec3) -> Quaternion {
+        let qx = Quaternion::from_axis_angle(&Vec3::new(1.0, 0.0, 0.0), degrees.x);
+        let qy = Quaternion::from_axis_angle(&Vec3::new(0.0, 1.0, 0.0), degrees.y);
+        let qz = Quaternion::from_axis_angle(&Vec3::new(0.0, 0.0, 1.0), degrees.z);
+
+        qz.multiply(&qy).multiply(&qx)
+    }
+
+    pub fn multiply(&self, rhs: &Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        }
+    }
+
+    pub fn dot(&self, rhs: &Quaternion) -> f64 {
+        self.w * rhs.w + self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    pub fn unit(&self) -> Quaternion {
+        let length = self.dot(self).sqrt();
+        Quaternion {
+            w: self.w / length,
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+        }
+    }
+
+    // Spherical linear interpolation along the shortest arc
+    pub fn slerp(&self, rhs: &Quaternion, t: f64) -> Quaternion {
+        let mut rhs = *rhs;
+        let mut cos_theta = self.dot(&rhs);
+        if cos_theta < 0.0 {
+            rhs = Quaternion {
+                w: -rhs.w,
+                x: -rhs.x,
+                y: -rhs.y,
+                z: -rhs.z,
+            };
+            cos_theta = -cos_theta;
+        }
+
+        let (a, b) = if cos_theta > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.clamp(-1.0, 1.0).acos();
+            let sin_theta = theta.sin();
+            (
+                ((1.0 - t) * theta).sin() / sin_theta,
+                (t * theta).sin() / sin_theta,
+            )
+        };
+
+        Quaternion {
+            w: a * self.w + b * rhs.w,
+            x: a * self.x + b * rhs.x,
+            y: a * self.y + b * rhs.y,
+            z: a * self.z + b * rhs.z,
+        }
+        .unit()
+    }
+
+    // Rotation angle in degrees
+    pub fn angle(&self) -> f64 {
+        2.0 * self.w.abs().clamp(0.0, 1.0).acos().to_degrees()
+    }
+
+    pub fn to_matrix(&self) -> Matrix4 {
+        let Quaternion { w, x, y, z } = self.unit();
+        Matrix4 {
+            m: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - z * w),
+                    2.0 * (x * z + y * w),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * y + z * w),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - x * w),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * z - y * w),
+                    2.0 * (y * z + x * w),
+                    1.0 - 2.0 * (x * x + y * y),
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+}
+
+// A transform split into the parts that can be interpolated independently. Rotation and
+// scale happen about the pivot point before the translation is applied.
+#[derive(Debug, Copy, Clone)]
+pub struct Decomposed {
+    pub translation: Vec3,
+    pub rotation: Quaternion,
+    pub scale: Vec3,
+    pub pivot: Vec3,
+}
+
+impl Decomposed {
+    pub fn identity() -> Decomposed {
+        Decomposed {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::identity(),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+            pivot: Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn to_matrix(&self) -> Matrix4 {
+        Matrix4::translation(&(self.translation + self.pivot))
+            .multiply(&self.rotation.to_matrix())
+            .multiply(&Matrix4::scale(&self.scale))
+            .multiply(&Matrix4::translation(&-self.pivot))
+    }
+
+    // The parts undone in reverse order, which is much cheaper than inverting the matrix
+    pub fn inverse_matrix(&self) -> Matrix4 {
+        let inverse_scale = Vec3::new(1.0 / self.scale.x, 1.0 / self.scale.y, 1.0 / self.scale.z);
+        Matrix4::translation(&self.pivot)
+            .multiply(&Matrix4::scale(&inverse_scale))
+            .multiply(&self.rotation.to_matrix().transpose())
+            .multiply(&Matrix4::translation(&-(self.translation + self.pivot)))
+    }
+
+    pub fn lerp(&self, rhs: &Decomposed, t: f64) -> Decomposed {
+        Decomposed {
+            translation: (1.0 - t) * &self.translation + t * &rhs.translation,
+            rotation: self.rotation.slerp(&rhs.rotation, t),
+            scale: (1.0 - t) * &self.scale + t * &rhs.scale,
+            pivot: (1.0 - t) * &self.pivot + t * &rhs.pivot,
+        }
+    }
+}
+
+// Number of steps used to bound the volume swept by a box over the shutter interval
+const MOTION_BOUND_STEPS: usize = 32;
+
+// A transform interpolated between a start transform at `time0` and an end transform
+// at `time1`
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    start: Decomposed,
+    end: Decomposed,
+    time0: f64,
+    time1: f64,
+}
+
+impl AnimatedTransform {
+    pub fn new(start: Decomposed, end: Decomposed, time0: f64, time1: f64) -> AnimatedTransform {
+        AnimatedTransform {
+            start,
+            end,
+            time0,
+            time1,
+        }
+    }
+
+    pub fn interpolate(&self, time: f64) -> Decomposed {
+        if self.time1 <= self.time0 {
+            return self.start;
+        }
+
+        let t = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.start.lerp(&self.end, t)
+    }
+
+    pub fn matrix_at(&self, time: f64) -> Matrix4 {
+        self.interpolate(time).to_matrix()
+    }
+
+    // Bounds the box as it moves between the start and end transforms by sampling the motion.
+    // Rotation can bow the path of a corner outwards between samples, so the result is padded
+    // by the largest gap between an arc and its chord.
+    pub fn motion_bounds(&self, bounds: &AxisAlignedBoundingBox) -> AxisAlignedBoundingBox {
+        let mut motion_bounds = self.start.to_matrix().transform_bounding_box(bounds);
+
+        for step in 1..=MOTION_BOUND_STEPS {
+            let t = step as f64 / MOTION_BOUND_STEPS as f64;
+            let time = self.time0 + t * (self.time1 - self.time0);
+            let step_bounds = self.matrix_at(time).transform_bounding_box(bounds);
+
+            for axis in 0..3 {
+                motion_bounds.minimum.set(
+                    axis,
+                    motion_bounds
+                        .minimum
+                        .get(axis)
+                        .min(step_bounds.minimum.get(axis)),
+                );
+                motion_bounds.maximum.set(
+                    axis,
+                    motion_bounds
+                        .maximum
+                        .get(axis)
+                        .max(step_bounds.maximum.get(axis)),
+                );
+            }
+        }
+
+        let relative = self.start.rotation.multiply(&Quaternion {
+            w: self.end.rotation.w,
+            x: -self.end.rotation.x,
+            y: -self.end.rotation.y,
+            z: -self.end.rotation.z,
+        });
+        let step_angle = (relative.angle() / MOTION_BOUND_STEPS as f64).to_radians();
+        let radius = (motion_bounds.maximum - motion_bounds.minimum).length() / 2.0;
+        let padding = radius * (1.0 - (step_angle / 2.0).cos());
+        let padding = Vec3::new(padding, padding, padding);
+
+        AxisAlignedBoundingBox::new(
+            motion_bounds.minimum - padding,
+            motion_bounds.maximum + padding,
+        )
+    }
+}
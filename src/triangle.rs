@@ -0,0 +1,491 @@
+use crate::aabb::AxisAlignedBoundingBox;
+use crate::kdtree::{KDTreeHitRecord, UVCoord};
+use crate::ray::{max_dimension, Ray, RayPacket};
+use crate::simd::{F64x4, Mask4};
+use crate::utils::gamma;
+use crate::vector::Vec3;
+
+use obj::{Obj, TexturedVertex};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum VertexPrecision {
+    Single,
+    Double,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Positions {
+    Single(Vec<[f32; 3]>),
+    Double(Vec<Vec3>),
+}
+
+// Triangles stored once in shared vertex and index buffers. Acceleration structures refer
+// to triangles by their index, so nothing is duplicated when a triangle lands in more
+// than one KD-tree leaf.
+#[derive(Serialize, Deserialize)]
+pub struct TriangleMesh {
+    positions: Positions,
+    normals: Vec<[f32; 3]>,
+    text_coords: Vec<[f32; 2]>,
+    // tangents given with the mesh, with the handedness of the bitangent in w. Without them
+    // the tangent frame comes from each triangle's UVs.
+    tangents: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+    shade_smooth: bool,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Vec3>,
+        normals: Vec<[f32; 3]>,
+        text_coords: Vec<[f32; 2]>,
+        indices: Vec<u32>,
+        shade_smooth: bool,
+        precision: VertexPrecision,
+    ) -> TriangleMesh {
+        if normals.len() != positions.len() || text_coords.len() != positions.len() {
+            panic!(
+                "Mesh has {} positions but {} normals and {} texture coordinates",
+                positions.len(),
+                normals.len(),
+                text_coords.len()
+            );
+        }
+        if !indices.len().is_multiple_of(3) {
+            panic!(
+                "Mesh has {} indices, which isn't a whole number of triangles",
+                indices.len()
+            );
+        }
+        if let Some(index) = indices
+            .iter()
+            .find(|&&index| index as usize >= positions.len())
+        {
+            panic!(
+                "Mesh index {} is out of range for {} vertices",
+                index,
+                positions.len()
+            );
+        }
+
+        let positions = match precision {
+            VertexPrecision::Single => Positions::Single(
+                positions
+                    .iter()
+                    .map(|p| [p.x as f32, p.y as f32, p.z as f32])
+                    .collect(),
+            ),
+            VertexPrecision::Double => Positions::Double(positions),
+        };
+
+        TriangleMesh {
+            positions,
+            normals,
+            text_coords,
+            tangents: vec![],
+            indices,
+            shade_smooth,
+        }
+    }
+
+    pub fn with_tangents(mut self, tangents: Vec<[f32; 4]>) -> TriangleMesh {
+        if tangents.len() != self.normals.len() {
+            panic!(
+                "Mesh has {} vertices but {} tangents",
+                self.normals.len(),
+                tangents.len()
+            );
+        }
+
+        self.tangents = tangents;
+        self
+    }
+
+    pub fn from_obj(
+        object: Obj<TexturedVertex, u32>,
+        shade_smooth: bool,
+        precision: VertexPrecision,
+    ) -> TriangleMesh {
+        let positions = object
+            .vertices
+            .iter()
+            .map(|vertex| {
+                Vec3::new(
+                    vertex.position[0].into(),
+                    vertex.position[1].into(),
+                    vertex.position[2].into(),
+                )
+            })
+            .collect();
+        let normals = object.vertices.iter().map(|vertex| vertex.normal).collect();
+        let text_coords = object
+            .vertices
+            .iter()
+            .map(|vertex| [vertex.texture[0], vertex.texture[1]])
+            .collect();
+
+        TriangleMesh::new(
+            positions,
+            normals,
+            text_coords,
+            object.indices,
+            shade_smooth,
+            precision,
+        )
+    }
+
+    // Number of triangles
+    pub fn len(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    fn vertices(&self, triangle: usize) -> [usize; 3] {
+        let indices = &self.indices[3 * triangle..3 * triangle + 3];
+        [
+            indices[0] as usize,
+            indices[1] as usize,
+            indices[2] as usize,
+        ]
+    }
+
+    pub fn points(&self, triangle: usize) -> [Vec3; 3] {
+        let vertices = self.vertices(triangle);
+        match &self.positions {
+            Positions::Single(positions) => vertices.map(|vertex| {
+                let [x, y, z] = positions[vertex];
+                Vec3::new(x.into(), y.into(), z.into())
+            }),
+            Positions::Double(positions) => vertices.map(|vertex| positions[vertex]),
+        }
+    }
+
+    fn normals(&self, triangle: usize) -> [Vec3; 3] {
+        self.vertices(triangle).map(|vertex| {
+            let [x, y, z] = self.normals[vertex];
+            Vec3::new(x.into(), y.into(), z.into())
+        })
+    }
+
+    fn text_coords(&self, triangle: usize) -> [UVCoord; 3] {
+        self.vertices(triangle).map(|vertex| {
+            let [u, v] = self.text_coords[vertex];
+            UVCoord {
+                u: u.into(),
+                v: v.into(),
+            }
+        })
+    }
+
+    fn tangents(&self, triangle: usize) -> Option<[[f32; 4]; 3]> {
+        if self.tangents.is_empty() {
+            return None;
+        }
+
+        Some(self.vertices(triangle).map(|vertex| self.tangents[vertex]))
+    }
+
+    // Smallest and largest corner of the triangle
+    pub fn triangle_bounds(&self, triangle: usize) -> [Vec3; 2] {
+        let [p1, p2, p3] = self.points(triangle);
+        [
+            Vec3::new(
+                p1.x.min(p2.x).min(p3.x),
+                p1.y.min(p2.y).min(p3.y),
+                p1.z.min(p2.z).min(p3.z),
+            ),
+            Vec3::new(
+                p1.x.max(p2.x).max(p3.x),
+                p1.y.max(p2.y).max(p3.y),
+                p1.z.max(p2.z).max(p3.z),
+            ),
+        ]
+    }
+
+    pub fn bounding_box(&self) -> AxisAlignedBoundingBox {
+        let mut minimum = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut maximum = Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+
+        for triangle in 0..self.len() {
+            let [triangle_min, triangle_max] = self.triangle_bounds(triangle);
+            for axis in 0..3 {
+                minimum.set(axis, minimum.get(axis).min(triangle_min.get(axis)));
+                maximum.set(axis, maximum.get(axis).max(triangle_max.get(axis)));
+            }
+        }
+
+        AxisAlignedBoundingBox::new(minimum, maximum)
+    }
+}
+
+// Watertight ray triangle intersection from Woop et al., as described in pbrt. The triangle
+// is moved into a space where the ray starts at the origin and points down +z, so the hit
+// test becomes three 2D edge functions. Neighbouring triangles evaluate the edge they share
+// in exactly the same way, so a ray can't slip through the crack between them.
+pub fn triangle_intersection(
+    t_start: f64,
+    t_end: f64,
+    ray: &Ray,
+    mesh: &TriangleMesh,
+    triangle: usize,
+) -> Option<KDTreeHitRecord> {
+    let (t, barycentrics) = intersect_triangle(t_start, t_end, ray, mesh.points(triangle))?;
+
+    Some(triangle_hit(t, barycentrics, ray, mesh, triangle))
+}
+
+// The full hit on a triangle, given where the ray meets it
+pub fn triangle_hit(
+    t: f64,
+    [b0, b1, b2]: [f64; 3],
+    ray: &Ray,
+    mesh: &TriangleMesh,
+    triangle: usize,
+) -> KDTreeHitRecord {
+    let [p0, p1, p2] = mesh.points(triangle);
+
+    // the point from the barycentrics is much closer to the surface than ray.at(t)
+    let p = b0 * &p0 + b1 * &p1 + b2 * &p2;
+    let p_error = gamma(7) * &((b0 * &p0).abs() + (b1 * &p1).abs() + (b2 * &p2).abs());
+
+    let geometric_normal = (p1 - p0).cross(&(p2 - p0)).unit();
+    let front_face = ray.direction.dot(&geometric_normal) <= 0.0;
+
+    // the rest of the triangle is only fetched once it has been hit
+    let text_coords = mesh.text_coords(triangle);
+    let text_coord = UVCoord {
+        u: b0 * text_coords[0].u + b1 * text_coords[1].u + b2 * text_coords[2].u,
+        v: b0 * text_coords[0].v + b1 * text_coords[1].v + b2 * text_coords[2].v,
+    };
+
+    let mut normal = geometric_normal;
+    if mesh.shade_smooth {
+        let normals = mesh.normals(triangle);
+        normal = b0 * &normals[0].unit() + b1 * &normals[1].unit() + b2 * &normals[2].unit();
+    }
+
+    // remap coordinate basis used for normal mapping
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+
+    let uv1 = Vec3::new(
+        text_coords[1].u - text_coords[0].u,
+        text_coords[1].v - text_coords[0].v,
+        0.0,
+    );
+    let uv2 = Vec3::new(
+        text_coords[2].u - text_coords[0].u,
+        text_coords[2].v - text_coords[0].v,
+        0.0,
+    );
+
+    let f = 1.0 / (uv1.x * uv2.y - uv2.x * uv1.y);
+
+    let tangent = Vec3::new(
+        f * (uv2.y * edge1.x - uv1.y * edge2.x),
+        f * (uv2.y * edge1.y - uv1.y * edge2.y),
+        f * (uv2.y * edge1.z - uv1.y * edge2.z),
+    );
+
+    let bitangent = Vec3::new(
+        f * (-uv2.x * edge1.x + uv1.x * edge2.x),
+        f * (-uv2.x * edge1.y + uv1.x * edge2.y),
+        f * (-uv2.x * edge1.z + uv1.x * edge2.z),
+    );
+
+    normal = normal.unit();
+
+    // a mesh's own tangents are kept at right angles to the shading normal, and give the
+    // bitangent's handedness
+    let (tangent, bitangent) = match mesh.tangents(triangle) {
+        Some(tangents) => {
+            let [t0, t1, t2] = tangents.map(|[x, y, z, _]| Vec3::new(x.into(), y.into(), z.into()));
+            let tangent = b0 * &t0 + b1 * &t1 + b2 * &t2;
+            let tangent = tangent - tangent.dot(&normal) * &normal;
+            // the corners can disagree where a mirrored UV seam meets them
+            let [w0, w1, w2] = tangents.map(|[_, _, _, w]| w as f64);
+            let handedness = if b0 * w0 + b1 * w1 + b2 * w2 < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            (tangent, handedness * &normal.cross(&tangent))
+        }
+        None => (tangent, bitangent),
+    };
+
+    if !front_face {
+        normal = -normal;
+    }
+
+    KDTreeHitRecord {
+        p,
+        t,
+        normal,
+        tangent: tangent.unit(),
+        bitangent: bitangent.unit(),
+        front_face,
+        text_coord,
+        geometric_normal,
+        p_error,
+        triangle,
+    }
+}
+
+// Whether the ray hits the triangle anywhere in its interval, without working out the
+// rest of the hit
+pub fn triangle_occludes(
+    t_start: f64,
+    t_end: f64,
+    ray: &Ray,
+    mesh: &TriangleMesh,
+    triangle: usize,
+) -> bool {
+    intersect_triangle(t_start, t_end, ray, mesh.points(triangle)).is_some()
+}
+
+// Distance along the ray to the hit and the hit's barycentric coordinates
+pub fn intersect_triangle(
+    t_start: f64,
+    t_end: f64,
+    ray: &Ray,
+    [p0, p1, p2]: [Vec3; 3],
+) -> Option<(f64, [f64; 3])> {
+    // translate to the ray origin, then permute so the largest direction component is z
+    let kz = max_dimension(&ray.direction.abs());
+    let kx = (kz + 1) % 3;
+    let ky = (kx + 1) % 3;
+    let permute = |v: Vec3| Vec3::new(v.get(kx), v.get(ky), v.get(kz));
+    let d = permute(ray.direction);
+    let mut p0t = permute(p0 - ray.origin);
+    let mut p1t = permute(p1 - ray.origin);
+    let mut p2t = permute(p2 - ray.origin);
+
+    // shear so the ray direction is +z, z is only sheared once the hit is certain
+    let sx = -d.x / d.z;
+    let sy = -d.y / d.z;
+    let sz = 1.0 / d.z;
+    for p in [&mut p0t, &mut p1t, &mut p2t] {
+        p.x += sx * p.z;
+        p.y += sy * p.z;
+    }
+
+    let e0 = p1t.x * p2t.y - p1t.y * p2t.x;
+    let e1 = p2t.x * p0t.y - p2t.y * p0t.x;
+    let e2 = p0t.x * p1t.y - p0t.y * p1t.x;
+
+    if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+        return None;
+    }
+    let det = e0 + e1 + e2;
+    if det == 0.0 {
+        return None;
+    }
+
+    // check the hit is inside the ray's interval before dividing by the determinant
+    p0t.z *= sz;
+    p1t.z *= sz;
+    p2t.z *= sz;
+    let t_scaled = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
+    if det < 0.0 && (t_scaled >= 0.0 || t_scaled < t_end * det) {
+        return None;
+    }
+    if det > 0.0 && (t_scaled <= 0.0 || t_scaled > t_end * det) {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let b0 = e0 * inv_det;
+    let b1 = e1 * inv_det;
+    let b2 = e2 * inv_det;
+    let t = t_scaled * inv_det;
+
+    // t has to be further from zero than its rounding error for the hit to be certain
+    let max_zt = p0t.z.abs().max(p1t.z.abs()).max(p2t.z.abs());
+    let max_xt = p0t.x.abs().max(p1t.x.abs()).max(p2t.x.abs());
+    let max_yt = p0t.y.abs().max(p1t.y.abs()).max(p2t.y.abs());
+    let delta_z = gamma(3) * max_zt;
+    let delta_x = gamma(5) * (max_xt + max_zt);
+    let delta_y = gamma(5) * (max_yt + max_zt);
+    let delta_e = 2.0 * (gamma(2) * max_xt * max_yt + delta_y * max_xt + delta_x * max_yt);
+    let max_e = e0.abs().max(e1.abs()).max(e2.abs());
+    let delta_t =
+        3.0 * (gamma(3) * max_e * max_zt + delta_e * max_zt + delta_z * max_e) * inv_det.abs();
+    if t <= delta_t || t <= t_start {
+        return None;
+    }
+
+    Some((t, [b0, b1, b2]))
+}
+
+// The watertight test above run on every ray of a coherent packet at once. The rays
+// share their largest direction component, so they all use the same permutation and
+// each lane does exactly the arithmetic the single ray test would. Returns the lanes that
+// hit inside their interval, the distance along each ray and the hits' barycentrics.
+pub fn triangle_intersection_packet(
+    t_start: f64,
+    t_end: F64x4,
+    packet: &RayPacket,
+    mesh: &TriangleMesh,
+    triangle: usize,
+) -> (Mask4, F64x4, [F64x4; 3]) {
+    let points = mesh.points(triangle);
+
+    let kz = packet.dominant_axis;
+    let kx = (kz + 1) % 3;
+    let ky = (kx + 1) % 3;
+    let zero = F64x4::splat(0.0);
+    let [dx, dy, dz] = [kx, ky, kz].map(|axis| packet.direction[axis]);
+    let [mut p0t, mut p1t, mut p2t] =
+        points.map(|p| [kx, ky, kz].map(|axis| F64x4::splat(p.get(axis)) - packet.origin[axis]));
+
+    let sx = (zero - dx) / dz;
+    let sy = (zero - dy) / dz;
+    let sz = F64x4::splat(1.0) / dz;
+    for p in [&mut p0t, &mut p1t, &mut p2t] {
+        p[0] = p[0] + sx * p[2];
+        p[1] = p[1] + sy * p[2];
+    }
+
+    let e0 = p1t[0] * p2t[1] - p1t[1] * p2t[0];
+    let e1 = p2t[0] * p0t[1] - p2t[1] * p0t[0];
+    let e2 = p0t[0] * p1t[1] - p0t[1] * p1t[0];
+
+    let any_negative = e0.lt(zero) | e1.lt(zero) | e2.lt(zero);
+    let any_positive = e0.gt(zero) | e1.gt(zero) | e2.gt(zero);
+    let det = e0 + e1 + e2;
+    let mut hit = !(any_negative & any_positive) & !det.eq(zero);
+
+    for p in [&mut p0t, &mut p1t, &mut p2t] {
+        p[2] = p[2] * sz;
+    }
+    let t_scaled = e0 * p0t[2] + e1 * p1t[2] + e2 * p2t[2];
+    let t_end_scaled = t_end * det;
+    hit = hit & !(det.lt(zero) & (t_scaled.ge(zero) | t_scaled.lt(t_end_scaled)));
+    hit = hit & !(det.gt(zero) & (t_scaled.le(zero) | t_scaled.gt(t_end_scaled)));
+    if !hit.any() {
+        return (hit, zero, [zero; 3]);
+    }
+
+    let inv_det = F64x4::splat(1.0) / det;
+    let t = t_scaled * inv_det;
+
+    let max_of = |a: F64x4, b: F64x4, c: F64x4| a.abs().max(b.abs()).max(c.abs());
+    let max_zt = max_of(p0t[2], p1t[2], p2t[2]);
+    let max_xt = max_of(p0t[0], p1t[0], p2t[0]);
+    let max_yt = max_of(p0t[1], p1t[1], p2t[1]);
+    let delta_z = gamma(3) * max_zt;
+    let delta_x = gamma(5) * (max_xt + max_zt);
+    let delta_y = gamma(5) * (max_yt + max_zt);
+    let delta_e = 2.0 * (gamma(2) * max_xt * max_yt + delta_y * max_xt + delta_x * max_yt);
+    let max_e = max_of(e0, e1, e2);
+    let delta_t =
+        3.0 * (gamma(3) * max_e * max_zt + delta_e * max_zt + delta_z * max_e) * inv_det.abs();
+    hit = hit & !t.le(delta_t) & !t.le(F64x4::splat(t_start));
+
+    (hit, t, [e0 * inv_det, e1 * inv_det, e2 * inv_det])
+}
@@ -0,0 +1,301 @@
+use crate::vector::Vec3;
+
+use obj::raw::object::{Polygon, RawObj};
+use obj::{Obj, TexturedVertex};
+use std::collections::HashMap;
+
+// Reads the faces of an OBJ whatever their number of corners and splits them into triangles.
+// Convex faces are fanned out from their first corner and concave ones have ears clipped off
+// them until only a triangle is left. Faces without UVs get a UV of zero, and faces without
+// normals get the normal of the face so they shade flat. Alongside the triangles comes the
+// face each one was cut from.
+pub fn triangulate(raw: RawObj) -> Result<(Obj<TexturedVertex, u32>, Vec<u32>), String> {
+    let missing_uv = raw.tex_coords.len();
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut faces = vec![];
+    let mut vertex_index: HashMap<(usize, usize, usize), u32> = HashMap::new();
+    for (face, polygon) in raw.polygons.iter().enumerate() {
+        let corners: Vec<(usize, Option<usize>, Option<usize>)> = match polygon {
+            Polygon::P(corners) => corners.iter().map(|&p| (p, None, None)).collect(),
+            Polygon::PT(corners) => corners.iter().map(|&(p, t)| (p, Some(t), None)).collect(),
+            Polygon::PN(corners) => corners.iter().map(|&(p, n)| (p, None, Some(n))).collect(),
+            Polygon::PTN(corners) => corners
+                .iter()
+                .map(|&(p, t, n)| (p, Some(t), Some(n)))
+                .collect(),
+        };
+        let describe = || {
+            let corners: Vec<String> = corners
+                .iter()
+                .map(|(p, _, _)| (p + 1).to_string())
+                .collect();
+            format!("face {} (f {})", face + 1, corners.join(" "))
+        };
+        if corners.len() < 3 {
+            return Err(format!("{} has fewer than 3 corners", describe()));
+        }
+
+        let points: Vec<Vec3> = corners
+            .iter()
+            .map(|&(p, _, _)| {
+                let (x, y, z, _) = raw.positions[p];
+                Vec3::new(x.into(), y.into(), z.into())
+            })
+            .collect();
+        let face_normal = newell_normal(&points);
+        let triangles = match polygon_triangles(&points, &face_normal) {
+            Ok(triangles) => triangles,
+            Err(why) => return Err(format!("{} {}", describe(), why)),
+        };
+
+        let face_normal = face_normal.unit();
+        let mut corner = |i: usize| {
+            let (p, t, n) = corners[i];
+            // a generated normal belongs to its face alone
+            let key = (
+                p,
+                t.unwrap_or(missing_uv),
+                n.unwrap_or(raw.normals.len() + face),
+            );
+            *vertex_index.entry(key).or_insert_with(|| {
+                let (x, y, z, _) = raw.positions[p];
+                let (u, v, w) = t.map_or((0.0, 0.0, 0.0), |t| raw.tex_coords[t]);
+                let normal = match n {
+                    Some(n) => [raw.normals[n].0, raw.normals[n].1, raw.normals[n].2],
+                    None if face_normal.x.is_finite() => [
+                        face_normal.x as f32,
+                        face_normal.y as f32,
+                        face_normal.z as f32,
+                    ],
+                    None => [0.0, 1.0, 0.0],
+                };
+                vertices.push(TexturedVertex {
+                    position: [x, y, z],
+                    normal,
+                    texture: [u, v, w],
+                });
+                (vertices.len() - 1) as u32
+            })
+        };
+
+        for [a, b, c] in triangles {
+            let triangle = [corner(a), corner(b), corner(c)];
+            indices.extend(triangle);
+            faces.push(face as u32);
+        }
+    }
+
+    let object = Obj {
+        name: raw.name,
+        vertices,
+        indices,
+    };
+    Ok((object, faces))
+}
+
+// The slot of every face of an OBJ from the usemtl before it, along with the material names
+// in slot order. Faces before any usemtl share a slot with an empty name.
+pub fn face_materials(raw: &RawObj) -> (Vec<String>, Vec<u32>) {
+    let mut groups: Vec<(&String, usize)> = raw
+        .meshes
+        .iter()
+        .filter_map(|(name, group)| {
+            let first = group
+                .polygons
+                .iter()
+                .filter(|range| range.start < range.end)
+                .map(|range| range.start)
+                .min()?;
+            Some((name, first))
+        })
+        .collect();
+    groups.sort_by_key(|&(_, first)| first);
+
+    let mut names: Vec<String> = groups.iter().map(|(name, _)| name.to_string()).collect();
+    let mut slots: Vec<Option<u32>> = vec![None; raw.polygons.len()];
+    for (slot, (name, _)) in groups.iter().enumerate() {
+        for range in &raw.meshes[*name].polygons {
+            for face in range.start..range.end.min(slots.len()) {
+                slots[face] = Some(slot as u32);
+            }
+        }
+    }
+
+    let unnamed = match names.iter().position(|name| name.is_empty()) {
+        Some(slot) => slot,
+        None => names.len(),
+    };
+    let slots = slots
+        .into_iter()
+        .map(|slot| slot.unwrap_or(unnamed as u32))
+        .collect::<Vec<u32>>();
+    if unnamed == names.len() && slots.iter().any(|&slot| slot as usize == unnamed) {
+        names.push(String::new());
+    }
+
+    (names, slots)
+}
+
+// The normal of a polygon that may not be flat, as the sum of its edges' contributions. Its
+// length is twice the polygon's area.
+fn newell_normal(points: &[Vec3]) -> Vec3 {
+    let mut normal = Vec3::new(0.0, 0.0, 0.0);
+    for (i, current) in points.iter().enumerate() {
+        let next = &points[(i + 1) % points.len()];
+        normal.x += (current.y - next.y) * (current.z + next.z);
+        normal.y += (current.z - next.z) * (current.x + next.x);
+        normal.z += (current.x - next.x) * (current.y + next.y);
+    }
+
+    normal
+}
+
+// Splits a polygon into triangles of its corner indices, keeping the order of its corners so
+// the triangles face the same way it does
+fn polygon_triangles(points: &[Vec3], normal: &Vec3) -> Result<Vec<[usize; 3]>, &'static str> {
+    let n = points.len();
+    if n == 3 {
+        return Ok(vec![[0, 1, 2]]);
+    }
+
+    // Work in the plane the polygon faces most, with its axes chosen so the polygon winds
+    // anticlockwise. A polygon without any area can't be clipped, so it is fanned.
+    let dominant = (0..3)
+        .max_by(|&a, &b| normal.get(a).abs().total_cmp(&normal.get(b).abs()))
+        .unwrap_or(2);
+    if normal.get(dominant) == 0.0 {
+        return Ok(fan(n));
+    }
+    let (mut x, mut y) = ((dominant + 1) % 3, (dominant + 2) % 3);
+    if normal.get(dominant) < 0.0 {
+        std::mem::swap(&mut x, &mut y);
+    }
+    let flat: Vec<(f64, f64)> = points.iter().map(|p| (p.get(x), p.get(y))).collect();
+    let turn = |a: usize, b: usize, c: usize| edge(flat[a], flat[b], flat[c]);
+
+    // edges that aren't next to each other must not cross
+    for i in 0..n {
+        for j in i + 2..n {
+            if i == 0 && j == n - 1 {
+                continue;
+            }
+            let (a, b, c, d) = (i, (i + 1) % n, j, (j + 1) % n);
+            if turn(a, b, c) * turn(a, b, d) < 0.0 && turn(c, d, a) * turn(c, d, b) < 0.0 {
+                return Err("crosses itself");
+            }
+        }
+    }
+
+    if (0..n).all(|i| turn(i, (i + 1) % n, (i + 2) % n) >= 0.0) {
+        return Ok(fan(n));
+    }
+
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut triangles = Vec::with_capacity(n - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let (a, b, c) = (
+                remaining[(i + m - 1) % m],
+                remaining[i],
+                remaining[(i + 1) % m],
+            );
+            if turn(a, b, c) <= 0.0 {
+                return false;
+            }
+
+            // no other corner may be inside or on the ear, apart from ones sitting exactly
+            // on its own corners
+            remaining.iter().all(|&other| {
+                let p = flat[other];
+                [a, b, c].contains(&other)
+                    || [a, b, c].iter().any(|&corner| flat[corner] == p)
+                    || !(edge(flat[a], flat[b], p) >= 0.0
+                        && edge(flat[b], flat[c], p) >= 0.0
+                        && edge(flat[c], flat[a], p) >= 0.0)
+            })
+        });
+        let ear = match ear {
+            Some(ear) => ear,
+            None => return Err("can't be split into triangles"),
+        };
+
+        triangles.push([
+            remaining[(ear + m - 1) % m],
+            remaining[ear],
+            remaining[(ear + 1) % m],
+        ]);
+        remaining.remove(ear);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+
+    Ok(triangles)
+}
+
+fn fan(n: usize) -> Vec<[usize; 3]> {
+    (1..n - 1).map(|i| [0, i, i + 1]).collect()
+}
+
+// Which side of the line from a to b the point p is on, positive to the left
+fn edge(a: (f64, f64), b: (f64, f64), p: (f64, f64)) -> f64 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use obj::raw::parse_obj;
+
+    fn triangulate_obj(obj: &str) -> Result<(Obj<TexturedVertex, u32>, Vec<u32>), String> {
+        triangulate(parse_obj(obj.as_bytes()).unwrap())
+    }
+
+    // Twice the area of each triangle, positive when it winds anticlockwise seen from +z
+    fn doubled_areas(object: &Obj<TexturedVertex, u32>) -> Vec<f64> {
+        object
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let [a, b, c] = [0, 1, 2].map(|i| {
+                    let [x, y, z] = object.vertices[triangle[i] as usize].position;
+                    Vec3::new(x as f64, y as f64, z as f64)
+                });
+                (b - a).cross(&(c - a)).z
+            })
+            .collect()
+    }
+
+    #[test]
+    fn faces_are_split_and_tagged() {
+        let (object, faces) =
+            triangulate_obj("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3\nf 1 2 3 4\n").unwrap();
+
+        assert_eq!(faces, vec![0, 1, 1]);
+        // without normals in the file the faces shade flat
+        for vertex in &object.vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn concave_faces_keep_their_area_and_winding() {
+        // an L shape of three unit squares, notched at (1, 1)
+        let (object, _) = triangulate_obj(
+            "v 0 0 0\nv 2 0 0\nv 2 1 0\nv 1 1 0\nv 1 2 0\nv 0 2 0\nf 1 2 3 4 5 6\n",
+        )
+        .unwrap();
+        let areas = doubled_areas(&object);
+
+        assert_eq!(areas.len(), 4);
+        assert!(areas.iter().all(|&area| area > 0.0));
+        assert!((areas.iter().sum::<f64>() - 6.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn faces_that_cross_themselves_are_errors() {
+        let result = triangulate_obj("v 0 0 0\nv 2 2 0\nv 2 0 0\nv 0 1 0\nf 1 2 3 4\n");
+
+        assert!(result.is_err_and(|why| why.contains("crosses itself")));
+    }
+}
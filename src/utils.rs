@@ -30,6 +30,33 @@ pub fn random_in_unit_sphere() -> Vec3 {
     }
 }
 
+// Bound on the relative rounding error of n floating point operations, from pbrt
+pub fn gamma(n: u32) -> f64 {
+    let epsilon = f64::EPSILON * 0.5;
+    (n as f64 * epsilon) / (1.0 - n as f64 * epsilon)
+}
+
+// Real roots of a*t^2 + b*t + c in increasing order, computed so that neither root loses
+// precision to cancellation
+pub fn quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 || a == 0.0 {
+        return None;
+    }
+
+    let root = discriminant.sqrt();
+    let q = if b < 0.0 {
+        -0.5 * (b - root)
+    } else {
+        -0.5 * (b + root)
+    };
+
+    let t0 = q / a;
+    let t1 = if q != 0.0 { c / q } else { t0 };
+
+    Some((t0.min(t1), t0.max(t1)))
+}
+
 pub fn distance(a: &Vec3, b: &Vec3) -> f64 {
     ((b.x - a.x).powf(2.0) + (b.y - a.y).powf(2.0) + (b.z - a.z).powf(2.0)).sqrt()
 }
@@ -1,9 +1,7 @@
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{HitRecord, Hittable, Pixel};
 use crate::material::Material;
 use crate::vector::Vec3;
 
-use std::sync::{Arc, Mutex};
-
 #[derive(Debug)]
 pub struct Volume {
     boundary: Box<dyn Hittable>,
@@ -25,31 +23,21 @@ impl Hittable for Volume {
     fn hit(
         &self,
         ray: &crate::ray::Ray,
-        camera: &crate::camera::Camera,
         t_min: f64,
         t_max: f64,
-        pixel: Option<(usize, usize)>,
-        zbuffer: Arc<Mutex<Vec<Vec<f64>>>>,
+        pixel: Option<Pixel>,
         first_ray: bool,
-    ) -> Option<crate::hittable::HitRecord> {
-        if let Some(hit1) = &mut self.boundary.hit(
-            ray,
-            camera,
-            -f64::INFINITY,
-            f64::INFINITY,
-            pixel,
-            Arc::clone(&zbuffer),
-            first_ray,
-        ) {
-            if let Some(hit2) = &mut self.boundary.hit(
-                ray,
-                camera,
-                hit1.t + 0.0001,
-                f64::INFINITY,
-                pixel,
-                Arc::clone(&zbuffer),
-                first_ray,
-            ) {
+    ) -> Option<crate::hittable::HitRecord<'_>> {
+        if let Some(hit1) =
+            &mut self
+                .boundary
+                .hit(ray, -f64::INFINITY, f64::INFINITY, pixel, first_ray)
+        {
+            if let Some(hit2) =
+                &mut self
+                    .boundary
+                    .hit(ray, hit1.t + 0.0001, f64::INFINITY, pixel, first_ray)
+            {
                 if hit1.t < t_min {
                     hit1.t = t_min;
                 }
@@ -86,6 +74,11 @@ impl Hittable for Volume {
                     material: &self.material,
                     u: 0.0,
                     v: 0.0,
+                    // scattering inside the medium isn't on a surface, so there is
+                    // nothing for a new ray to hit again
+                    geometric_normal: Vec3::new(1.0, 0.0, 0.0),
+                    p_error: Vec3::new(0.0, 0.0, 0.0),
+                    colour: None,
                 });
             } else {
                 return None;
@@ -94,6 +87,30 @@ impl Hittable for Volume {
         None
     }
 
+    // The boundary's box gives the stretch of the ray inside the medium, which is exact
+    // for the cube volumes the scene file makes. This samples transmittance on purpose:
+    // like `hit`, each call draws a fresh free-flight distance, so a shadow ray is blocked
+    // with probability one minus the medium's transmittance along it. One call is a random
+    // draw rather than a fixed answer, and the soft shadow comes out of the pixel's average.
+    fn occluded(&self, ray: &crate::ray::Ray, t_max: f64) -> bool {
+        let bounding_box = match self.boundary.bounding_box() {
+            Some(bounding_box) => bounding_box,
+            None => return false,
+        };
+
+        let (hit, t_enter, t_exit) = bounding_box.hit(ray, 0.0, t_max);
+        let t_enter = t_enter.max(0.0);
+        let t_exit = t_exit.min(t_max);
+        if !hit || t_enter >= t_exit {
+            return false;
+        }
+
+        let distance_inside_boundary = (t_exit - t_enter) * ray.direction.length();
+        let hit_distance = self.neg_inv_density * rand::random::<f64>().log10();
+
+        hit_distance <= distance_inside_boundary
+    }
+
     fn bounding_box(&self) -> Option<crate::aabb::AxisAlignedBoundingBox> {
         None
     }
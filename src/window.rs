@@ -1,6 +1,9 @@
 // Most of this code is directly out of the wgpu tutorial at https://sotrh.github.io/learn-wgpu
-use crate::scene::Scene;
+use crate::animation::Animation;
+use crate::scene::{read_scene_json, MeshCache, Scene, ALL_CAMERAS};
 use image::RgbaImage;
+use std::ops::RangeInclusive;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use wgpu::util::DeviceExt;
@@ -76,6 +79,9 @@ struct State {
     num_vertices: u32,
     diffuse_bind_group: wgpu::BindGroup,
     diffuse_texture: wgpu::Texture,
+    diffuse_dimensions: (u32, u32),
+    diffuse_sampler: wgpu::Sampler,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
     rgba_image: Arc<Mutex<RgbaImage>>,
 }
 
@@ -116,43 +122,6 @@ impl State {
         };
         surface.configure(&device, &config);
 
-        let diffuse_rgba = rgba_image.lock().unwrap();
-
-        let dimensions = diffuse_rgba.dimensions();
-
-        let texture_size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
-        };
-        let diffuse_texture = device.create_texture(&wgpu::TextureDescriptor {
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            label: Some("diffuse_texture"),
-        });
-
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &diffuse_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &diffuse_rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * dimensions.0),
-                rows_per_image: std::num::NonZeroU32::new(dimensions.1),
-            },
-            texture_size,
-        );
-
-        let diffuse_texture_view =
-            diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default());
         let diffuse_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
@@ -186,20 +155,13 @@ impl State {
                 label: Some("texture_bind_group_layout"),
             });
 
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
+        let dimensions = rgba_image.lock().unwrap().dimensions();
+        let (diffuse_texture, diffuse_bind_group) = create_diffuse_texture(
+            &device,
+            &texture_bind_group_layout,
+            &diffuse_sampler,
+            dimensions,
+        );
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("shader"),
@@ -267,6 +229,9 @@ impl State {
             num_vertices,
             diffuse_bind_group,
             diffuse_texture,
+            diffuse_dimensions: dimensions,
+            diffuse_sampler,
+            texture_bind_group_layout,
             rgba_image: Arc::clone(&rgba_image),
         }
     }
@@ -285,6 +250,19 @@ impl State {
 
         let dimensions = image.dimensions();
 
+        // cameras can render images of different sizes, ie stereo rigs
+        if dimensions != self.diffuse_dimensions {
+            let (diffuse_texture, diffuse_bind_group) = create_diffuse_texture(
+                &self.device,
+                &self.texture_bind_group_layout,
+                &self.diffuse_sampler,
+                dimensions,
+            );
+            self.diffuse_texture = diffuse_texture;
+            self.diffuse_bind_group = diffuse_bind_group;
+            self.diffuse_dimensions = dimensions;
+        }
+
         let texture_size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
@@ -352,27 +330,118 @@ impl State {
     }
 }
 
-pub async fn run(scene_path: String, out_path: String) {
+fn create_diffuse_texture(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    dimensions: (u32, u32),
+) -> (wgpu::Texture, wgpu::BindGroup) {
+    let texture_size = wgpu::Extent3d {
+        width: dimensions.0,
+        height: dimensions.1,
+        depth_or_array_layers: 1,
+    };
+    let diffuse_texture = device.create_texture(&wgpu::TextureDescriptor {
+        size: texture_size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        label: Some("diffuse_texture"),
+    });
+
+    let diffuse_texture_view = diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&diffuse_texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+        label: Some("diffuse_bind_group"),
+    });
+
+    (diffuse_texture, diffuse_bind_group)
+}
+
+pub async fn run(
+    scene_path: String,
+    out_path: String,
+    frames: Option<RangeInclusive<i64>>,
+    cameras: Vec<String>,
+    cache_dir: Option<String>,
+) {
     env_logger::init();
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
     window.set_title("Tracer Raytracer v0.1.0");
 
-    let scene = Scene::new(scene_path);
-    let rgba_image = Arc::new(Mutex::new(RgbaImage::new(
-        scene.render_settings.image_width,
-        scene.render_settings.image_height,
-    )));
+    // A single render is frame 0 of the animation, written out without a frame number
+    let frames: Vec<Option<i64>> = match frames {
+        Some(frames) => frames.map(Some).collect(),
+        None => vec![None],
+    };
+
+    let animation = Animation::new(read_scene_json(&scene_path));
+    let mut meshes = match cache_dir {
+        Some(cache_dir) => MeshCache::with_disk_cache(&cache_dir),
+        None => MeshCache::default(),
+    };
+    let first_frame = frames[0].unwrap_or(0);
+    let scene = Scene::from_json(animation.scene_at(first_frame as f64), &mut meshes);
+    let cameras = select_cameras(&scene, &cameras);
+
+    let (image_width, image_height) = scene
+        .camera(&cameras[0])
+        .unwrap()
+        .image_dimensions(&scene.render_settings);
+    let rgba_image = Arc::new(Mutex::new(RgbaImage::new(image_width, image_height)));
     let mut state = State::new(&window, Arc::clone(&rgba_image)).await;
 
     // Where the magic happens. This starts the Raytracer.
     thread::spawn(move || {
-        scene.render(Arc::clone(&rgba_image));
+        let mut scene = Some(scene);
 
-        let image = rgba_image.lock().unwrap();
-        match image.save(&out_path) {
-            Ok(_) => println!("Wrote render out to {}", out_path),
-            Err(e) => panic!("Something went wrong trying to save the file {}...", e),
+        for frame in frames {
+            // meshes and their trees are loaded once and shared by every frame and camera
+            let scene = scene.take().unwrap_or_else(|| {
+                Scene::from_json(animation.scene_at(frame.unwrap_or(0) as f64), &mut meshes)
+            });
+
+            for name in &cameras {
+                let camera = match scene.camera(name) {
+                    Some(camera) => camera,
+                    None => panic!("Camera {} is missing from frame {:?}", name, frame),
+                };
+
+                let dimensions = camera.image_dimensions(&scene.render_settings);
+                if rgba_image.lock().unwrap().dimensions() != dimensions {
+                    *rgba_image.lock().unwrap() = RgbaImage::new(dimensions.0, dimensions.1);
+                }
+
+                scene.render(camera, Arc::clone(&rgba_image));
+
+                // the camera name is only needed to tell the images apart
+                let camera_name = if cameras.len() > 1 {
+                    Some(name.as_str())
+                } else {
+                    None
+                };
+                let out_path = output_filename(&out_path, camera_name, frame);
+
+                let image = rgba_image.lock().unwrap();
+                match image.save(&out_path) {
+                    Ok(_) => println!("Wrote render out to {}", out_path),
+                    Err(e) => panic!("Something went wrong trying to save the file {}...", e),
+                }
+            }
         }
     });
 
@@ -414,3 +483,53 @@ pub async fn run(scene_path: String, out_path: String) {
         _ => {}
     });
 }
+
+// Every camera in the scene unless some are asked for by name
+fn select_cameras(scene: &Scene, requested: &[String]) -> Vec<String> {
+    let available: Vec<String> = scene
+        .cameras
+        .iter()
+        .map(|camera| camera.name.clone())
+        .collect();
+
+    if requested.is_empty() || requested.iter().any(|name| name == ALL_CAMERAS) {
+        return available;
+    }
+
+    for name in requested {
+        if !available.contains(name) {
+            panic!(
+                "No camera named {} in the scene, try one of: {}",
+                name,
+                available.join(", ")
+            );
+        }
+    }
+
+    requested.to_vec()
+}
+
+// Adds the camera name and frame number to the output file when rendering more than one
+// image, ie 'car.jpg' becomes 'car_front_0012.jpg'
+fn output_filename(out_path: &str, camera: Option<&str>, frame: Option<i64>) -> String {
+    let path = Path::new(out_path);
+    let mut stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("untitled")
+        .to_string();
+
+    if let Some(camera) = camera {
+        stem = format!("{}_{}", stem, camera);
+    }
+    if let Some(frame) = frame {
+        stem = format!("{}_{:04}", stem, frame);
+    }
+
+    let filename = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => format!("{}.{}", stem, extension),
+        None => stem,
+    };
+
+    path.with_file_name(filename).to_string_lossy().into_owned()
+}